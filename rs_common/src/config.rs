@@ -0,0 +1,13 @@
+use std::env;
+use std::path::PathBuf;
+
+// where a plugin keeps its user files - $XDG_CONFIG_HOME/<plugin>, or ~/.config/<plugin> if that isn't set
+// %APPDATA%\<plugin> on windows, None if there's nowhere sensible
+pub fn dir(plugin: &str) -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .map(|d| d.join(plugin))
+}
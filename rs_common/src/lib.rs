@@ -0,0 +1,11 @@
+/*
+*   Code shared between the plugins - not a plugin itself, each plugin pulls this in as a path dependency
+*/
+
+pub mod config;
+pub mod parameter;
+pub mod preset;
+pub mod program;
+pub mod properties;
+pub mod state;
+pub mod waveshaper;
@@ -0,0 +1,587 @@
+use vst::util::AtomicFloat;
+
+// everything the host needs to know about a parameter, in one place - each plugin lists its parameters
+// in a table with the parameters! macro, and the host facing methods are all worked out from that
+//
+// the host only ever sees 0 - 1, the value in the parameter's own units is min + (max - min) * value^skew
+pub struct Parameter<P: 'static> {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub default: f32,
+    pub min: f32,
+    pub max: f32,
+    pub skew: f32,
+    pub display: Display<P>,
+    // text typed in by the user to a 0 - 1 value, when the display can't be worked back from
+    pub parse: Option<fn(&P, &str) -> Option<f32>>,
+    pub automatable: bool
+}
+
+pub enum Display<P: 'static> {
+    // the value in the parameter's units, signed puts a + in front of anything above 0
+    Number { decimals: usize, signed: bool },
+    // one of count evenly sized steps, shown by name or as a whole number from min
+    Steps { count: usize, names: &'static [&'static str], signed: bool },
+    // worked out by the plugin, from the 0 - 1 value and anything else it needs
+    Custom(fn(&P, f32) -> String)
+}
+
+impl<P> Parameter<P> {
+    // 0 - 1 shown to 2 decimal places, until told otherwise
+    pub const fn new(name: &'static str, default: f32) -> Self {
+        Parameter {
+            name,
+            label: "",
+            default,
+            min: 0.0,
+            max: 1.0,
+            skew: 1.0,
+            display: Display::Number { decimals: 2, signed: false },
+            parse: None,
+            automatable: true
+        }
+    }
+
+    pub const fn label(mut self, label: &'static str) -> Self {
+        self.label = label;
+        self
+    }
+
+    pub const fn range(mut self, min: f32, max: f32) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub const fn skew(mut self, skew: f32) -> Self {
+        self.skew = skew;
+        self
+    }
+
+    pub const fn decimals(mut self, decimals: usize) -> Self {
+        if let Display::Number { signed, .. } = self.display {
+            self.display = Display::Number { decimals, signed };
+        }
+        self
+    }
+
+    pub const fn signed(mut self) -> Self {
+        match self.display {
+            Display::Number { decimals, .. } => self.display = Display::Number { decimals, signed: true },
+            Display::Steps { count, names, .. } => self.display = Display::Steps { count, names, signed: true },
+            Display::Custom(_) => ()
+        }
+        self
+    }
+
+    // whole numbers from min to max, set the range first
+    pub const fn integers(mut self) -> Self {
+        self.display = Display::Steps { count: (self.max - self.min) as usize + 1, names: &[], signed: false };
+        self
+    }
+
+    pub const fn choices(mut self, names: &'static [&'static str]) -> Self {
+        self.max = (names.len() - 1) as f32;
+        self.display = Display::Steps { count: names.len(), names, signed: false };
+        self
+    }
+
+    pub const fn choice<C: Choice>(self) -> Self {
+        assert!(C::ALL.len() == C::NAMES.len(), "every choice needs a name");
+        self.choices(C::NAMES)
+    }
+
+    pub const fn text(mut self, text: fn(&P, f32) -> String) -> Self {
+        self.display = Display::Custom(text);
+        self
+    }
+
+    pub const fn parse(mut self, parse: fn(&P, &str) -> Option<f32>) -> Self {
+        self.parse = Some(parse);
+        self
+    }
+
+    // the host shouldn't offer to automate it
+    pub const fn fixed(mut self) -> Self {
+        self.automatable = false;
+        self
+    }
+
+    // how many steps there are, None if it isn't stepped
+    pub fn steps(&self) -> Option<usize> {
+        match self.display {
+            Display::Steps { count, .. } => Some(count),
+            _ => None
+        }
+    }
+
+    // the 0 - 1 value in the parameter's units
+    pub fn value(&self, normalized: f32) -> f32 {
+        match self.steps() {
+            Some(count) => self.min + step(normalized, count) as f32,
+            None => self.min + ((self.max - self.min) * normalized.powf(self.skew))
+        }
+    }
+
+    // a value in the parameter's units back to 0 - 1, anything outside the range is clamped to it
+    pub fn normalize(&self, value: f32) -> f32 {
+        match self.steps() {
+            Some(count) => step_value(((value - self.min).round().max(0.0) as usize).min(count - 1), count),
+            None => ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0).powf(1.0 / self.skew)
+        }
+    }
+
+    pub fn display_text(&self, params: &P, normalized: f32) -> String {
+        match self.display {
+            Display::Number { decimals, signed } => number_text(self.value(normalized), decimals, signed),
+            Display::Steps { count, names, .. } if !names.is_empty() => names[step(normalized, count)].to_string(),
+            Display::Steps { signed, .. } => number_text(self.value(normalized), 0, signed),
+            Display::Custom(text) => text(params, normalized)
+        }
+    }
+
+    // the value as a preset file keeps it - a number in full in the parameter's units, anything else as it's
+    // shown, and the label after either, so a person can read it and parse_text reads it back
+    pub fn preset_text(&self, params: &P, normalized: f32) -> String {
+        let labelled = |text: String| if self.label.is_empty() { text } else { format!("{} {}", text, self.label) };
+        let full = labelled(self.value(normalized).to_string());
+        match self.display {
+            Display::Number { .. } => full,
+            Display::Steps { .. } => labelled(self.display_text(params, normalized)),
+            // custom text can be rounded, so the number in full is kept instead if it reads back closer
+            Display::Custom(_) => {
+                let shown = labelled(self.display_text(params, normalized));
+                let error = |text: &str| self.parse_text(params, text).map_or(f32::INFINITY, |value| (value - normalized).abs());
+                if error(&full) < error(&shown) { full } else { shown }
+            }
+        }
+    }
+
+    // what the user typed as a 0 - 1 value, None if it doesn't mean anything for this parameter
+    // custom text is matched against what the parameter shows, so a choice can be typed by name
+    pub fn parse_text(&self, params: &P, text: &str) -> Option<f32> {
+        let text = text.trim();
+        if let Some(parse) = self.parse {
+            return parse(params, text).map(|value| value.clamp(0.0, 1.0));
+        }
+        match self.display {
+            Display::Number { .. } => parse_number(text, self.label).map(|value| self.normalize(value)),
+            Display::Steps { count, names, .. } if !names.is_empty() => {
+                names.iter().position(|name| same_text(name, text)).map(|i| step_value(i, count))
+            },
+            Display::Steps { .. } => parse_number(text, self.label).map(|value| self.normalize(value)),
+            Display::Custom(display) => {
+                let text = strip_label(text, self.label);
+                let matches = |value: f32| same_text(&display(params, value), text);
+                let first = (0..=PARSE_STEPS).find(|step| matches(*step as f32 / PARSE_STEPS as f32))?;
+                let last = (first..=PARSE_STEPS).take_while(|step| matches(*step as f32 / PARSE_STEPS as f32)).last()?;
+                // the middle of the values that show the same, so it isn't sitting on the edge of the next one
+                Some((first + last) as f32 / (2 * PARSE_STEPS) as f32)
+            }
+        }
+    }
+}
+
+// which of count evenly sized steps value falls in - every step gets the same share of 0 - 1
+pub fn step(value: f32, count: usize) -> usize {
+    ((value * count as f32) as usize).min(count.saturating_sub(1))
+}
+
+// the 0 - 1 value for a step, 0 for the first and 1 for the last so a host's min and max land on them
+pub fn step_value(step: usize, count: usize) -> f32 {
+    if count < 2 { 0.0 } else { step as f32 / (count - 1) as f32 }
+}
+
+// for migrations - the value that picks the step an older mapping picked by splitting the range at thresholds,
+// value >= thresholds[i] being past step i
+pub fn step_from_thresholds(value: f32, thresholds: &[f32]) -> f32 {
+    step_value(thresholds.iter().filter(|t| value >= **t).count(), thresholds.len() + 1)
+}
+
+// an enum picked with a stepped parameter - ALL in the order the parameter steps through them, and NAMES to match
+pub trait Choice: Copy + PartialEq + 'static {
+    const ALL: &'static [Self];
+    const NAMES: &'static [&'static str];
+
+    fn from_value(value: f32) -> Self {
+        Self::ALL[step(value, Self::ALL.len())]
+    }
+
+    fn value(self) -> f32 {
+        step_value(Self::ALL.iter().position(|c| *c == self).unwrap_or(0), Self::ALL.len())
+    }
+}
+
+// how finely custom text is searched for what was typed
+const PARSE_STEPS: usize = 1000;
+
+// ignores case and a leading +, so "+2" and "2" or "saw" and "Saw" are the same
+fn same_text(shown: &str, typed: &str) -> bool {
+    shown.trim_start_matches('+').eq_ignore_ascii_case(typed.trim_start_matches('+'))
+}
+
+fn strip_label<'a>(text: &'a str, label: &str) -> &'a str {
+    let text = text.trim();
+    let start = text.len().saturating_sub(label.len());
+    if !label.is_empty() && text.is_char_boundary(start) && text[start..].eq_ignore_ascii_case(label) {
+        text[..start].trim_end()
+    }
+    else {
+        text
+    }
+}
+
+// a number with or without the parameter's label after it, and k for thousands - "1200", "1200 Hz" and "1.2k Hz" are all 1200
+pub fn parse_number(text: &str, label: &str) -> Option<f32> {
+    let text = strip_label(text, label);
+    let (text, scale) = match text.strip_suffix(|c| c == 'k' || c == 'K') {
+        Some(thousands) => (thousands.trim_end(), 1000.0),
+        None => (text, 1.0)
+    };
+    text.parse::<f32>().ok().filter(|value| value.is_finite()).map(|value| value * scale)
+}
+
+fn number_text(value: f32, decimals: usize, signed: bool) -> String {
+    let text = format!("{:.*}", decimals, value);
+    // anything that rounds to 0 is shown without a sign
+    if text.chars().all(|c| "-0.".contains(c)) {
+        text.trim_start_matches('-').to_string()
+    }
+    else if signed && value > 0.0 {
+        format!("+{}", text)
+    }
+    else {
+        text
+    }
+}
+
+// where the parameter with an ID is in the table, worked out when the plugin is built - the parameters! macro only
+// asks for IDs that are in it
+pub const fn position<P>(table: &[(i32, Parameter<P>)], id: i32) -> usize {
+    let mut i = 0;
+    while table[i].0 != id {
+        i += 1;
+    }
+    i
+}
+
+// every ID from 0 up to the number of parameters, once each - the host numbers parameters that way
+// IDs are what hosts and saved states know parameters by, so a new parameter takes the next ID wherever
+// it goes in the table and existing IDs never move - the parameters! macro won't build a table that breaks this
+pub const fn valid_ids<P>(table: &[(i32, Parameter<P>)]) -> bool {
+    let mut id = 0;
+    while id < table.len() {
+        let mut found = 0;
+        let mut i = 0;
+        while i < table.len() {
+            if table[i].0 == id as i32 {
+                found += 1;
+            }
+            i += 1;
+        }
+        if found != 1 {
+            return false;
+        }
+        id += 1;
+    }
+    true
+}
+
+// implemented by the parameters! macro - TABLE is every parameter with the ID the host knows it by,
+// and value is the field that holds it
+pub trait Parameters: Sized + 'static {
+    const TABLE: &'static [(i32, Parameter<Self>)];
+
+    fn value(&self, id: i32) -> Option<&AtomicFloat>;
+
+    fn parameter(id: i32) -> Option<&'static Parameter<Self>> {
+        Self::TABLE.iter().find(|(i, _)| *i == id).map(|(_, parameter)| parameter)
+    }
+
+    fn normalized(&self, id: i32) -> f32 {
+        self.value(id).map(|v| v.get()).unwrap_or(0.0)
+    }
+
+    fn set_normalized(&self, id: i32, value: f32) {
+        if let Some(v) = self.value(id) {
+            v.set(value);
+        }
+    }
+
+    // a value in its parameter's units - the DSP reads values through this, so it can't disagree with what the
+    // host shows. ids come from the table (the parameters! macro names them), so there's always a parameter
+    // behind one, and both lookups are a jump to it
+    fn plain(&self, id: i32) -> f32 {
+        match (Self::parameter(id), self.value(id)) {
+            (Some(parameter), Some(value)) => parameter.value(value.get()),
+            _ => 0.0
+        }
+    }
+
+    fn name(&self, id: i32) -> String {
+        Self::parameter(id).map(|p| p.name).unwrap_or_default().to_string()
+    }
+
+    fn text(&self, id: i32) -> String {
+        Self::parameter(id).map(|p| p.display_text(self, self.normalized(id))).unwrap_or_default()
+    }
+
+    fn preset_text(&self, id: i32) -> String {
+        Self::parameter(id).map(|p| p.preset_text(self, self.normalized(id))).unwrap_or_default()
+    }
+
+    fn label(&self, id: i32) -> String {
+        Self::parameter(id).map(|p| p.label).unwrap_or_default().to_string()
+    }
+
+    // sets the parameter from text typed in by the user, false if it doesn't mean anything for it
+    fn set_text(&self, id: i32, text: &str) -> bool {
+        match Self::parameter(id).and_then(|p| p.parse_text(self, text)) {
+            Some(value) => {
+                self.set_normalized(id, value);
+                true
+            },
+            None => false
+        }
+    }
+
+    fn automatable(&self, id: i32) -> bool {
+        Self::parameter(id).is_some_and(|p| p.automatable)
+    }
+
+    // every parameter back to its default
+    fn reset(&self) {
+        for (id, parameter) in Self::TABLE.iter() {
+            self.set_normalized(*id, parameter.default);
+        }
+    }
+}
+
+// the parameter table for a plugin's parameters struct - each entry is the ID the host knows the parameter by,
+// the field that holds it (an AtomicFloat, or one in an array) and its Parameter, in any order. an entry can
+// name its ID with as, for the DSP to read it by with plain:
+//
+// type Parameter = rs_common::parameter::Parameter<DistortionParameters>;
+//
+// parameters! {
+//     DistortionParameters {
+//         0 => coefficient as COEFFICIENT: Parameter::new("Coefficient", 0.5).range(1.0, 10.0),
+//         1 => levels[0]: Parameter::new("Level 1", 0.5)
+//     }
+// }
+//
+// let coefficient = params.plain(DistortionParameters::COEFFICIENT);
+#[macro_export]
+macro_rules! parameters {
+    ($params:ty { $($id:literal => $field:ident $([$index:literal])? $(as $name:ident)?: $parameter:expr),* $(,)? }) => {
+        impl $params {
+            $($(pub const $name: i32 = $id;)?)*
+        }
+
+        impl $crate::parameter::Parameters for $params {
+            const TABLE: &'static [(i32, $crate::parameter::Parameter<Self>)] = &[$(($id, $parameter)),*];
+
+            fn value(&self, id: i32) -> Option<&::vst::util::AtomicFloat> {
+                match id {
+                    $($id => Some(&self.$field $([$index])?),)*
+                    _ => None
+                }
+            }
+
+            fn parameter(id: i32) -> Option<&'static $crate::parameter::Parameter<Self>> {
+                match id {
+                    $($id => {
+                        const AT: usize = $crate::parameter::position(<$params as $crate::parameter::Parameters>::TABLE, $id);
+                        Some(&<$params as $crate::parameter::Parameters>::TABLE[AT].1)
+                    },)*
+                    _ => None
+                }
+            }
+        }
+
+        const _: () = assert!(
+            $crate::parameter::valid_ids(<$params as $crate::parameter::Parameters>::TABLE),
+            "parameter IDs must be 0 up to the number of parameters, once each"
+        );
+    };
+}
+
+// the host facing side of the table, used inside a plugin's impl PluginParameters
+#[macro_export]
+macro_rules! parameter_methods {
+    () => {
+        fn get_parameter_name(&self, index: i32) -> String {
+            $crate::parameter::Parameters::name(self, index)
+        }
+
+        fn get_parameter_text(&self, index: i32) -> String {
+            $crate::parameter::Parameters::text(self, index)
+        }
+
+        fn get_parameter_label(&self, index: i32) -> String {
+            $crate::parameter::Parameters::label(self, index)
+        }
+
+        fn get_parameter(&self, index: i32) -> f32 {
+            $crate::parameter::Parameters::normalized(self, index)
+        }
+
+        fn set_parameter(&self, index: i32, value: f32) {
+            $crate::parameter::Parameters::set_normalized(self, index, value)
+        }
+
+        fn can_be_automated(&self, index: i32) -> bool {
+            $crate::parameter::Parameters::automatable(self, index)
+        }
+
+        fn string_to_parameter(&self, index: i32, text: String) -> bool {
+            $crate::parameter::Parameters::set_text(self, index, &text)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Parameter = super::Parameter<TestParameters>;
+
+    struct TestParameters {
+        gain: AtomicFloat,
+        mode: AtomicFloat,
+        sends: [AtomicFloat; 2],
+        delay: AtomicFloat
+    }
+
+    parameters! {
+        TestParameters {
+            0 => gain as GAIN: Parameter::new("Gain", 0.5).label("dB").range(-24.0, 24.0).decimals(1).signed(),
+            1 => mode: Parameter::new("Mode", 0.0).text(|p, value| format!("{} of {:.1}", if value < 0.5 { "A" } else { "B" }, p.gain.get())).fixed(),
+            3 => sends[1] as SEND_2: Parameter::new("Send 2", 0.25).label("%").range(0.0, 100.0).decimals(0),
+            2 => sends[0] as SEND_1: Parameter::new("Send 1", 1.0).label("Hz").range(20.0, 20000.0).skew(3.0),
+            4 => delay: Parameter::new("Delay", 0.0)
+                .text(|_, value| format!("{:.0} samples", value * 64.0))
+                .parse(|_, text| parse_number(text, "samples").map(|samples| samples / 64.0))
+        }
+    }
+
+    fn params() -> TestParameters {
+        let params = TestParameters { gain: AtomicFloat::default(), mode: AtomicFloat::default(), sends: Default::default(), delay: AtomicFloat::default() };
+        params.reset();
+        params
+    }
+
+    #[test]
+    fn maps_values_into_their_units() {
+        let params = params();
+        assert_eq!(params.text(0), "0.0");
+        params.set_normalized(0, 1.0);
+        assert_eq!(params.text(0), "+24.0");
+        params.set_normalized(0, 0.0);
+        assert_eq!(params.text(0), "-24.0");
+        assert_eq!(params.text(2), "20000.00");
+        params.set_normalized(2, 0.5);
+        assert_eq!(params.text(2), "2517.50");
+        assert_eq!(params.text(3), "25");
+        assert_eq!(params.label(2), "Hz");
+    }
+
+    #[test]
+    fn values_are_read_in_their_units() {
+        let params = params();
+        params.set_normalized(2, 0.5);
+        let cases = [(TestParameters::GAIN, 0.0), (TestParameters::SEND_1, 2517.5), (TestParameters::SEND_2, 25.0), (4, 0.0)];
+        for (id, expected) in cases.iter() {
+            assert_eq!(params.plain(*id), *expected);
+        }
+        // every ID finds its own parameter wherever it is in the table
+        for id in 0..5 {
+            assert_eq!(TestParameters::parameter(id).map(|p| p.name), TestParameters::TABLE.iter().find(|(i, _)| *i == id).map(|(_, p)| p.name));
+        }
+        assert!(TestParameters::parameter(5).is_none());
+    }
+
+    #[test]
+    fn preset_text_reads_back_to_the_same_value() {
+        let params = params();
+        for (id, value) in [(0, 0.37), (1, 0.75), (2, 0.123), (3, 0.5), (4, 0.25)].iter() {
+            params.set_normalized(*id, *value);
+            let (shown, text) = (params.text(*id), params.preset_text(*id));
+            params.set_normalized(*id, 0.0);
+            assert!(params.set_text(*id, &text), "{} couldn't be read back", text);
+            assert_eq!(params.text(*id), shown);
+            assert!((params.normalized(*id) - *value).abs() < 0.01, "{} read back as {}", text, params.normalized(*id));
+        }
+        params.set_normalized(2, 0.5);
+        assert_eq!(params.preset_text(2), "2517.5 Hz");
+        assert_eq!(params.preset_text(1), "B of 0.4");
+    }
+
+    #[test]
+    fn custom_text_can_look_at_other_parameters() {
+        let params = params();
+        assert_eq!(params.text(1), "A of 0.5");
+        params.set_normalized(1, 0.75);
+        assert_eq!(params.text(1), "B of 0.5");
+        assert!(!params.automatable(1));
+        assert!(params.automatable(0));
+    }
+
+    #[test]
+    fn unknown_ids_are_left_alone() {
+        let params = params();
+        params.set_normalized(5, 1.0);
+        assert_eq!(params.normalized(5), 0.0);
+        assert_eq!(params.name(5), "");
+        assert_eq!(params.text(5), "");
+        assert!(!params.set_text(5, "1"));
+        assert_eq!(params.name(3), "Send 2");
+        assert_eq!(params.normalized(3), 0.25);
+    }
+
+    #[test]
+    fn typed_numbers_are_read_in_the_parameters_units() {
+        let params = params();
+        assert!(params.set_text(2, "1200 Hz"));
+        assert_eq!(params.text(2), "1200.00");
+        assert!(params.set_text(2, " 2.5k hz "));
+        assert_eq!(params.text(2), "2500.00");
+        assert!(params.set_text(0, "+6"));
+        assert_eq!(params.text(0), "+6.0");
+        assert!(params.set_text(3, "150%"));
+        assert_eq!(params.text(3), "100");
+        assert!(!params.set_text(3, "loud"));
+        assert!(!params.set_text(3, "NaN"));
+        assert_eq!(params.text(3), "100");
+    }
+
+    #[test]
+    fn typed_choices_are_matched_against_the_text() {
+        let params = params();
+        assert!(params.set_text(1, "b of 0.5"));
+        assert_eq!(params.normalized(1), 0.75);
+        assert!(!params.set_text(1, "C of 0.5"));
+        assert!(params.set_text(4, "32 samples"));
+        assert_eq!(params.normalized(4), 0.5);
+    }
+
+    #[test]
+    fn steps_share_the_range_evenly() {
+        let octave = super::Parameter::<()>::new("Octave", 0.5).range(-2.0, 2.0).integers().signed();
+        let texts: Vec<String> = [0.0, 0.19, 0.21, 0.5, 0.79, 0.81, 1.0].iter().map(|v| octave.display_text(&(), *v)).collect();
+        assert_eq!(texts, ["-2", "-2", "-1", "0", "+1", "+2", "+2"]);
+        assert_eq!(octave.steps(), Some(5));
+        assert_eq!(octave.parse_text(&(), "+1"), Some(0.75));
+        assert_eq!(octave.parse_text(&(), "7"), Some(1.0));
+
+        let shaper = super::Parameter::<()>::new("Shaper", 0.0).choice::<crate::waveshaper::ShaperType>();
+        assert_eq!(shaper.display_text(&(), 0.0), "Tanh");
+        assert_eq!(shaper.display_text(&(), 0.5), "Arctan");
+        assert_eq!(shaper.display_text(&(), 0.9), "Chebyshev 3rd Order");
+        assert_eq!(shaper.parse_text(&(), "arctan"), Some(0.5));
+        assert_eq!(shaper.parse_text(&(), "Fold"), None);
+        for i in 0..5 {
+            assert_eq!(step(step_value(i, 5), 5), i);
+        }
+    }
+}
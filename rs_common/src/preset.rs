@@ -0,0 +1,372 @@
+use crate::config;
+use crate::parameter::Parameters;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// user presets are plain text a person can read, diff and edit, keyed by parameter name rather than index -
+// a small subset of TOML, so other tools can read them too:
+//
+// name = "Warm Pad"
+//
+// [parameters]
+// "Filter Cutoff" = "559.4032 Hz"
+// "Osc 1 Type" = "Saw"
+//
+// values are in each parameter's own units and read back the way text typed into the host is, so "1200 Hz",
+// "1.2k" and a bare 1200 are all the same cutoff - a preset keeps meaning the same sound when the 0 - 1 the host
+// sees is mapped differently
+//
+// what's read is one key = value a line, with # comments and [table] headers - keys are "basic" or 'literal' strings,
+// or bare up to the =, and values are single line strings or plain numbers. basic strings take every
+// TOML escape, \b \t \n \f \r \" \\ \uXXXX and \UXXXXXXXX. multi-line strings, arrays, inline tables and dotted
+// keys aren't - a line using them is an error, unless it's in a table other than [parameters], which is skipped whole.
+// the name is only read before the first table
+//
+// what's written is always valid TOML - keys, values and the name are basic strings, with quotes, backslashes and
+// control characters escaped
+pub const EXTENSION: &str = "toml";
+const PARAMETERS_TABLE: &str = "[parameters]";
+
+// where a line is - before any table, where the name goes, in [parameters], or in some other table
+#[derive(PartialEq)]
+enum Table {
+    Top, Parameters, Other
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub values: Vec<(String, String)>
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\u{8}' => quoted += "\\b",
+            '\t' => quoted += "\\t",
+            '\n' => quoted += "\\n",
+            '\u{c}' => quoted += "\\f",
+            '\r' => quoted += "\\r",
+            c if c.is_control() => quoted += &format!("\\u{:04X}", u32::from(c)),
+            c => quoted.push(c)
+        }
+    }
+    quoted + "\""
+}
+
+// the character a \u or \U escape of digits hex digits stands for
+fn unescape_unicode(chars: &mut impl Iterator<Item = (usize, char)>, digits: usize) -> Result<char, String> {
+    let escape = if digits == 4 { 'u' } else { 'U' };
+    let hex: String = chars.take(digits).map(|(_, c)| c).collect();
+    if hex.chars().count() != digits || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("expected {} hex digits after \\{}", digits, escape));
+    }
+    u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or_else(|| format!("\\{}{} isn't a character", escape, hex))
+}
+
+// a basic or literal string at the start of text, and whatever comes after it
+fn unquote(text: &str) -> Result<(String, &str), String> {
+    if let Some(literal) = text.strip_prefix('\'') {
+        let end = literal.find('\'').ok_or("unclosed quote")?;
+        return Ok((literal[..end].to_string(), &literal[end + 1..]));
+    }
+    let mut unquoted = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((unquoted, &text[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, 'b')) => unquoted.push('\u{8}'),
+                Some((_, 't')) => unquoted.push('\t'),
+                Some((_, 'n')) => unquoted.push('\n'),
+                Some((_, 'f')) => unquoted.push('\u{c}'),
+                Some((_, 'r')) => unquoted.push('\r'),
+                Some((_, '"')) => unquoted.push('"'),
+                Some((_, '\\')) => unquoted.push('\\'),
+                Some((_, 'u')) => unquoted.push(unescape_unicode(&mut chars, 4)?),
+                Some((_, 'U')) => unquoted.push(unescape_unicode(&mut chars, 8)?),
+                Some((_, c)) => return Err(format!("unknown escape \\{}", c)),
+                None => return Err("unclosed quote".to_string())
+            },
+            _ => unquoted.push(c)
+        }
+    }
+    Err("unclosed quote".to_string())
+}
+
+// drops a trailing comment, the text must not have a quoted string in it
+fn strip_comment(text: &str) -> &str {
+    text.split('#').next().unwrap_or_default().trim()
+}
+
+// a whole value that's a string, with nothing but a comment after it
+fn parse_string(value: &str, what: &str) -> Result<String, String> {
+    let (text, rest) = unquote(value)?;
+    if !strip_comment(rest).is_empty() {
+        return Err(format!("'{}' after the {}", rest.trim(), what));
+    }
+    Ok(text)
+}
+
+// key = value, the key can be quoted or bare
+fn parse_line(line: &str) -> Result<(String, &str), String> {
+    let (key, rest) = if line.starts_with('"') || line.starts_with('\'') {
+        unquote(line)?
+    }
+    else {
+        let end = line.find('=').ok_or("expected key = value")?;
+        (line[..end].trim().to_string(), &line[end..])
+    };
+    let value = rest.trim_start().strip_prefix('=').ok_or("expected = after the key")?;
+    Ok((key, value.trim()))
+}
+
+impl Preset {
+    // every parameter, in ID order
+    pub fn from_parameters<P: Parameters>(name: &str, params: &P) -> Preset {
+        let values = (0..P::TABLE.len() as i32).map(|id| (params.name(id), params.preset_text(id))).collect();
+        Preset { name: name.to_string(), values }
+    }
+
+    pub fn write(&self) -> String {
+        let mut text = format!("name = {}\n\n{}\n", quote(&self.name), PARAMETERS_TABLE);
+        for (parameter, value) in self.values.iter() {
+            text += &format!("{} = {}\n", quote(parameter), quote(value));
+        }
+        text
+    }
+
+    // values are kept as text for the plugin to read, keys this doesn't know about are skipped
+    pub fn parse(text: &str) -> Result<Preset, String> {
+        let mut preset = Preset::default();
+        let mut table = Table::Top;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                table = if strip_comment(line) == PARAMETERS_TABLE { Table::Parameters } else { Table::Other };
+                continue;
+            }
+            // anything in a table this doesn't know is left for whatever wrote it
+            if table == Table::Other {
+                continue;
+            }
+            let (key, value) = parse_line(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            if table == Table::Parameters {
+                let value = if value.starts_with('"') || value.starts_with('\'') {
+                    parse_string(value, "value").map_err(|e| format!("line {}: {}", number + 1, e))?
+                }
+                else {
+                    let bare = strip_comment(value);
+                    match bare.parse::<f64>() {
+                        Ok(number) if number.is_finite() => bare.to_string(),
+                        _ => return Err(format!("line {}: '{}' isn't a string or a number", number + 1, value))
+                    }
+                };
+                preset.values.push((key, value));
+            }
+            else if key == "name" {
+                preset.name = parse_string(value, "name").map_err(|e| format!("line {}: {}", number + 1, e))?;
+            }
+        }
+        Ok(preset)
+    }
+
+    pub fn value(&self, parameter: &str) -> Option<&str> {
+        self.values.iter().find(|(p, _)| p == parameter).map(|(_, value)| value.as_str())
+    }
+
+    // a preset with no name is named after its file
+    pub fn load(path: &Path) -> Result<Preset, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut preset = Preset::parse(&text)?;
+        if preset.name.is_empty() {
+            preset.name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        }
+        Ok(preset)
+    }
+
+    // <dir>/<name>.toml, anything that can't go in a file name is swapped for _
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        let mut file_name: String = self.name.chars()
+            .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+            .collect();
+        if file_name.trim().is_empty() {
+            file_name = "Untitled".to_string();
+        }
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.{}", file_name, EXTENSION));
+        fs::write(&path, self.write())?;
+        Ok(path)
+    }
+}
+
+// saving and importing user presets, for the tools that drive a plugin - rs_render's --save-preset and --import-preset
+pub trait Library {
+    // the current program as <dir>/<name>.toml, the folder is made if it isn't there
+    fn save_preset(&self, dir: &Path) -> Result<PathBuf, String>;
+
+    // a preset file into the current program, which takes the preset's name
+    fn import_preset(&self, path: &Path) -> Result<(), String>;
+}
+
+// $XDG_CONFIG_HOME/<plugin>/presets
+pub fn library_dir(plugin: &str) -> Option<PathBuf> {
+    config::dir(plugin).map(|d| d.join("presets"))
+}
+
+// every preset file in the folder, sorted by file name
+pub fn list(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(dir)?.map(|e| e.map(|e| e.path())).collect::<io::Result<Vec<PathBuf>>>()?;
+    files.retain(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(EXTENSION)));
+    files.sort();
+    Ok(files)
+}
+
+// every preset in the folder that loads - a missing folder is an empty library
+pub fn load_library(dir: &Path) -> Vec<Preset> {
+    list(dir).unwrap_or_default().iter().filter_map(|path| Preset::load(path).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preset(name: &str) -> Preset {
+        Preset {
+            name: name.to_string(),
+            values: vec![("Filter Cutoff".to_string(), "559.4032 Hz".to_string()), ("Dry/Wet".to_string(), "1".to_string()), ("Osc 1 \"Type\"".to_string(), "\"Saw\"".to_string())]
+        }
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let text = preset("Bass").write();
+        assert!(text.starts_with("name = \"Bass\"\n\n[parameters]\n"));
+        assert!(text.contains("\"Filter Cutoff\" = \"559.4032 Hz\"\n"));
+        assert!(text.contains("\"Dry/Wet\" = \"1\"\n"));
+        assert!(text.contains("\"Osc 1 \\\"Type\\\"\" = \"\\\"Saw\\\"\"\n"));
+        assert_eq!(Preset::parse(&text), Ok(preset("Bass")));
+    }
+
+    #[test]
+    fn names_round_trip_as_toml_strings() {
+        // the name, and how it's written
+        let cases = [
+            ("Plain", "\"Plain\""),
+            ("Bass \"Deep\"", "\"Bass \\\"Deep\\\"\""),
+            ("C:\\Presets\\", "\"C:\\\\Presets\\\\\""),
+            ("Two\nLines", "\"Two\\nLines\""),
+            ("Tab\tCarriage\rFeed\u{c}Back\u{8}", "\"Tab\\tCarriage\\rFeed\\fBack\\b\""),
+            ("Bell\u{7}Delete\u{7f}", "\"Bell\\u0007Delete\\u007F\""),
+            ("# not a comment", "\"# not a comment\""),
+            ("'single'", "\"'single'\""),
+            ("Ünïcødé 🎹", "\"Ünïcødé 🎹\""),
+            ("", "\"\"")
+        ];
+        for (name, quoted) in cases.iter() {
+            let text = preset(name).write();
+            assert!(text.starts_with(&format!("name = {}\n", quoted)), "{:?} was written as {}", name, text.lines().next().unwrap());
+            assert_eq!(Preset::parse(&text), Ok(preset(name)), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn reads_every_kind_of_string() {
+        let cases = [
+            ("name = \"Pluck\" # short", "Pluck"),
+            ("name = 'C:\\Presets\\Pluck'", "C:\\Presets\\Pluck"),
+            ("name = '\"quoted\" # kept'", "\"quoted\" # kept"),
+            ("name = \"caf\\u00e9\"", "café"),
+            ("name = \"keys \\U0001F3B9\"", "keys 🎹"),
+            ("name = \"a\\\\b\\\"c\\n\"", "a\\b\"c\n"),
+            ("'name' = \"literal key\"", "literal key")
+        ];
+        for (line, name) in cases.iter() {
+            assert_eq!(Preset::parse(line).map(|p| p.name), Ok(name.to_string()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn reads_hand_written_files() {
+        let text = "# from the team library\nname = \"Pluck\" # short\nauthor = \"someone\"\n\n[parameters]\nVoices = 4\n  \"Filter Cutoff\"=\"1.2k Hz\" # bright\n'Drive' = '-1.5 dB'\nDetune = -0.25 # cents\n\n[extra]\nignored = 0.1\n";
+        let preset = Preset::parse(text).unwrap();
+        assert_eq!(preset.name, "Pluck");
+        let expected = [("Voices", "4"), ("Filter Cutoff", "1.2k Hz"), ("Drive", "-1.5 dB"), ("Detune", "-0.25")];
+        assert_eq!(preset.values, expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>());
+        assert_eq!(preset.value("Voices"), Some("4"));
+        assert_eq!(preset.value("Missing"), None);
+    }
+
+    #[test]
+    fn only_reads_the_name_before_the_first_table() {
+        let cases = [
+            ("name = \"Top\"\n[parameters]\nDrive = 1\n", "Top"),
+            // a name in another table is something else's name
+            ("[metadata]\nname = \"Author's\"\n[parameters]\nDrive = 1\n", ""),
+            ("name = \"Top\"\n[metadata]\nname = \"Author's\"\n", "Top"),
+            // and lines TOML allows but this doesn't read are fine there
+            ("[metadata]\ntags = [\"pad\", \"warm\"]\nnotes = \"\"\"two\nlines\"\"\"\n", "")
+        ];
+        for (text, name) in cases.iter() {
+            let preset = Preset::parse(text).unwrap();
+            assert_eq!(preset.name, *name, "{:?}", text);
+            assert!(preset.values.iter().all(|(key, _)| key == "Drive"), "{:?}", text);
+        }
+        // the parameters table is only [parameters], not one inside it
+        assert_eq!(Preset::parse("[parameters.extra]\nDrive = 1\n").unwrap().values, vec![]);
+    }
+
+    #[test]
+    fn rejects_broken_lines() {
+        let cases = [
+            ("[parameters]\n\"Drive\" = loud\n", "line 2: 'loud' isn't a string or a number"),
+            ("[parameters]\n\"Drive\" = NaN\n", "line 2: 'NaN' isn't a string or a number"),
+            ("[parameters]\nDrive = [0.5]\n", "line 2: '[0.5]' isn't a string or a number"),
+            ("[parameters]\nDrive = \"1 dB\" \"2 dB\"\n", "line 2: '\"2 dB\"' after the value"),
+            ("[parameters]\nDrive = \"1 dB\n", "line 2: unclosed quote"),
+            ("[parameters]\nDrive 0.5\n", "line 2: expected key = value"),
+            ("[parameters]\n\"Drive\" 0.5\n", "line 2: expected = after the key"),
+            ("name = \"unclosed\n", "line 1: unclosed quote"),
+            ("name = 'unclosed\n", "line 1: unclosed quote"),
+            ("name = \"ends in \\", "line 1: unclosed quote"),
+            ("name = \"\\q\"", "line 1: unknown escape \\q"),
+            ("name = \"\\u00\"", "line 1: expected 4 hex digits after \\u"),
+            ("name = \"\\U1F3B9\"", "line 1: expected 8 hex digits after \\U"),
+            ("name = \"\\U0000D800\"", "line 1: \\U0000D800 isn't a character"),
+            ("name = \"\\uD800\"", "line 1: \\uD800 isn't a character"),
+            ("name = \"one\" \"two\"", "line 1: '\"two\"' after the name"),
+            ("name = \"\"\"multi\nline\"\"\"", "line 1: '\"multi' after the name")
+        ];
+        for (text, error) in cases.iter() {
+            assert_eq!(Preset::parse(text), Err(error.to_string()), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn saves_and_lists_a_library() {
+        let dir = std::env::temp_dir().join(format!("rs_common_presets_{}", std::process::id()));
+        assert!(list(&dir).is_err());
+        assert!(load_library(&dir).is_empty());
+        let saved = preset("Bass \"Deep\" \\ 2\n").save(&dir).unwrap();
+        assert_eq!(saved.file_name().unwrap(), "Bass _Deep_ _ 2_.toml");
+        fs::write(dir.join("Unnamed.toml"), "[parameters]\nDrive = 0.5\n").unwrap();
+        fs::write(dir.join("Broken.toml"), "[parameters]\nDrive = loud\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a preset").unwrap();
+        let files = list(&dir).unwrap();
+        let library = load_library(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<String> = files.iter().map(|f| f.file_name().unwrap().to_string_lossy().to_string()).collect();
+        assert_eq!(names, ["Bass _Deep_ _ 2_.toml", "Broken.toml", "Unnamed.toml"]);
+        assert_eq!(library.len(), 2);
+        assert_eq!(library[0], preset("Bass \"Deep\" \\ 2\n"));
+        assert_eq!(library[1].name, "Unnamed");
+    }
+}
@@ -0,0 +1,354 @@
+use crate::parameter::Parameters;
+use crate::preset::Preset;
+use crate::state::{self, Migration, State, read_bytes};
+
+use std::convert::TryInto;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// the current program's name, saved with a preset
+pub const NAME_SECTION: [u8; 4] = *b"NAME";
+// every program in the bank, saved with a bank - current (u32) | count (u32) | programs, each
+// name length (u32) | name | parameter count (u32) | parameters (f32 each)
+pub const BANK_SECTION: [u8; 4] = *b"BANK";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    pub name: String,
+    pub parameters: Vec<f32>
+}
+
+impl Program {
+    // the plugin's defaults with some parameters changed, looked up by name so a preset still reads
+    // the same when parameters move - None if it changes a name the plugin doesn't have
+    pub fn new(name: &str, defaults: &[f32], names: &[String], changes: &[(&str, f32)]) -> Option<Self> {
+        let mut parameters = defaults.to_vec();
+        for (parameter, value) in changes.iter() {
+            let index = names.iter().position(|n| n == parameter)?;
+            *parameters.get_mut(index)? = *value;
+        }
+        Some(Program { name: name.to_string(), parameters })
+    }
+
+    // a user preset read into params, which are reset first so anything it doesn't set is at its default - each
+    // value is read as if it had been typed into the host, in ID order so text that depends on an earlier
+    // parameter reads right, and names params doesn't have or values that don't mean anything are skipped
+    pub fn from_preset<P: Parameters>(preset: &Preset, params: &P) -> Self {
+        params.reset();
+        let count = P::TABLE.len() as i32;
+        for id in 0..count {
+            if let Some(text) = preset.value(&params.name(id)) {
+                params.set_text(id, text);
+            }
+        }
+        Program { name: preset.name.clone(), parameters: (0..count).map(|id| params.normalized(id)).collect() }
+    }
+}
+
+fn read_u32(data: &[u8], at: &mut usize) -> Option<u32> {
+    let value = u32::from_le_bytes(read_bytes(data, *at, 4)?.try_into().ok()?);
+    *at += 4;
+    Some(value)
+}
+
+// the host's program slots - the selected program's values live in the plugin's parameters while it's
+// selected, and only get copied back into its slot when it's switched away from or the bank is saved
+pub struct Bank {
+    programs: Mutex<Vec<Program>>,
+    // fixed, the host is told how many programs there are when the plugin loads
+    count: usize,
+    current: AtomicUsize
+}
+
+impl Bank {
+    pub fn new(mut programs: Vec<Program>) -> Self {
+        // room in every slot for the longest program, so switching on the audio thread never has to grow one
+        let width = programs.iter().map(|p| p.parameters.len()).max().unwrap_or(0);
+        for program in programs.iter_mut() {
+            program.parameters.reserve(width - program.parameters.len());
+        }
+        Bank {
+            count: programs.len(),
+            programs: Mutex::new(programs),
+            current: AtomicUsize::new(0)
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Acquire)
+    }
+
+    pub fn name(&self, index: usize) -> String {
+        self.programs.lock().unwrap().get(index).map(|p| p.name.clone()).unwrap_or_default()
+    }
+
+    pub fn rename_current(&self, name: String) {
+        if let Some(program) = self.programs.lock().unwrap().get_mut(self.current()) {
+            program.name = name;
+        }
+    }
+
+    // keeps parameters (the values of the program being left) in its slot and returns the values of program index
+    // index must be less than count()
+    pub fn switch(&self, index: usize, parameters: &[f32]) -> Vec<f32> {
+        let mut programs = self.programs.lock().unwrap();
+        self.keep_current(&mut programs, parameters);
+        self.current.store(index, Ordering::Release);
+        programs[index].parameters.clone()
+    }
+
+    // the same, but for the audio thread - parameters are copied into the slot, then program index's values are
+    // copied back into them, so nothing is allocated as long as parameters has room for every value (anything
+    // past that stays in the slot). false if the bank is busy, try again later
+    pub fn try_switch(&self, index: usize, parameters: &mut Vec<f32>) -> bool {
+        let mut programs = match self.programs.try_lock() {
+            Ok(programs) => programs,
+            Err(_) => return false
+        };
+        self.keep_current(&mut programs, parameters);
+        self.current.store(index, Ordering::Release);
+        let room = parameters.capacity();
+        parameters.clear();
+        parameters.extend(programs[index].parameters.iter().take(room));
+        true
+    }
+
+    // into the slot's own values, which keep their room
+    fn keep_current(&self, programs: &mut [Program], parameters: &[f32]) {
+        if let Some(program) = programs.get_mut(self.current()) {
+            program.parameters.clear();
+            program.parameters.extend_from_slice(parameters);
+        }
+    }
+
+    // parameters are the current program's values
+    pub fn write(&self, parameters: &[f32]) -> Vec<u8> {
+        let mut programs = self.programs.lock().unwrap();
+        self.keep_current(&mut programs, parameters);
+        let mut data = vec![];
+        data.extend_from_slice(&(self.current() as u32).to_le_bytes());
+        data.extend_from_slice(&(programs.len() as u32).to_le_bytes());
+        for program in programs.iter() {
+            data.extend_from_slice(&(program.name.len() as u32).to_le_bytes());
+            data.extend_from_slice(program.name.as_bytes());
+            data.extend_from_slice(&(program.parameters.len() as u32).to_le_bytes());
+            for value in program.parameters.iter() {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    // replaces the programs with a saved bank whose parameters are from the given version, false if it isn't a whole bank
+    // a bank with more programs than this one loses the extras, one with fewer leaves the rest alone
+    // the selected program's values are returned in the state the bank was saved with, so they aren't loaded here
+    pub fn read(&self, data: &[u8], version: u32, migrations: &[Migration]) -> bool {
+        let (current, loaded) = match Bank::parse(data, version, migrations) {
+            Some(bank) => bank,
+            None => return false
+        };
+        let mut programs = self.programs.lock().unwrap();
+        for (program, loaded) in programs.iter_mut().zip(loaded) {
+            program.name = loaded.name;
+            program.parameters.clear();
+            program.parameters.extend_from_slice(&loaded.parameters);
+        }
+        self.current.store(current.min(self.count.saturating_sub(1)), Ordering::Release);
+        true
+    }
+
+    fn parse(data: &[u8], version: u32, migrations: &[Migration]) -> Option<(usize, Vec<Program>)> {
+        let mut at = 0;
+        let current = read_u32(data, &mut at)? as usize;
+        let count = read_u32(data, &mut at)? as usize;
+        let mut programs = vec![];
+        for _ in 0..count {
+            let name_length = read_u32(data, &mut at)? as usize;
+            let name = String::from_utf8(read_bytes(data, at, name_length)?.to_vec()).ok()?;
+            at += name_length;
+            let parameter_count = read_u32(data, &mut at)? as usize;
+            let parameters = read_bytes(data, at, parameter_count.checked_mul(4)?)?;
+            at += parameters.len();
+            let mut program = State::new(version, state::read_parameters(parameters));
+            program.migrate(migrations);
+            programs.push(Program { name, parameters: program.parameters });
+        }
+        Some((current, programs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameter::Parameter;
+    use crate::parameters;
+    use vst::util::AtomicFloat;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    type Changes<'a> = &'a [(&'a str, f32)];
+
+    fn program(name: &str, parameters: &[f32]) -> Program {
+        Program { name: name.to_string(), parameters: parameters.to_vec() }
+    }
+
+    fn bank() -> Bank {
+        Bank::new(vec![program("First", &[0.0, 0.0]), program("Second", &[0.0, 0.5]), program("Third", &[1.0, 0.25])])
+    }
+
+    #[test]
+    fn programs_are_built_from_the_defaults_by_name() {
+        let names = names(&["One", "Two"]);
+        let cases: [(Changes, Option<Program>); 5] = [
+            (&[], Some(program("Test", &[0.1, 0.2]))),
+            (&[("Two", 0.5)], Some(program("Test", &[0.1, 0.5]))),
+            (&[("One", 1.0), ("Two", 0.25)], Some(program("Test", &[1.0, 0.25]))),
+            // the last change to a parameter wins
+            (&[("One", 1.0), ("One", 0.75)], Some(program("Test", &[0.75, 0.2]))),
+            // a name the plugin doesn't have
+            (&[("One", 1.0), ("Three", 1.0)], None)
+        ];
+        for (changes, expected) in cases.iter() {
+            assert_eq!(Program::new("Test", &[0.1, 0.2], &names, changes), *expected, "{:?}", changes);
+        }
+        // a name with no default behind it
+        assert_eq!(Program::new("Test", &[0.1], &names, &[("Two", 1.0)]), None);
+    }
+
+    struct TestParameters {
+        cutoff: AtomicFloat,
+        mode: AtomicFloat,
+        gain: AtomicFloat
+    }
+
+    parameters! {
+        TestParameters {
+            0 => cutoff: Parameter::new("Cutoff", 1.0).label("Hz").range(20.0, 20000.0),
+            1 => mode: Parameter::new("Mode", 0.0).choices(&["Off", "On"]),
+            2 => gain: Parameter::new("Gain", 0.5)
+        }
+    }
+
+    #[test]
+    fn user_presets_fall_back_to_the_defaults() {
+        let params = TestParameters { cutoff: AtomicFloat::default(), mode: AtomicFloat::default(), gain: AtomicFloat::default() };
+        params.gain.set(0.1);
+        let value = |name: &str, text: &str| (name.to_string(), text.to_string());
+        let preset = Preset {
+            name: "User".to_string(),
+            values: vec![value("Mode", "On"), value("Gain", "loud"), value("Volume", "1")]
+        };
+        // the gain it couldn't read and the volume params doesn't have are left at the defaults
+        assert_eq!(Program::from_preset(&preset, &params), program("User", &[1.0, 1.0, 0.5]));
+        let preset = Preset { name: "Cutoff".to_string(), values: vec![value("Cutoff", "10010 Hz")] };
+        assert_eq!(Program::from_preset(&preset, &params), program("Cutoff", &[0.5, 0.0, 0.5]));
+    }
+
+    #[test]
+    fn switching_keeps_the_edits_to_the_program_left_behind() {
+        let bank = bank();
+        assert_eq!(bank.count(), 3);
+        assert_eq!(bank.name(2), "Third");
+        assert_eq!(bank.name(3), "");
+        assert_eq!(bank.switch(1, &[0.1, 0.1]), vec![0.0, 0.5]);
+        assert_eq!(bank.current(), 1);
+        bank.rename_current("Renamed".to_string());
+        let mut values = vec![0.2, 0.2];
+        assert!(bank.try_switch(0, &mut values));
+        assert_eq!(values, vec![0.1, 0.1]);
+        assert_eq!(bank.switch(1, &[0.1, 0.1]), vec![0.2, 0.2]);
+        assert_eq!(bank.name(1), "Renamed");
+    }
+
+    #[test]
+    fn switching_on_the_audio_thread_copies_into_the_room_there_is() {
+        let bank = Bank::new(vec![program("Short", &[0.1]), program("Long", &[0.2, 0.3, 0.4])]);
+        let mut values = Vec::with_capacity(3);
+        values.extend_from_slice(&[0.5, 0.5, 0.5]);
+        let buffer = values.as_ptr();
+        assert!(bank.try_switch(1, &mut values));
+        assert_eq!((values.as_slice(), values.as_ptr()), (&[0.2, 0.3, 0.4][..], buffer));
+        // the short program's slot had room for all the values left in it
+        assert!(bank.try_switch(0, &mut values));
+        assert_eq!((values.as_slice(), values.as_ptr()), (&[0.5, 0.5, 0.5][..], buffer));
+        // only as many values as there's room for come back
+        let mut small = Vec::with_capacity(1);
+        small.push(0.0);
+        assert!(bank.try_switch(1, &mut small));
+        assert_eq!(small, vec![0.2]);
+        // a busy bank doesn't switch
+        let _busy = bank.programs.lock().unwrap();
+        assert!(!bank.try_switch(0, &mut values));
+        assert_eq!(bank.current(), 1);
+    }
+
+    #[test]
+    fn round_trips_every_program() {
+        // which program is selected, and the values it was saved with
+        let cases: [(usize, &str, [f32; 2]); 3] = [
+            (0, "First", [0.0, 0.0]),
+            (2, "Edited", [0.75, 0.75]),
+            (1, "", [-1.0, 2.0])
+        ];
+        for (current, name, values) in cases.iter() {
+            let saved = bank();
+            saved.switch(*current, &[0.0, 0.0]);
+            saved.rename_current(name.to_string());
+            let data = saved.write(values);
+
+            let loaded = bank();
+            assert!(loaded.read(&data, 0, &[]));
+            assert_eq!(loaded.current(), *current);
+            for index in 0..3 {
+                assert_eq!(loaded.name(index), saved.name(index));
+                assert_eq!(loaded.switch(index, values), saved.switch(index, values), "{} {}", name, index);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_banks_that_arent_whole() {
+        let whole = bank().write(&[0.0, 0.0]);
+        // a bank of one program, after the current program
+        let one_program = |name: &[u8], count: u32, values: &[u8]| [&1u32.to_le_bytes(), &(name.len() as u32).to_le_bytes(), name, &count.to_le_bytes(), values].concat();
+        let cases: [(&str, Vec<u8>); 7] = [
+            ("empty", vec![]),
+            ("no program count", whole[..4].to_vec()),
+            ("truncated name", whole[..10].to_vec()),
+            ("truncated parameters", whole[..whole.len() - 1].to_vec()),
+            ("name that isn't utf-8", [&0u32.to_le_bytes()[..], &one_program(&[0xff], 0, &[])].concat()),
+            ("name length of u32::MAX", [&0u32.to_le_bytes()[..], &1u32.to_le_bytes(), &u32::MAX.to_le_bytes(), &[0; 16]].concat()),
+            ("parameter count of u32::MAX", [&0u32.to_le_bytes()[..], &one_program(b"A", u32::MAX, &[0; 16])].concat())
+        ];
+        for (case, data) in cases.iter() {
+            let loaded = bank();
+            assert!(!loaded.read(data, 0, &[]), "{}", case);
+            // and nothing changed
+            assert_eq!(loaded.name(0), "First", "{}", case);
+            assert_eq!(loaded.current(), 0, "{}", case);
+        }
+    }
+
+    #[test]
+    fn migrates_every_program() {
+        let data = bank().write(&[0.0, 0.0]);
+        let loaded = bank();
+        assert!(loaded.read(&data, 0, &[|parameters| parameters.swap(0, 1)]));
+        assert_eq!(loaded.switch(1, &[0.0, 0.0]), vec![0.5, 0.0]);
+        assert_eq!(loaded.switch(2, &[0.0, 0.0]), vec![0.25, 1.0]);
+    }
+
+    #[test]
+    fn an_empty_bank_has_nothing_to_rename_or_keep() {
+        let bank = Bank::new(vec![]);
+        bank.rename_current("Nothing".to_string());
+        assert_eq!(bank.name(0), "");
+        assert_eq!(bank.write(&[0.5]), [0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
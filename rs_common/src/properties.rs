@@ -0,0 +1,163 @@
+use crate::parameter::Parameters;
+
+use vst::api::{AEffect, DispatcherProc};
+
+use std::os::raw::c_void;
+use std::sync::OnceLock;
+
+// effGetParameterProperties - how a host finds out which parameters are stepped and how many steps they have
+// vst 0.2.1 never passes it on to the plugin, so it's answered here from the parameter table and everything
+// else goes on to the usual dispatcher
+const GET_PARAMETER_PROPERTIES: i32 = 56;
+const IS_SWITCH: i32 = 1;
+const USES_INTEGER_MIN_MAX: i32 = 1 << 1;
+const USES_FLOAT_STEP: i32 = 1 << 2;
+const USES_INT_STEP: i32 = 1 << 3;
+
+// VstParameterProperties
+#[repr(C)]
+pub struct ParameterProperties {
+    pub step_float: f32,
+    pub small_step_float: f32,
+    pub large_step_float: f32,
+    pub label: [u8; 64],
+    pub flags: i32,
+    pub min_integer: i32,
+    pub max_integer: i32,
+    pub step_integer: i32,
+    pub large_step_integer: i32,
+    pub short_label: [u8; 8],
+    pub display_index: i16,
+    pub category: i16,
+    pub parameters_in_category: i16,
+    pub reserved: i16,
+    pub category_label: [u8; 24],
+    pub future: [u8; 16]
+}
+
+// vst's own dispatcher, the same for every instance
+static DISPATCH: OnceLock<DispatcherProc> = OnceLock::new();
+
+// the properties of a stepped parameter, None for anything else - the host treats those as continuous
+pub fn properties<P: Parameters>(id: i32) -> Option<ParameterProperties> {
+    let parameter = P::parameter(id)?;
+    let steps = parameter.steps()?;
+    let step = 1.0 / (steps.max(2) - 1) as f32;
+    let mut label = [0; 64];
+    for (to, from) in label.iter_mut().zip(parameter.name.bytes().take(63)) {
+        *to = from;
+    }
+    Some(ParameterProperties {
+        step_float: step,
+        small_step_float: step,
+        large_step_float: step,
+        label,
+        flags: USES_INTEGER_MIN_MAX | USES_FLOAT_STEP | USES_INT_STEP | if steps == 2 { IS_SWITCH } else { 0 },
+        min_integer: parameter.min as i32,
+        max_integer: parameter.min as i32 + steps as i32 - 1,
+        step_integer: 1,
+        large_step_integer: 1,
+        short_label: [0; 8],
+        display_index: 0,
+        category: 0,
+        parameters_in_category: 0,
+        reserved: 0,
+        category_label: [0; 24],
+        future: [0; 16]
+    })
+}
+
+pub fn dispatch<P: Parameters>(effect: *mut AEffect, opcode: i32, index: i32, value: isize, ptr: *mut c_void, opt: f32) -> isize {
+    if opcode == GET_PARAMETER_PROPERTIES {
+        return match properties::<P>(index) {
+            Some(properties) if !ptr.is_null() => {
+                unsafe { (ptr as *mut ParameterProperties).write(properties) };
+                1
+            },
+            _ => 0
+        };
+    }
+    match DISPATCH.get() {
+        Some(dispatch) => dispatch(effect, opcode, index, value, ptr, opt),
+        None => 0
+    }
+}
+
+// puts dispatch in front of the effect vst::main made
+/// # Safety
+/// effect has to be one vst::main just returned
+pub unsafe fn wrap<P: Parameters>(effect: *mut AEffect) -> *mut AEffect {
+    unsafe {
+        let _ = DISPATCH.set((*effect).dispatcher);
+        (*effect).dispatcher = dispatch::<P>;
+    }
+    effect
+}
+
+// plugin_main! that also tells the host about the stepped parameters in the plugin's parameter table
+#[macro_export]
+macro_rules! plugin_main_with_properties {
+    ($plugin:ty, $params:ty) => {
+        #[cfg(target_os = "macos")]
+        #[no_mangle]
+        pub extern "system" fn main_macho(callback: ::vst::api::HostCallbackProc) -> *mut ::vst::api::AEffect {
+            VSTPluginMain(callback)
+        }
+
+        #[cfg(target_os = "windows")]
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        pub extern "system" fn MAIN(callback: ::vst::api::HostCallbackProc) -> *mut ::vst::api::AEffect {
+            VSTPluginMain(callback)
+        }
+
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        pub extern "C" fn VSTPluginMain(callback: ::vst::api::HostCallbackProc) -> *mut ::vst::api::AEffect {
+            unsafe { $crate::properties::wrap::<$params>(::vst::main::<$plugin>(callback)) }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameter::Parameter;
+    use crate::parameters;
+    use vst::util::AtomicFloat;
+
+    struct TestParameters {
+        octave: AtomicFloat,
+        mode: AtomicFloat,
+        gain: AtomicFloat
+    }
+
+    parameters! {
+        TestParameters {
+            0 => octave: Parameter::new("Octave", 0.5).range(-2.0, 2.0).integers(),
+            1 => mode: Parameter::new("Mode", 0.0).choices(&["Off", "On"]),
+            2 => gain: Parameter::new("Gain", 0.5)
+        }
+    }
+
+    #[test]
+    fn reports_the_steps_of_stepped_parameters() {
+        let octave = properties::<TestParameters>(0).unwrap();
+        assert_eq!((octave.min_integer, octave.max_integer, octave.step_float), (-2, 2, 0.25));
+        assert_eq!(octave.flags & IS_SWITCH, 0);
+        assert_eq!(&octave.label[..7], b"Octave\0");
+        let mode = properties::<TestParameters>(1).unwrap();
+        assert_eq!((mode.min_integer, mode.max_integer, mode.flags & IS_SWITCH), (0, 1, IS_SWITCH));
+        assert!(properties::<TestParameters>(2).is_none());
+        assert!(properties::<TestParameters>(3).is_none());
+    }
+
+    #[test]
+    fn answers_the_host_without_the_plugin() {
+        let mut properties = std::mem::MaybeUninit::<ParameterProperties>::uninit();
+        let ptr = properties.as_mut_ptr() as *mut c_void;
+        assert_eq!(dispatch::<TestParameters>(std::ptr::null_mut(), GET_PARAMETER_PROPERTIES, 0, 0, ptr, 0.0), 1);
+        assert_eq!(unsafe { properties.assume_init() }.max_integer, 2);
+        assert_eq!(dispatch::<TestParameters>(std::ptr::null_mut(), GET_PARAMETER_PROPERTIES, 2, 0, ptr, 0.0), 0);
+    }
+}
@@ -0,0 +1,189 @@
+use std::convert::TryInto;
+
+// what a plugin hands the host to save - the parameter values plus tagged sections for anything that isn't a parameter
+// magic | version (u32) | parameter count (u32) | parameters (f32 each) | sections, each tag | length (u32) | bytes
+// everything is little endian, and a reader skips any section it doesn't know so older versions can open newer states
+pub const MAGIC: [u8; 4] = *b"RSVS";
+
+// takes the parameters saved by one version to the next - migrations[n] takes version n to n + 1,
+// so the current version is however many migrations the plugin has
+pub type Migration = fn(&mut Vec<f32>);
+
+#[derive(Debug, Default, PartialEq)]
+pub struct State {
+    pub version: u32,
+    pub parameters: Vec<f32>,
+    sections: Vec<([u8; 4], Vec<u8>)>
+}
+
+// length bytes from at, None if they run past the end - lengths come from the data, so the sum is checked
+pub fn read_bytes(data: &[u8], at: usize, length: usize) -> Option<&[u8]> {
+    data.get(at..at.checked_add(length)?)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(read_bytes(data, at, 4)?.try_into().ok()?))
+}
+
+// f32s until the data runs out - anything past the end of a short state is left at its default
+pub fn read_parameters(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(4).map(|value| f32::from_le_bytes(value.try_into().unwrap())).collect()
+}
+
+impl State {
+    pub fn new(version: u32, parameters: Vec<f32>) -> Self {
+        State { version, parameters, sections: vec![] }
+    }
+
+    pub fn add_section(&mut self, tag: [u8; 4], data: Vec<u8>) {
+        self.sections.push((tag, data));
+    }
+
+    pub fn section(&self, tag: [u8; 4]) -> Option<&[u8]> {
+        self.sections.iter().find(|(t, _)| *t == tag).map(|(_, data)| data.as_slice())
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.extend_from_slice(&(self.parameters.len() as u32).to_le_bytes());
+        for value in self.parameters.iter() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for (tag, section) in self.sections.iter() {
+            data.extend_from_slice(tag);
+            data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            data.extend_from_slice(section);
+        }
+        data
+    }
+
+    // None if this isn't a state or it's been cut short
+    pub fn read(data: &[u8]) -> Option<State> {
+        if data.get(0..4)? != MAGIC {
+            return None;
+        }
+        let version = read_u32(data, 4)?;
+        let count = read_u32(data, 8)? as usize;
+        let parameters = read_bytes(data, 12, count.checked_mul(4)?)?;
+        let mut state = State::new(version, read_parameters(parameters));
+        let mut at = 12 + parameters.len();
+        while at < data.len() {
+            let tag = read_bytes(data, at, 4)?.try_into().ok()?;
+            let length = read_u32(data, at + 4)? as usize;
+            state.add_section(tag, read_bytes(data, at + 8, length)?.to_vec());
+            at += 8 + length;
+        }
+        Some(state)
+    }
+
+    // brings the parameters up to the current version, a state from a newer version is left as it is
+    pub fn migrate(&mut self, migrations: &[Migration]) {
+        let current = migrations.len() as u32;
+        if self.version >= current {
+            return;
+        }
+        for migration in migrations[self.version as usize..].iter() {
+            migration(&mut self.parameters);
+        }
+        self.version = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(version: u32, parameters: &[f32], sections: &[(&[u8; 4], &[u8])]) -> State {
+        let mut state = State::new(version, parameters.to_vec());
+        for (tag, data) in sections.iter() {
+            state.add_section(**tag, data.to_vec());
+        }
+        state
+    }
+
+    // the bytes of a state written by hand, so the reader isn't only checked against the writer
+    fn bytes(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[test]
+    fn round_trips_parameters_and_sections() {
+        let cases = [
+            state(0, &[], &[]),
+            state(3, &[0.0, 0.25, 1.0], &[]),
+            state(1, &[], &[(b"ONE ", &[1, 2, 3])]),
+            state(7, &[-1.0, f32::MAX], &[(b"ONE ", &[1, 2, 3]), (b"NONE", &[])])
+        ];
+        for state in cases.iter() {
+            assert_eq!(State::read(&state.write()).as_ref(), Some(state));
+        }
+        let read = State::read(&cases[3].write()).unwrap();
+        assert_eq!(read.section(*b"ONE "), Some(&[1u8, 2, 3][..]));
+        assert_eq!(read.section(*b"NONE"), Some(&[][..]));
+        assert_eq!(read.section(*b"MISS"), None);
+    }
+
+    #[test]
+    fn reads_a_state_written_by_hand() {
+        let data = bytes(&[b"RSVS", &2u32.to_le_bytes(), &1u32.to_le_bytes(), &0.5f32.to_le_bytes(), b"TAG ", &2u32.to_le_bytes(), &[9, 8]]);
+        assert_eq!(State::read(&data), Some(state(2, &[0.5], &[(b"TAG ", &[9, 8])])));
+    }
+
+    #[test]
+    fn rejects_data_that_isnt_a_whole_state() {
+        let whole = state(1, &[0.5; 4], &[(b"DATA", &[7; 8])]).write();
+        let cases: [(&str, Vec<u8>); 10] = [
+            ("empty", vec![]),
+            ("wrong magic", bytes(&[b"VST2", &whole[4..]])),
+            ("no magic", whole[4..].to_vec()),
+            ("no version", whole[..4].to_vec()),
+            ("no parameter count", whole[..8].to_vec()),
+            ("truncated parameters", whole[..20].to_vec()),
+            ("truncated section tag", whole[..30].to_vec()),
+            ("truncated section", whole[..whole.len() - 1].to_vec()),
+            // lengths that overflow if they're added up without checking
+            ("parameter count of u32::MAX", bytes(&[b"RSVS", &0u32.to_le_bytes(), &u32::MAX.to_le_bytes(), &[0; 16]])),
+            ("section length of u32::MAX", bytes(&[b"RSVS", &0u32.to_le_bytes(), &0u32.to_le_bytes(), b"DATA", &u32::MAX.to_le_bytes(), &[0; 16]]))
+        ];
+        for (case, data) in cases.iter() {
+            assert_eq!(State::read(data), None, "{}", case);
+        }
+    }
+
+    #[test]
+    fn survives_any_count_and_length() {
+        // every count and section length a hostile or corrupt chunk could hold at the edges of u32 and usize
+        let lengths = [0, 1, 3, 4, u32::MAX / 4, u32::MAX / 4 + 1, u32::MAX - 7, u32::MAX - 3, u32::MAX];
+        for count in lengths.iter() {
+            for length in lengths.iter() {
+                let data = bytes(&[b"RSVS", &0u32.to_le_bytes(), &count.to_le_bytes(), b"DATA", &length.to_le_bytes(), &[1; 8]]);
+                let read = State::read(&data);
+                assert!(read.is_none() || read.unwrap().parameters.len() <= 4, "{} {}", count, length);
+            }
+        }
+    }
+
+    #[test]
+    fn migrates_from_the_saved_version_onwards() {
+        // version 1 added a parameter at the start, version 2 swapped the first two
+        let migrations: [Migration; 2] = [
+            |parameters| parameters.insert(0, 0.5),
+            |parameters| parameters.swap(0, 1)
+        ];
+        let mut from_zero = State::new(0, vec![0.1, 0.2]);
+        from_zero.migrate(&migrations);
+        assert_eq!(from_zero, State::new(2, vec![0.1, 0.5, 0.2]));
+
+        let mut from_one = State::new(1, vec![0.5, 0.1, 0.2]);
+        from_one.migrate(&migrations);
+        assert_eq!(from_one, State::new(2, vec![0.1, 0.5, 0.2]));
+
+        // nothing to do for the current version, or for one newer than this build knows about
+        for version in [2, 3].iter() {
+            let mut state = State::new(*version, vec![0.1, 0.2]);
+            state.migrate(&migrations);
+            assert_eq!(state, State::new(*version, vec![0.1, 0.2]));
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use crate::parameter::Choice;
+
+const HALF_PI: f32 = std::f32::consts::PI / 2.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ShaperType {
+    Tanh, Arctan, Chebyshev
+}
+
+impl Choice for ShaperType {
+    const ALL: &'static [ShaperType] = &[ShaperType::Tanh, ShaperType::Arctan, ShaperType::Chebyshev];
+    const NAMES: &'static [&'static str] = &["Tanh", "Arctan", "Chebyshev 3rd Order"];
+}
+
+// the raw transfer curves, all map -1 - 1 onto -1 - 1 - callers handle drive and output level
+pub fn shape(shaper: ShaperType, sample: f32) -> f32 {
+    match shaper {
+        ShaperType::Tanh => sample.tanh(),
+        ShaperType::Arctan => sample.atan() / HALF_PI,
+        // T3 is only bounded inside -1 - 1, so clip before it rather than letting it run off to infinity
+        ShaperType::Chebyshev => {
+            let x = sample.clamp(-1.0, 1.0);
+            4.0 * x.powi(3) - (3.0 * x)
+        }
+    }
+}
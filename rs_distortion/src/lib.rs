@@ -1,113 +1,247 @@
-#[macro_use]
 extern crate vst;
+#[macro_use]
+extern crate rs_common;
 
-use vst::plugin::{HostCallback, Info, Plugin, Category, PluginParameters};
+use vst::plugin::{HostCallback, Info, Plugin, Category, PluginParameters, CanDo};
 use vst::util::AtomicFloat;
 use vst::buffer::AudioBuffer;
+use vst::api::{Events, Supported};
+use vst::event::Event;
+use vst::host::Host;
 
+use rs_common::parameter::{self, Choice, Parameters};
+use rs_common::preset::{self, Library};
+use rs_common::program::{self, Bank};
+use rs_common::state::{Migration, State};
+use rs_common::waveshaper::{self, ShaperType};
+
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::f32;
 
-const HALF_PI: f32 = std::f32::consts::PI / 2.0;
+mod presets;
+
+const PARAMETERS: i32 = DistortionParameters::TABLE.len() as i32;
+// add one whenever parameters are moved or removed, the saved state's version is how many there are
+// new parameters take the next ID and don't need one - they're missing from older states and keep their defaults
+const MIGRATIONS: [Migration; 1] = [|parameters| from_uneven_types(parameters)];
 
 #[derive(Default)]
-struct Distortion {
+pub struct Distortion {
+    host: HostCallback,
     params: Arc<DistortionParameters>,
+    // from a MIDI program change, waiting for the bank to be free
+    pending_program: Option<usize>,
+    // the values that go into the bank and come back out on a program change, with room made in resume so
+    // switching on the audio thread doesn't allocate
+    program_values: Vec<f32>
 }
 
 struct DistortionParameters {
-    // drive level
-    // type - Tanh, Arctan, Chebyshev 3rd Order
-    // dry/wet
     coefficient: AtomicFloat,
     distortion_type: AtomicFloat,
     level: AtomicFloat,
-    dry_wet: AtomicFloat
+    dry_wet: AtomicFloat,
+    bank: Bank
+}
+
+type Parameter = rs_common::parameter::Parameter<DistortionParameters>;
+
+parameters! {
+    DistortionParameters {
+        0 => coefficient as COEFFICIENT: Parameter::new("Coefficient", 0.5).range(1.0, 10.0),
+        1 => distortion_type: Parameter::new("Type", 0.0).choice::<ShaperType>(),
+        2 => level as LEVEL: Parameter::new("Level", 0.5),
+        3 => dry_wet as DRY_WET: Parameter::new("Dry/Wet", 0.5)
+    }
+}
+
+// Type used to be Tanh below 0.5, Arctan below 1 and Chebyshev only at 1, now each gets a third of the range
+fn from_uneven_types(parameters: &mut [f32]) {
+    if let Some(value) = parameters.get_mut(1) {
+        *value = parameter::step_from_thresholds(*value, &[0.5, 1.0]);
+    }
 }
 
 impl DistortionParameters {
-    fn get_distortion_name(&self) -> String {        
-        match self.distortion_type.get() {
-            t if t < 0.5 => "Tanh",
-            t if t < 1.0 => "Arctan",
-            t if t == 1.0 => "Chebyshev 3rd Order",
-            _ => "" 
-        }.to_string()
+    fn get_distortion_type(&self) -> ShaperType {
+        ShaperType::from_value(self.distortion_type.get())
+    }
+
+    // the defaults, plus the user presets from the config folder if the library feature is on
+    fn with_library() -> Self {
+        if !cfg!(feature = "library") {
+            return DistortionParameters::default();
+        }
+        let mut params = DistortionParameters::default();
+        if let Some(dir) = preset::library_dir("oxidize") {
+            params.bank = Bank::new(presets::with_library(&params, &dir));
+        }
+        params
+    }
+
+    fn parameter_values(&self) -> Vec<f32> {
+        (0..PARAMETERS).map(|i| self.get_parameter(i)).collect()
+    }
+
+    // anything past the parameters this version has is skipped
+    fn set_parameter_values(&self, values: &[f32]) {
+        for (i, value) in values.iter().enumerate().take(PARAMETERS as usize) {
+            self.set_parameter(i as i32, *value);
+        }
+    }
+
+    // the current program - its parameters and name
+    fn program_state(&self) -> State {
+        let mut state = State::new(MIGRATIONS.len() as u32, self.parameter_values());
+        state.add_section(program::NAME_SECTION, self.bank.name(self.bank.current()).into_bytes());
+        state
     }
 
-    fn get_distortion_type_rounded(&self) -> i32 {
-        match self.distortion_type.get() {
-            t if t < 0.5 => 0,
-            t if t < 1.0 => 1,
-            t if t == 1.0 => 2,
-            _ => 0 
+    fn load_program_state(&self, mut state: State) {
+        state.migrate(&MIGRATIONS);
+        self.set_parameter_values(&state.parameters);
+        if let Some(name) = state.section(program::NAME_SECTION).and_then(|n| String::from_utf8(n.to_vec()).ok()) {
+            self.bank.rename_current(name);
         }
     }
 }
 
 impl Default for DistortionParameters {
     fn default() -> DistortionParameters {
-        DistortionParameters {
-            coefficient: AtomicFloat::new(0.5),
-            distortion_type: AtomicFloat::new(0.0),
-            level: AtomicFloat::new(0.5),
-            dry_wet: AtomicFloat::new(0.5)
-        }
+        let mut params = DistortionParameters {
+            coefficient: AtomicFloat::default(),
+            distortion_type: AtomicFloat::default(),
+            level: AtomicFloat::default(),
+            dry_wet: AtomicFloat::default(),
+            bank: Bank::new(vec![])
+        };
+        // the defaults are in the parameter table, and the factory programs are built from them
+        params.reset();
+        params.bank = Bank::new(presets::factory(&params));
+        params
     }
 }
 
 impl PluginParameters for DistortionParameters {
-    fn get_parameter_text(&self, index: i32) -> String {
-        match index {
-            0 => format!("{:.2}", (self.coefficient.get() * 9.0) + 1.0),
-            1 => self.get_distortion_name(),
-            2 => format!("{:.2}", self.level.get()),
-            3 => format!("{:.2}", self.dry_wet.get()),
-            _ => "".to_string()
+    parameter_methods!();
+
+    fn change_preset(&self, preset: i32) {
+        let index = preset as usize;
+        if preset >= 0 && index < self.bank.count() && index != self.bank.current() {
+            self.set_parameter_values(&self.bank.switch(index, &self.parameter_values()));
         }
     }
 
-    fn get_parameter_name(&self, index: i32) -> String {
-        match index {
-            0 => "Coefficient",
-            1 => "Type",
-            2 => "Level",
-            3 => "Dry/Wet",
-            _ => ""
-        }.to_string()
+    fn get_preset_num(&self) -> i32 {
+        self.bank.current() as i32
+    }
+
+    fn set_preset_name(&self, name: String) {
+        self.bank.rename_current(name);
+    }
+
+    fn get_preset_name(&self, preset: i32) -> String {
+        self.bank.name(preset as usize)
+    }
+
+    fn get_preset_data(&self) -> Vec<u8> {
+        self.program_state().write()
     }
 
-    fn get_parameter(&self, index: i32) -> f32 {
-        match index {
-            0 => self.coefficient.get(),
-            1 => self.distortion_type.get(),
-            2 => self.level.get(),
-            3 => self.dry_wet.get(),
-            _ => 0.0
+    fn load_preset_data(&self, data: &[u8]) {
+        if let Some(state) = State::read(data) {
+            self.load_program_state(state);
         }
     }
 
-    fn set_parameter(&self, index: i32, value: f32) {
-        match index {
-            0 => self.coefficient.set(value),
-            1 => self.distortion_type.set(value),
-            2 => self.level.set(value),
-            3 => self.dry_wet.set(value),
-            _ => ()
+    // the current program's state plus every program in the bank
+    fn get_bank_data(&self) -> Vec<u8> {
+        let mut state = self.program_state();
+        state.add_section(program::BANK_SECTION, self.bank.write(&state.parameters));
+        state.write()
+    }
+
+    // a state saved as a preset loads into the current program
+    fn load_bank_data(&self, data: &[u8]) {
+        if let Some(state) = State::read(data) {
+            if let Some(bank) = state.section(program::BANK_SECTION) {
+                self.bank.read(bank, state.version, &MIGRATIONS);
+            }
+            self.load_program_state(state);
         }
     }
 }
 
-impl Distortion {
+// a preset saved into the user library is listed after the factory programs the next time the plugin loads
+impl Library for Distortion {
+    fn save_preset(&self, dir: &Path) -> Result<PathBuf, String> {
+        let preset = presets::current(&self.params);
+        preset.save(dir).map_err(|e| format!("couldn't save {} in {}: {}", preset.name, dir.display(), e))
+    }
+
+    fn import_preset(&self, path: &Path) -> Result<(), String> {
+        let program = presets::import(path, &self.params).map_err(|e| format!("couldn't import {}: {}", path.display(), e))?;
+        self.params.bank.rename_current(program.name);
+        Ok(())
+    }
+}
+
+// the parameters in their units, read once a buffer
+struct Shaping {
+    coefficient: f32,
+    shaper: ShaperType,
+    level: f32,
+    dry_wet: f32
+}
+
+impl Shaping {
+    fn new(params: &DistortionParameters) -> Self {
+        Shaping {
+            coefficient: params.plain(DistortionParameters::COEFFICIENT),
+            shaper: params.get_distortion_type(),
+            level: params.plain(DistortionParameters::LEVEL),
+            dry_wet: params.plain(DistortionParameters::DRY_WET)
+        }
+    }
+
     fn process_sample(&self, sample: f32) -> f32 {
-        let level = (self.params.coefficient.get() * 9.0) + 1.0;
-        let processed = match self.params.distortion_type.get() {
-            t if t < 0.5 => level * sample.tanh(),
-            t if t < 1.0 => level * sample.atanh() * HALF_PI,
-            t if t == 1.0 => level * (4.0 * sample.powi(3) - (3.0 * sample)),
-            _ => 0.0    
-        };
-        ((sample * (1.0 - self.params.dry_wet.get())) + (processed * self.params.dry_wet.get())) * self.params.level.get()
+        let processed = self.coefficient * waveshaper::shape(self.shaper, sample);
+        ((sample * (1.0 - self.dry_wet)) + (processed * self.dry_wet)) * self.level
+    }
+}
+
+impl Distortion {
+    // new picks up the user's library, the tests start from the factory defaults so they don't depend on the machine
+    fn with_params(host: HostCallback, params: DistortionParameters) -> Distortion {
+        Distortion {
+            host,
+            params: Arc::new(params),
+            pending_program: None,
+            program_values: vec![]
+        }
+    }
+
+    // program change on any channel is the only MIDI we listen to
+    fn process_midi_event(&mut self, data: [u8; 3]) {
+        if data[0] & 0xf0 == 192 && usize::from(data[1]) < self.params.bank.count() {
+            self.pending_program = Some(usize::from(data[1]));
+        }
+    }
+
+    // switches to a program picked with a MIDI program change - never waits on the bank, it tries again next buffer instead
+    fn update_program(&mut self) {
+        if let Some(index) = self.pending_program {
+            let params = &self.params;
+            self.program_values.clear();
+            self.program_values.extend((0..PARAMETERS).map(|i| params.get_parameter(i)));
+            if params.bank.try_switch(index, &mut self.program_values) {
+                params.set_parameter_values(&self.program_values);
+                self.pending_program = None;
+                if self.host.raw_callback().is_some() {
+                    self.host.update_display();
+                }
+            }
+        }
     }
 }
 
@@ -120,22 +254,28 @@ impl Plugin for Distortion {
             version: 1,
             inputs: 2,
             outputs: 2,
-            parameters: 4,
+            parameters: PARAMETERS,
+            presets: self.params.bank.count() as i32,
             category: Category::Effect,
+            preset_chunks: true,
             ..Default::default()
         }
     }
 
-    fn new(_host: HostCallback) -> Self {
-        Distortion {
-            params: Arc::new(DistortionParameters::default())
-        }
+    fn new(host: HostCallback) -> Self {
+        Distortion::with_params(host, DistortionParameters::with_library())
+    }
+
+    fn resume(&mut self) {
+        self.program_values = Vec::with_capacity(PARAMETERS as usize);
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {                
+        self.update_program();
+        let shaping = Shaping::new(&self.params);
         for (input_buffer, output_buffer) in buffer.zip() {            
             for (input_sample, output_sample) in input_buffer.iter().zip(output_buffer) {
-                *output_sample = self.process_sample(*input_sample);
+                *output_sample = shaping.process_sample(*input_sample);
             }
         }
     }
@@ -143,6 +283,229 @@ impl Plugin for Distortion {
     fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
         Arc::clone(&self.params) as Arc<dyn PluginParameters>
     }
+
+    fn can_do(&self, can_do: CanDo) -> Supported {
+        match can_do {
+            CanDo::ReceiveMidiEvent => Supported::Yes,
+            _ => Supported::Maybe
+        }
+    }
+
+    fn process_events(&mut self, events: &Events) {
+        for event in events.events() {
+            if let Event::Midi(ev) = event {
+                self.process_midi_event(ev.data);
+            }
+        }
+    }
 }
 
-plugin_main!(Distortion);
\ No newline at end of file
+#[cfg(feature = "entry")]
+plugin_main_with_properties!(Distortion, DistortionParameters);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changed_parameters() -> DistortionParameters {
+        let params = DistortionParameters::default();
+        for (i, value) in [0.9, 0.7, 0.2, 1.0].iter().enumerate() {
+            params.set_parameter(i as i32, *value);
+        }
+        params
+    }
+
+    fn assert_same_parameters(loaded: &DistortionParameters, saved: &DistortionParameters) {
+        for i in 0..PARAMETERS {
+            assert_eq!(loaded.get_parameter(i), saved.get_parameter(i), "parameter {}", i);
+        }
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let saved = changed_parameters();
+        for case in ["preset", "bank"].iter() {
+            let loaded = DistortionParameters::default();
+            if *case == "bank" {
+                loaded.load_bank_data(&saved.get_bank_data());
+            }
+            else {
+                loaded.load_preset_data(&saved.get_preset_data());
+            }
+            assert_same_parameters(&loaded, &saved);
+            assert_eq!(loaded.get_preset_data(), saved.get_preset_data(), "{}", case);
+        }
+    }
+
+    #[test]
+    fn loads_state_from_a_newer_version() {
+        let saved = changed_parameters();
+        let mut state = State::read(&saved.get_preset_data()).unwrap();
+        state.version += 1;
+        state.parameters.push(0.5);
+        state.add_section(*b"NEW ", vec![1, 2, 3]);
+        let loaded = DistortionParameters::default();
+        loaded.load_preset_data(&state.write());
+        assert_same_parameters(&loaded, &saved);
+    }
+
+    #[test]
+    fn switching_programs_loads_them_and_keeps_edits() {
+        let params = DistortionParameters::default();
+        assert_eq!(params.get_preset_name(3), "Fuzz");
+        params.change_preset(3);
+        assert_eq!(params.get_parameter_text(1), "Chebyshev 3rd Order");
+        params.set_parameter(2, 0.1);
+        params.set_preset_name("Quiet Fuzz".to_string());
+        params.change_preset(1);
+        assert_eq!(params.get_parameter(2), 0.6);
+        params.change_preset(3);
+        assert_eq!(params.get_parameter(2), 0.1);
+        assert_eq!(params.get_preset_name(3), "Quiet Fuzz");
+    }
+
+    #[test]
+    fn bank_round_trips_every_program() {
+        let saved = changed_parameters();
+        saved.change_preset(2);
+        saved.set_parameter(0, 0.3);
+        saved.set_preset_name("Edited".to_string());
+        let loaded = DistortionParameters::default();
+        loaded.load_bank_data(&saved.get_bank_data());
+        assert_same_parameters(&loaded, &saved);
+        assert_eq!(loaded.get_preset_num(), 2);
+        assert_eq!(loaded.get_preset_name(2), "Edited");
+        saved.change_preset(0);
+        loaded.change_preset(0);
+        assert_same_parameters(&loaded, &saved);
+    }
+
+    #[test]
+    fn midi_program_change_selects_a_program() {
+        let mut distortion = Distortion::with_params(HostCallback::default(), DistortionParameters::default());
+        distortion.process_midi_event([192, 2, 0]);
+        distortion.update_program();
+        assert_eq!(distortion.params.get_preset_name(distortion.params.get_preset_num()), "Crunch");
+        distortion.process_midi_event([192, 4, 0]);
+        distortion.update_program();
+        assert_eq!(distortion.params.get_preset_num(), 2);
+        distortion.process_midi_event([192 | 9, 3, 0]);
+        distortion.update_program();
+        assert_eq!(distortion.params.get_preset_num(), 3);
+    }
+
+    // a preset keeps plain values, which don't always turn back into exactly the same 0 - 1 value, so
+    // values are the same if they're shown the same
+    fn assert_same_values(loaded: &[f32], saved: &DistortionParameters) {
+        let params = DistortionParameters::default();
+        params.set_parameter_values(loaded);
+        for i in 0..PARAMETERS {
+            assert_eq!(params.get_parameter_text(i), saved.get_parameter_text(i), "parameter {}", i);
+        }
+    }
+
+    #[test]
+    fn saved_presets_join_the_bank_and_import() {
+        let dir = std::env::temp_dir().join(format!("oxidize_save_{}", std::process::id()));
+        let distortion = Distortion::default();
+        distortion.params.change_preset(2);
+        distortion.params.set_parameter(3, 0.25);
+        distortion.params.set_preset_name("Half Crunch".to_string());
+        let saved = distortion.save_preset(&dir);
+        let text = std::fs::read_to_string(dir.join("Half Crunch.toml"));
+        let programs = presets::with_library(&DistortionParameters::default(), &dir);
+        let imported = Distortion::default();
+        let import = imported.import_preset(&dir.join("Half Crunch.toml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(saved, Ok(dir.join("Half Crunch.toml")));
+        assert!(text.unwrap().contains("\"Dry/Wet\" = \"0.25\"\n"));
+        assert_eq!(programs.len(), 5);
+        assert_eq!(programs[4].name, "Half Crunch");
+        assert_same_values(&programs[4].parameters, &distortion.params);
+        assert_eq!(import, Ok(()));
+        assert_eq!(imported.params.get_preset_name(0), "Half Crunch");
+        assert_same_values(&imported.params.parameter_values(), &distortion.params);
+        assert!(imported.import_preset(&dir.join("Half Crunch.toml")).unwrap_err().starts_with("couldn't import"));
+    }
+
+    #[test]
+    fn loads_types_saved_with_the_old_mapping() {
+        // Type, and what it picked before each type got a third of the range
+        let cases = [(0.0, ShaperType::Tanh), (0.4, ShaperType::Tanh), (0.5, ShaperType::Arctan), (0.9, ShaperType::Arctan), (1.0, ShaperType::Chebyshev)];
+        for (value, expected) in cases.iter() {
+            let loaded = DistortionParameters::default();
+            loaded.load_preset_data(&State::new(0, vec![0.5, *value, 0.5, 0.5]).write());
+            assert_eq!(loaded.get_distortion_type(), *expected, "{}", value);
+            // and it's saved with the new mapping
+            assert_eq!(State::read(&loaded.get_preset_data()).unwrap().version, 1);
+        }
+        // every program in a bank, with the second one selected
+        let old = DistortionParameters::default().bank;
+        old.switch(1, &[0.5, 0.4, 0.5, 0.5]);
+        let mut state = State::new(0, vec![0.5, 0.9, 0.5, 0.5]);
+        state.add_section(program::BANK_SECTION, old.write(&state.parameters));
+        let loaded = DistortionParameters::default();
+        loaded.load_bank_data(&state.write());
+        assert_eq!(loaded.get_distortion_type(), ShaperType::Arctan);
+        loaded.change_preset(0);
+        assert_eq!(loaded.get_distortion_type(), ShaperType::Tanh);
+    }
+
+    #[test]
+    fn every_type_gets_a_third_of_the_range() {
+        let params = DistortionParameters::default();
+        for (value, expected) in [(0.0, ShaperType::Tanh), (0.3, ShaperType::Tanh), (0.4, ShaperType::Arctan), (0.6, ShaperType::Arctan), (0.7, ShaperType::Chebyshev), (1.0, ShaperType::Chebyshev)].iter() {
+            params.set_parameter(1, *value);
+            assert_eq!(params.get_distortion_type(), *expected, "{}", value);
+        }
+        assert_eq!(rs_common::properties::properties::<DistortionParameters>(1).map(|p| p.max_integer), Some(2));
+    }
+
+    #[test]
+    fn typed_values_set_parameters_in_their_units() {
+        let params = DistortionParameters::default();
+        assert!(params.string_to_parameter(0, "5.5".to_string()));
+        assert_eq!(params.get_parameter(0), 0.5);
+        assert!(params.string_to_parameter(1, "Arctan".to_string()));
+        assert_eq!(params.get_distortion_type(), ShaperType::Arctan);
+        assert!(params.string_to_parameter(1, "chebyshev 3rd order".to_string()));
+        assert_eq!(params.get_distortion_type(), ShaperType::Chebyshev);
+        assert!(!params.string_to_parameter(1, "Fold".to_string()));
+        assert!(!params.string_to_parameter(4, "1".to_string()));
+    }
+
+    #[test]
+    fn processing_uses_the_displayed_values() {
+        let distortion = Distortion::default();
+        for (coefficient, dry_wet, level) in [("1", "1", "1"), ("5.5", "1", "0.5"), ("10", "0.25", "1")].iter() {
+            assert!(distortion.params.string_to_parameter(0, coefficient.to_string()));
+            assert!(distortion.params.string_to_parameter(3, dry_wet.to_string()));
+            assert!(distortion.params.string_to_parameter(2, level.to_string()));
+            let (coefficient, dry_wet, level): (f32, f32, f32) = (coefficient.parse().unwrap(), dry_wet.parse().unwrap(), level.parse().unwrap());
+            let shaped = waveshaper::shape(distortion.params.get_distortion_type(), 0.3);
+            let expected = (0.3 * (1.0 - dry_wet) + coefficient * shaped * dry_wet) * level;
+            assert!((Shaping::new(&distortion.params).process_sample(0.3) - expected).abs() < 0.0001, "coefficient {}", coefficient);
+        }
+    }
+
+    #[test]
+    fn ignores_state_that_isnt_whole() {
+        let data = changed_parameters().get_preset_data();
+        let cases: [(&str, Vec<u8>); 5] = [
+            ("empty", vec![]),
+            ("wrong magic", [&b"VST2"[..], &data[4..]].concat()),
+            ("truncated header", data[..10].to_vec()),
+            ("truncated parameters", data[..data.len() / 2].to_vec()),
+            ("truncated name", data[..data.len() - 1].to_vec())
+        ];
+        for (case, data) in cases.iter() {
+            let loaded = DistortionParameters::default();
+            loaded.load_preset_data(data);
+            loaded.load_bank_data(data);
+            for i in 0..PARAMETERS {
+                assert_eq!(loaded.get_parameter(i), DistortionParameters::default().get_parameter(i), "{} parameter {}", case, i);
+            }
+        }
+    }
+}
\ No newline at end of file
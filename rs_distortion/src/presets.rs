@@ -0,0 +1,72 @@
+use crate::{DistortionParameters, PARAMETERS};
+
+use rs_common::preset::{self, Preset};
+use rs_common::program::Program;
+use vst::plugin::PluginParameters;
+
+use std::path::Path;
+
+// each program is the default settings with a few parameters changed, by name
+const FACTORY: [(&str, &[(&str, f32)]); 4] = [
+    ("Init", &[]),
+    ("Clean", &[
+        ("Coefficient", 0.05),
+        ("Level", 0.6),
+        ("Dry/Wet", 0.3)
+    ]),
+    ("Crunch", &[
+        ("Coefficient", 0.4),
+        ("Type", 0.6),
+        ("Level", 0.4),
+        ("Dry/Wet", 0.8)
+    ]),
+    ("Fuzz", &[
+        ("Coefficient", 1.0),
+        ("Type", 1.0),
+        ("Level", 0.25),
+        ("Dry/Wet", 1.0)
+    ])
+];
+
+pub fn names(params: &DistortionParameters) -> Vec<String> {
+    (0..PARAMETERS).map(|i| params.get_parameter_name(i)).collect()
+}
+
+pub fn factory(defaults: &DistortionParameters) -> Vec<Program> {
+    let values = defaults.parameter_values();
+    let names = names(defaults);
+    // every factory program only changes parameters the plugin has, the tests make sure of it
+    FACTORY.iter().filter_map(|(name, changes)| Program::new(name, &values, &names, changes)).collect()
+}
+
+// the factory programs followed by every preset in the user library
+pub fn with_library(defaults: &DistortionParameters, dir: &Path) -> Vec<Program> {
+    // each preset is read into a scratch copy of the defaults
+    let scratch = DistortionParameters::default();
+    let mut programs = factory(defaults);
+    programs.extend(preset::load_library(dir).iter().map(|p| Program::from_preset(p, &scratch)));
+    programs
+}
+
+// a preset file read into params, anything it doesn't set goes back to the default
+pub fn import(path: &Path, params: &DistortionParameters) -> Result<Program, String> {
+    Ok(Program::from_preset(&Preset::load(path)?, params))
+}
+
+// the current program as a user preset
+pub fn current(params: &DistortionParameters) -> Preset {
+    Preset::from_parameters(&params.get_preset_name(params.get_preset_num()), params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_factory_program_is_built() {
+        let programs = factory(&DistortionParameters::default());
+        let names: Vec<&str> = programs.iter().map(|p| p.name.as_str()).collect();
+        let expected: Vec<&str> = FACTORY.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, expected);
+    }
+}
@@ -0,0 +1,81 @@
+use vst::api::{AEffect, TimeInfo, TimeInfoFlags};
+use vst::host;
+use vst::plugin::{self, Plugin};
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::ptr;
+
+const VERSION: i32 = host::OpCode::Version as i32;
+const GET_TIME: i32 = host::OpCode::GetTime as i32;
+const GET_SAMPLE_RATE: i32 = host::OpCode::GetSampleRate as i32;
+const GET_BLOCK_SIZE: i32 = host::OpCode::GetBlockSize as i32;
+const SHUTDOWN: i32 = plugin::OpCode::Shutdown as i32;
+
+thread_local! {
+    // the callback can't carry any state of its own, and each render runs on one thread,
+    // so the transport lives here - the plugin gets a pointer to it when it asks for the time
+    static TIME: Cell<TimeInfo> = Cell::new(TimeInfo::default());
+    static BLOCK_SIZE: Cell<usize> = const { Cell::new(0) };
+}
+
+// answers the little a plugin asks of a host while it plays - everything else gets 0, which hosts use for "don't know"
+fn callback(_effect: *mut AEffect, opcode: i32, _index: i32, _value: isize, _ptr: *mut c_void, _opt: f32) -> isize {
+    match opcode {
+        VERSION => 2400,
+        GET_TIME => TIME.with(|time| time.as_ptr() as isize),
+        GET_SAMPLE_RATE => TIME.with(|time| time.get().sample_rate as isize),
+        GET_BLOCK_SIZE => BLOCK_SIZE.with(|size| size.get() as isize),
+        _ => 0
+    }
+}
+
+// a plugin made the way a host makes it, through vst's entry point, with the callback above behind it
+pub struct Instance<T> {
+    effect: *mut AEffect,
+    plugin: PhantomData<T>
+}
+
+impl<T: Plugin + Default> Instance<T> {
+    pub fn new() -> Self {
+        Instance {
+            effect: vst::main::<T>(callback),
+            plugin: PhantomData
+        }
+    }
+
+    pub fn plugin(&mut self) -> &mut Box<dyn Plugin> {
+        // the effect stays alive until this is dropped
+        unsafe { (*self.effect).get_plugin() }
+    }
+
+    // the plugin as its own type, for what a host can't ask it for
+    pub fn typed(&mut self) -> &mut T {
+        // vst::main boxed a T
+        unsafe { &mut *(self.plugin().as_mut() as *mut dyn Plugin as *mut T) }
+    }
+
+    // the transport as of the next buffer - tempo in bpm, position in samples
+    pub fn set_time(&mut self, sample_rate: f32, buffer_size: usize, tempo: f32, position: usize) {
+        let seconds = position as f64 / f64::from(sample_rate);
+        TIME.with(|time| time.set(TimeInfo {
+            sample_pos: position as f64,
+            sample_rate: f64::from(sample_rate),
+            ppq_pos: seconds * f64::from(tempo) / 60.0,
+            tempo: f64::from(tempo),
+            time_sig_numerator: 4,
+            time_sig_denominator: 4,
+            flags: (TimeInfoFlags::TRANSPORT_PLAYING | TimeInfoFlags::PPQ_POS_VALID | TimeInfoFlags::TEMPO_VALID | TimeInfoFlags::TIME_SIG_VALID).bits(),
+            ..TimeInfo::default()
+        }));
+        BLOCK_SIZE.with(|size| size.set(buffer_size));
+    }
+}
+
+impl<T> Drop for Instance<T> {
+    // the same as a host closing the plugin - vst frees the plugin and the effect
+    fn drop(&mut self) {
+        unsafe { ((*self.effect).dispatcher)(self.effect, SHUTDOWN, 0, 0, ptr::null_mut(), 0.0) };
+    }
+}
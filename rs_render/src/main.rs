@@ -0,0 +1,205 @@
+use rs_common::preset::Library;
+use rs_distortion::Distortion;
+use rs_synth::Synth;
+use vst::plugin::Plugin;
+
+use std::path::PathBuf;
+use std::process;
+
+mod host;
+mod midi;
+mod render;
+mod wav;
+
+/*
+*   Renders the plugins without a DAW - Oscicrate plays a MIDI file, Oxidize processes a WAV file,
+*   and what comes out is written to a 32 bit float WAV file. Nothing here is random between runs
+*   (the synth's voices are seeded) or read from the user's config folder (the plugins are built without
+*   their library feature), so the same input and settings always give the same file
+*/
+
+const USAGE: &str = "usage:
+    rs_render synth <input.mid> <output.wav> [options]
+    rs_render distortion <input.wav> <output.wav> [options]
+
+options:
+    --sample-rate <hz>         defaults to the input WAV's rate, or 44100 for a MIDI file
+    --buffer-size <samples>    how much is processed at a time, defaults to 512
+    --program <number>         the program to start from, counting from 0
+    --import-preset <file>     loads a preset file into the program, before anything is set
+    --set <name>=<value>       sets a parameter by name to a value in its units, e.g. --set \"Filter Cutoff=1200 Hz\"
+    --save-preset <folder>     saves the program, once everything is set, as <folder>/<name>.toml
+    --tail <seconds>           how long to carry on after the input ends, defaults to 2 for the synth and 0 for the distortion";
+
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const DEFAULT_BUFFER_SIZE: usize = 512;
+// long enough for most releases to finish
+const DEFAULT_SYNTH_TAIL: f32 = 2.0;
+
+enum Target {
+    Synth, Distortion
+}
+
+struct Options {
+    target: Target,
+    input: PathBuf,
+    output: PathBuf,
+    sample_rate: Option<u32>,
+    buffer_size: usize,
+    program: Option<i32>,
+    import_preset: Option<PathBuf>,
+    parameters: Vec<(String, String)>,
+    save_preset: Option<PathBuf>,
+    tail: Option<f32>
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(message) = parse_args(&args).and_then(|options| run(&options)) {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let (target, input, output) = match args {
+        [target, input, output, ..] => (target, input, output),
+        _ => return Err(USAGE.to_string())
+    };
+    let mut options = Options {
+        target: match target.as_str() {
+            "synth" => Target::Synth,
+            "distortion" => Target::Distortion,
+            _ => return Err(USAGE.to_string())
+        },
+        input: PathBuf::from(input),
+        output: PathBuf::from(output),
+        sample_rate: None,
+        buffer_size: DEFAULT_BUFFER_SIZE,
+        program: None,
+        import_preset: None,
+        parameters: vec![],
+        save_preset: None,
+        tail: None
+    };
+    let mut rest = args[3..].iter();
+    while let Some(option) = rest.next() {
+        let value = rest.next().ok_or_else(|| format!("{} needs a value\n\n{}", option, USAGE))?;
+        let invalid = || format!("invalid value for {}: {}", option, value);
+        match option.as_str() {
+            "--sample-rate" => options.sample_rate = Some(value.parse().ok().filter(|rate| *rate > 0).ok_or_else(invalid)?),
+            "--buffer-size" => options.buffer_size = value.parse().ok().filter(|size| *size > 0).ok_or_else(invalid)?,
+            "--program" => options.program = Some(value.parse().map_err(|_| invalid())?),
+            "--import-preset" => options.import_preset = Some(PathBuf::from(value)),
+            "--save-preset" => options.save_preset = Some(PathBuf::from(value)),
+            "--set" => {
+                let (name, text) = value.split_once('=').ok_or_else(invalid)?;
+                options.parameters.push((name.trim().to_string(), text.trim().to_string()));
+            },
+            "--tail" => options.tail = Some(value.parse().ok().filter(|tail: &f32| *tail >= 0.0).ok_or_else(invalid)?),
+            _ => return Err(format!("unknown option {}\n\n{}", option, USAGE))
+        }
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    match options.target {
+        Target::Synth => {
+            let bytes = std::fs::read(&options.input).map_err(|e| format!("can't read {}: {}", options.input.display(), e))?;
+            let sample_rate = options.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+            let song = midi::read(&bytes, sample_rate as f32)?;
+            render_with(host::Instance::<Synth>::new(), options, &song, &[], sample_rate, DEFAULT_SYNTH_TAIL)
+        },
+        Target::Distortion => {
+            let (input, file_rate) = wav::read(&options.input)?;
+            // there's no resampling, the file plays at its own rate
+            if options.sample_rate.is_some_and(|rate| rate != file_rate) {
+                return Err(format!("{} is at {} Hz, it can only be rendered at that rate", options.input.display(), file_rate));
+            }
+            render_with(host::Instance::<Distortion>::new(), options, &midi::Song::default(), &input, file_rate, 0.0)
+        }
+    }
+}
+
+fn render_with<T: Plugin + Default + Library>(mut instance: host::Instance<T>, options: &Options, song: &midi::Song, input: &[Vec<f32>], sample_rate: u32, default_tail: f32) -> Result<(), String> {
+    set_parameters(&mut instance, options)?;
+
+    let tail = options.tail.unwrap_or(default_tail);
+    let length = song.length().max(input.first().map_or(0, |channel| channel.len())) + (tail * sample_rate as f32) as usize;
+    let settings = render::Settings {
+        sample_rate: sample_rate as f32,
+        buffer_size: options.buffer_size
+    };
+    let output = render::render(&mut instance, &settings, song, input, length);
+    wav::write(&options.output, &output, sample_rate)
+}
+
+// the program first, then the preset into it, so anything set by name is changed from them - the preset
+// is saved last, so it's what gets rendered
+fn set_parameters<T: Plugin + Default + Library>(instance: &mut host::Instance<T>, options: &Options) -> Result<(), String> {
+    let plugin = instance.plugin();
+    let count = plugin.get_info().parameters;
+    let params = plugin.get_parameter_object();
+    if let Some(program) = options.program {
+        let programs = plugin.get_info().presets;
+        if !(0..programs).contains(&program) {
+            return Err(format!("there are only {} programs", programs));
+        }
+        params.change_preset(program);
+    }
+    if let Some(path) = &options.import_preset {
+        instance.typed().import_preset(path)?;
+    }
+    for (name, text) in options.parameters.iter() {
+        let index = (0..count).find(|i| params.get_parameter_name(*i).eq_ignore_ascii_case(name)).ok_or_else(|| format!("no parameter called {}", name))?;
+        if !params.string_to_parameter(index, text.clone()) {
+            return Err(format!("{} can't be set to {}", params.get_parameter_name(index), text));
+        }
+    }
+    if let Some(dir) = &options.save_preset {
+        let path = instance.typed().save_preset(dir)?;
+        println!("saved {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rs_synth::SynthParameters;
+
+    fn options(args: &[&str]) -> Options {
+        parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>()).unwrap()
+    }
+
+    #[test]
+    fn presets_are_saved_and_imported_through_the_options() {
+        let dir = std::env::temp_dir().join(format!("rs_render_presets_{}", process::id()));
+        let mut saved = host::Instance::<Synth>::new();
+        let saving = options(&["synth", "in.mid", "out.wav", "--program", "3", "--set", "Filter Cutoff=1200 Hz", "--save-preset", dir.to_str().unwrap()]);
+        let save = set_parameters(&mut saved, &saving);
+        let saved_params = saved.plugin().get_parameter_object();
+        let file = dir.join(format!("{}.toml", saved_params.get_preset_name(3)));
+        let mut imported = host::Instance::<Synth>::new();
+        let import = set_parameters(&mut imported, &options(&["synth", "in.mid", "out.wav", "--import-preset", file.to_str().unwrap()]));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(save, Ok(()));
+        assert_eq!(import, Ok(()));
+        let imported_params = imported.plugin().get_parameter_object();
+        assert_eq!(imported_params.get_preset_name(0), saved_params.get_preset_name(3));
+        for i in 0..imported.plugin().get_info().parameters {
+            assert_eq!(imported_params.get_parameter_text(i), saved_params.get_parameter_text(i), "{}", saved_params.get_parameter_name(i));
+        }
+        assert_eq!(imported_params.get_parameter_text(SynthParameters::FILTER_CUTOFF), "1200.00");
+    }
+
+    #[test]
+    fn preset_failures_stop_the_render() {
+        let missing = std::env::temp_dir().join(format!("rs_render_missing_{}.toml", process::id()));
+        let mut instance = host::Instance::<Distortion>::new();
+        let result = set_parameters(&mut instance, &options(&["distortion", "in.wav", "out.wav", "--import-preset", missing.to_str().unwrap()]));
+        assert!(result.unwrap_err().starts_with("couldn't import"));
+    }
+}
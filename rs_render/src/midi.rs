@@ -0,0 +1,139 @@
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+// 120 bpm, what a MIDI file plays at until it says otherwise
+const DEFAULT_TEMPO: u32 = 500_000;
+
+pub enum Data {
+    Midi([u8; 3]),
+    // the whole message, 0xf0 to 0xf7
+    SysEx(Vec<u8>)
+}
+
+pub struct Message {
+    // in samples from the start
+    pub position: usize,
+    pub data: Data
+}
+
+// every track's messages in the order they play, and where the tempo changes - both in samples
+#[derive(Default)]
+pub struct Song {
+    pub messages: Vec<Message>,
+    pub tempos: Vec<(usize, f32)>
+}
+
+impl Song {
+    // where the last message plays
+    pub fn length(&self) -> usize {
+        self.messages.last().map_or(0, |message| message.position)
+    }
+
+    // bpm at position
+    pub fn tempo_at(&self, position: usize) -> f32 {
+        self.tempos.iter().take_while(|(start, _)| *start <= position).last().map_or(60_000_000.0 / DEFAULT_TEMPO as f32, |(_, tempo)| *tempo)
+    }
+}
+
+pub fn read(bytes: &[u8], sample_rate: f32) -> Result<Song, String> {
+    let smf = Smf::parse(bytes).map_err(|e| format!("not a MIDI file: {}", e))?;
+
+    // all the tracks in one list by tick, a stable sort keeps each track's own order
+    let mut events = vec![];
+    for track in smf.tracks.iter() {
+        let mut tick = 0;
+        for event in track.iter() {
+            tick += u64::from(event.delta.as_int());
+            events.push((tick, event.kind));
+        }
+    }
+    events.sort_by_key(|(tick, _)| *tick);
+
+    let mut song = Song { messages: vec![], tempos: vec![] };
+    let mut tempo = DEFAULT_TEMPO;
+    let mut last_tick = 0;
+    let mut seconds = 0.0;
+    for (tick, kind) in events {
+        seconds += (tick - last_tick) as f64 * seconds_per_tick(smf.header.timing, tempo);
+        last_tick = tick;
+        let position = (seconds * f64::from(sample_rate)).round() as usize;
+        let data = match kind {
+            TrackEventKind::Midi { channel, message } => Data::Midi(midi_bytes(channel.as_int(), message)),
+            // a file leaves the 0xf0 off
+            TrackEventKind::SysEx(payload) => Data::SysEx([&[0xf0], payload].concat()),
+            TrackEventKind::Meta(MetaMessage::Tempo(microseconds)) => {
+                tempo = microseconds.as_int();
+                song.tempos.push((position, 60_000_000.0 / tempo as f32));
+                continue;
+            },
+            _ => continue
+        };
+        song.messages.push(Message { position, data });
+    }
+    Ok(song)
+}
+
+fn seconds_per_tick(timing: Timing, tempo: u32) -> f64 {
+    match timing {
+        Timing::Metrical(ticks_per_beat) => f64::from(tempo) / 1_000_000.0 / f64::from(ticks_per_beat.as_int()),
+        Timing::Timecode(fps, ticks_per_frame) => 1.0 / (f64::from(fps.as_f32()) * f64::from(ticks_per_frame))
+    }
+}
+
+// the three bytes a host would send, a note on with no velocity is sent as the note off it means
+fn midi_bytes(channel: u8, message: MidiMessage) -> [u8; 3] {
+    match message {
+        MidiMessage::NoteOn { key, vel } if vel == 0 => [0x80 | channel, key.as_int(), 0],
+        MidiMessage::NoteOff { key, vel } => [0x80 | channel, key.as_int(), vel.as_int()],
+        MidiMessage::NoteOn { key, vel } => [0x90 | channel, key.as_int(), vel.as_int()],
+        MidiMessage::Aftertouch { key, vel } => [0xa0 | channel, key.as_int(), vel.as_int()],
+        MidiMessage::Controller { controller, value } => [0xb0 | channel, controller.as_int(), value.as_int()],
+        MidiMessage::ProgramChange { program } => [0xc0 | channel, program.as_int(), 0],
+        MidiMessage::ChannelAftertouch { vel } => [0xd0 | channel, vel.as_int(), 0],
+        MidiMessage::PitchBend { bend } => {
+            let value = bend.0.as_int();
+            [0xe0 | channel, (value & 0x7f) as u8, (value >> 7) as u8]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::{Format, Header, TrackEvent};
+    use midly::num::{u4, u7, u15, u24, u28};
+
+    fn event(delta: u32, kind: TrackEventKind) -> TrackEvent {
+        TrackEvent { delta: u28::new(delta), kind }
+    }
+
+    fn note(on: bool, key: u8) -> TrackEventKind<'static> {
+        let (key, vel) = (u7::new(key), u7::new(if on { 100 } else { 0 }));
+        TrackEventKind::Midi { channel: u4::new(0), message: MidiMessage::NoteOn { key, vel } }
+    }
+
+    #[test]
+    fn ticks_become_samples_through_the_tempo_changes() {
+        // a beat at 120 bpm, then a beat at 60 bpm, 96 ticks a beat
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(u15::new(96))),
+            tracks: vec![vec![
+                event(0, note(true, 60)),
+                event(96, TrackEventKind::Meta(MetaMessage::Tempo(u24::new(1_000_000)))),
+                event(0, note(false, 60)),
+                event(96, note(true, 62)),
+                event(0, TrackEventKind::Meta(MetaMessage::EndOfTrack))
+            ]]
+        };
+        let mut bytes = vec![];
+        smf.write_std(&mut bytes).unwrap();
+
+        let song = read(&bytes, 48000.0).unwrap();
+        let positions: Vec<usize> = song.messages.iter().map(|m| m.position).collect();
+        assert_eq!(positions, [0, 24000, 72000]);
+        assert!(matches!(song.messages[1].data, Data::Midi([0x80, 60, 0])));
+        assert_eq!(song.length(), 72000);
+        assert_eq!(song.tempo_at(0), 120.0);
+        assert_eq!(song.tempo_at(30000), 60.0);
+        assert!(read(b"MThd", 48000.0).is_err());
+    }
+}
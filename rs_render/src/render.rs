@@ -0,0 +1,136 @@
+use crate::host::Instance;
+use crate::midi::{Data, Song};
+
+use vst::buffer::SendEventBuffer;
+use vst::event::{Event, MidiEvent, SysExEvent};
+use vst::host::HostBuffer;
+use vst::plugin::Plugin;
+
+pub struct Settings {
+    pub sample_rate: f32,
+    pub buffer_size: usize
+}
+
+// runs length samples through the plugin a buffer at a time, the way a host would - the song's messages go in
+// at the start of the buffer they fall in, with how far into it they are, and the input is spread over the
+// plugin's inputs (a mono file goes to all of them). returns each of the plugin's outputs
+pub fn render<T: Plugin + Default>(instance: &mut Instance<T>, settings: &Settings, song: &Song, input: &[Vec<f32>], length: usize) -> Vec<Vec<f32>> {
+    let info = instance.plugin().get_info();
+    let (inputs, outputs) = (info.inputs as usize, info.outputs as usize);
+    {
+        let plugin = instance.plugin();
+        plugin.set_sample_rate(settings.sample_rate);
+        plugin.set_block_size(settings.buffer_size as i64);
+        plugin.resume();
+    }
+
+    let mut host_buffer: HostBuffer<f32> = HostBuffer::new(inputs, outputs);
+    let mut events = SendEventBuffer::new(song.messages.len().max(1));
+    let mut rendered = vec![Vec::with_capacity(length); outputs];
+    let mut next_message = 0;
+    let mut start = 0;
+    while start < length {
+        let samples = settings.buffer_size.min(length - start);
+        instance.set_time(settings.sample_rate, settings.buffer_size, song.tempo_at(start), start);
+
+        let due = song.messages[next_message..].iter().take_while(|message| message.position < start + samples).count();
+        let messages = &song.messages[next_message..next_message + due];
+        next_message += due;
+        let buffer_events: Vec<Event> = messages.iter().map(|message| {
+            let delta_frames = (message.position - start) as i32;
+            match &message.data {
+                Data::Midi(data) => Event::Midi(MidiEvent {
+                    data: *data,
+                    delta_frames,
+                    live: false,
+                    note_length: None,
+                    note_offset: None,
+                    detune: 0,
+                    note_off_velocity: 0
+                }),
+                Data::SysEx(payload) => Event::SysEx(SysExEvent { payload, delta_frames })
+            }
+        }).collect();
+        events.send_events_to_plugin(&buffer_events, instance.plugin().as_mut());
+
+        // silence once the input runs out
+        let input_buffers: Vec<Vec<f32>> = (0..inputs).map(|i| {
+            let channel = input.get(i % input.len().max(1)).map_or(&[][..], |channel| &channel[..]);
+            (start..start + samples).map(|n| channel.get(n).copied().unwrap_or(0.0)).collect()
+        }).collect();
+        let mut output_buffers = vec![vec![0.0; samples]; outputs];
+        let mut buffer = host_buffer.bind(&input_buffers, &mut output_buffers);
+        instance.plugin().process(&mut buffer);
+
+        for (to, from) in rendered.iter_mut().zip(output_buffers) {
+            to.extend(from);
+        }
+        start += samples;
+    }
+
+    instance.plugin().suspend();
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::Message;
+
+    use rs_distortion::Distortion;
+    use rs_synth::{Synth, SynthParameters};
+
+    const SETTINGS: Settings = Settings { sample_rate: 44100.0, buffer_size: 100 };
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn the_synth_plays_the_song_the_same_every_time() {
+        let song = Song {
+            messages: vec![
+                Message { position: 150, data: Data::Midi([0x90, 60, 100]) },
+                Message { position: 4410, data: Data::Midi([0x80, 60, 0]) }
+            ],
+            tempos: vec![]
+        };
+        let first = render(&mut Instance::<Synth>::new(), &SETTINGS, &song, &[], 8820);
+        assert_eq!(first.len(), 2);
+        assert!(first.iter().all(|channel| channel.len() == 8820));
+        // nothing before the note's own frame, halfway through the second buffer
+        assert_eq!(peak(&first[0][..150]), 0.0);
+        assert!(peak(&first[0][150..200]) > 0.0);
+        assert!(peak(&first[0][200..4410]) > 0.01);
+        assert_eq!(render(&mut Instance::<Synth>::new(), &SETTINGS, &song, &[], 8820), first);
+    }
+
+    #[test]
+    fn renders_leave_out_the_user_library() {
+        // the synth is built without its library feature here, so whatever is in the config folder it only has
+        // the factory programs and 12-TET
+        let mut instance = Instance::<Synth>::new();
+        let mut factory = Synth::default();
+        let (params, factory_params) = (instance.plugin().get_parameter_object(), factory.get_parameter_object());
+        let programs = instance.plugin().get_info().presets;
+        assert_eq!(programs, factory.get_info().presets);
+        for program in 0..programs {
+            assert_eq!(params.get_preset_name(program), factory_params.get_preset_name(program));
+        }
+        // the last tuning is the first
+        params.set_parameter(SynthParameters::TUNING, 1.0);
+        assert_eq!(params.get_parameter_text(SynthParameters::TUNING), "12-TET");
+    }
+
+    #[test]
+    fn the_distortion_processes_the_input_on_every_channel() {
+        let sine: Vec<f32> = (0..1000).map(|n| (n as f32 * 0.05).sin() * 0.5).collect();
+        let output = render(&mut Instance::<Distortion>::new(), &SETTINGS, &Song::default(), std::slice::from_ref(&sine), 1050);
+        assert_eq!(output[0], output[1]);
+        assert_eq!(output[0].len(), 1050);
+        assert!(peak(&output[0][..1000]) > 0.1);
+        assert_ne!(&output[0][..1000], &sine[..]);
+        // past the end of the input it's fed silence
+        assert_eq!(peak(&output[0][1000..]), 0.0);
+    }
+}
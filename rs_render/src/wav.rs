@@ -0,0 +1,39 @@
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+use std::path::Path;
+
+// each channel's samples as -1 - 1, and the sample rate
+pub fn read(path: &Path) -> Result<(Vec<Vec<f32>>, u32), String> {
+    let error = |e: hound::Error| format!("can't read {}: {}", path.display(), e);
+    let mut reader = WavReader::open(path).map_err(error)?;
+    let spec = reader.spec();
+    let samples: Result<Vec<f32>, _> = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect(),
+        SampleFormat::Int => {
+            let full_scale = (1u32 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|sample| sample.map(|s| s as f32 / full_scale)).collect()
+        }
+    };
+    let samples = samples.map_err(error)?;
+    let channels = usize::from(spec.channels);
+    let deinterleaved = (0..channels).map(|c| samples.iter().skip(c).step_by(channels).copied().collect()).collect();
+    Ok((deinterleaved, spec.sample_rate))
+}
+
+// 32 bit float, so nothing the plugin puts out is clipped or rounded
+pub fn write(path: &Path, channels: &[Vec<f32>], sample_rate: u32) -> Result<(), String> {
+    let error = |e: hound::Error| format!("can't write {}: {}", path.display(), e);
+    let spec = WavSpec {
+        channels: channels.len() as u16,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float
+    };
+    let mut writer = WavWriter::create(path, spec).map_err(error)?;
+    for i in 0..channels.first().map_or(0, |channel| channel.len()) {
+        for channel in channels {
+            writer.write_sample(channel[i]).map_err(error)?;
+        }
+    }
+    writer.finalize().map_err(error)
+}
@@ -1,54 +1,108 @@
-// 100% knicked from https://github.com/BelaPlatform/Bela/tree/master/libraries/ADSR
+use crate::context::Context;
 
-fn calculate_coefficient(rate: f32, ratio: f32) -> f32 {
-    let log = -((1.0 + ratio) / ratio).log(std::f32::consts::E) / rate;        
-    log.exp()
+// delay, attack, hold, decay, sustain, release - delay and hold default to 0 so it's a plain ADSR unless they're used
+// every stage is a ramp of a fixed length from wherever the output was when the stage started, bent by that stage's curve
+
+// how steep a curve of 1 or -1 is - at 10 an exponential stage is 99% of the way there half way through
+const MAX_CURVE: f32 = 10.0;
+// the closest the old one pole curves were to the new ones, so patches sound the same by default
+pub const DEFAULT_ATTACK_CURVE: f32 = 0.15;
+pub const DEFAULT_DR_CURVE: f32 = 0.92;
+// key follow is centred on middle C, at 100% decay and release halve every octave up
+const KEY_FOLLOW_REFERENCE: f32 = 60.0;
+// long enough not to click, short enough not to be heard as a gap
+const RESET_FADE_TIME: f32 = 0.003;
+// how long the sustain level takes to follow a jump of the whole 0 - 1 range, so moving the knob never clicks
+const SUSTAIN_SLEW_TIME: f32 = 0.005;
+
+// progress is 0 - 1 through a stage, returns 0 - 1 of the way to the stage's target
+// curve is -1 - 1: 0 is a straight line, above 0 is exponential (fast then slowing down, like an analog
+// envelope charging a capacitor), below 0 is logarithmic (slow then speeding up)
+pub fn shape(progress: f32, curve: f32) -> f32 {
+    let k = curve * MAX_CURVE;
+    if k.abs() < 0.001 {
+        progress
+    }
+    else {
+        (1.0 - (-k * progress).exp()) / (1.0 - (-k).exp())
+    }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone)]
 pub struct ADSR {
+    // stage times are in seconds
+    delay: f32,
     attack: f32,
+    hold: f32,
     decay: f32,
     sustain: f32,
-    release: f32,    
-    attack_coeff: f32,
-    decay_coeff: f32,
-    release_coeff: f32,
-    attack_target_ratio: f32,
-    dr_target_ratio: f32,    
-    attack_base: f32,
-    decay_base: f32,
-    release_base: f32,
+    // follows sustain at most 1 / SUSTAIN_SLEW_TIME per second
+    sustain_level: f32,
+    release: f32,
+    attack_curve: f32,
+    decay_curve: f32,
+    release_curve: f32,
+    // 0 - 1
+    velocity_to_attack: f32,
+    velocity_to_amount: f32,
+    key_follow: f32,
+    // from the velocity and pitch of the note being played
+    attack_scale: f32,
+    decay_scale: f32,
+    amount: f32,
+    retrigger: Retrigger,
     state: ADSRState,
-    sample_rate: f32,
+    // seconds into the current stage, and the level when it started
+    stage_time: f32,
+    stage_start: f32,
+    context: Context,
+    // level runs 0 - 1 through the stages, output is level scaled by the velocity amount
+    level: f32,
     output: f32
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 enum ADSRState {
-    Idle, Attack, Decay, Sustain, Release
+    // Fade is the quick drop to 0 before a reset note starts
+    Idle, Fade, Delay, Attack, Hold, Decay, Sustain, Release
+}
+
+// what a new note does if the envelope hasn't finished the last one
+#[derive(PartialEq, Clone)]
+pub enum Retrigger {
+    // the attack starts from wherever the envelope is, so it never jumps - it still takes the whole attack
+    // time to get from there to the top
+    FromCurrent,
+    // fade to 0 over RESET_FADE_TIME then start the attack from 0, so every note gets the whole attack without a click
+    Reset
 }
 
 impl Default for ADSR {
     fn default() -> ADSR {
-        let attack_coeff = calculate_coefficient(0.2, 0.3);
-        let decay_coeff = calculate_coefficient(0.2, 0.0001);
-        let release_coeff = calculate_coefficient(0.5, 0.0001);
         ADSR {
+            delay: 0.0,
             attack: 0.2,
+            hold: 0.0,
             decay: 0.1,
             sustain: 0.8,
-            release: 0.5,                        
-            attack_coeff: attack_coeff,
-            decay_coeff: decay_coeff,
-            release_coeff: release_coeff,
-            attack_target_ratio: 0.3,
-            dr_target_ratio: 0.0001,
-            attack_base: (1.0 + 0.3) * (1.0 - attack_coeff),
-            decay_base: (1.0 - 0.0001) * (1.0 - decay_coeff),
-            release_base: -0.0001 * (1.0 - release_coeff),
+            sustain_level: 0.8,
+            release: 0.5,
+            attack_curve: DEFAULT_ATTACK_CURVE,
+            decay_curve: DEFAULT_DR_CURVE,
+            release_curve: DEFAULT_DR_CURVE,
+            velocity_to_attack: 0.0,
+            velocity_to_amount: 0.0,
+            key_follow: 0.0,
+            attack_scale: 1.0,
+            decay_scale: 1.0,
+            amount: 1.0,
+            retrigger: Retrigger::FromCurrent,
             state: ADSRState::Idle,
-            sample_rate: 44100.0,
+            stage_time: 0.0,
+            stage_start: 0.0,
+            context: Context::default(),
+            level: 0.0,
             output: 0.0
         }
     }
@@ -57,100 +111,249 @@ impl Default for ADSR {
 impl ADSR {
 
     pub fn start_note(&mut self) {
-        self.state = ADSRState::Attack;
+        if self.retrigger == Retrigger::Reset && self.level > 0.0 {
+            self.start_stage(ADSRState::Fade);
+        }
+        else {
+            self.start_stage(ADSRState::Delay);
+        }
     }
 
-    pub fn end_note(&mut self) {         
+    pub fn end_note(&mut self) {
         if self.state != ADSRState::Idle {
-            self.state = ADSRState::Release;
+            self.start_stage(ADSRState::Release);
         }
     }
 
-    pub fn reset(&mut self) {
-        self.state = ADSRState::Idle;
-        self.output = 0.0;
+    // call before start_note, pitch is the tuned note number and velocity is 0 - 1
+    pub fn set_note(&mut self, pitch: f32, velocity: f32) {
+        self.attack_scale = 1.0 - (self.velocity_to_attack * velocity);
+        self.amount = 1.0 - (self.velocity_to_amount * (1.0 - velocity));
+        self.decay_scale = (-self.key_follow * (pitch - KEY_FOLLOW_REFERENCE) / 12.0).exp2();
     }
 
     pub fn get_output(&self) -> f32 {
         self.output
     }
 
+    // times in seconds, sustain 0 - 1
     pub fn set_params(&mut self, attack: f32, decay: f32, sustain: f32, release: f32) {
-        self.calculate_attack(attack * self.sample_rate);
-        self.calculate_decay(decay * self.sample_rate);           
-        self.calculate_sustain(sustain);     
-        self.calculate_release(release * self.sample_rate);        
-    }   
+        self.attack = attack;
+        self.decay = decay;
+        self.sustain = sustain;
+        self.release = release;
+        // nothing's playing, so nothing to click
+        if self.state == ADSRState::Idle {
+            self.sustain_level = sustain;
+        }
+    }
+
+    // in seconds, delay holds the output where it was before the attack starts and hold keeps it at the top afterwards
+    pub fn set_delay_hold(&mut self, delay: f32, hold: f32) {
+        self.delay = delay;
+        self.hold = hold;
+    }
+
+    // -1 - 1, see shape()
+    pub fn set_curves(&mut self, attack: f32, decay: f32, release: f32) {
+        self.attack_curve = attack;
+        self.decay_curve = decay;
+        self.release_curve = release;
+    }
+
+    // all 0 - 1, picked up on the next note
+    // velocity_to_attack - harder notes attack faster, at 1 a full velocity note has no attack
+    // velocity_to_amount - softer notes get less of the envelope, at 1 a velocity of 0 gets none of it
+    // key_follow - higher notes decay and release faster
+    pub fn set_scaling(&mut self, velocity_to_attack: f32, velocity_to_amount: f32, key_follow: f32) {
+        self.velocity_to_attack = velocity_to_attack;
+        self.velocity_to_amount = velocity_to_amount;
+        self.key_follow = key_follow;
+    }
 
-    pub fn set_sample_rate(&mut self, sr: f32) {
-        self.sample_rate = sr;
+    pub fn set_retrigger(&mut self, retrigger: Retrigger) {
+        self.retrigger = retrigger;
+    }
+
+    pub fn set_context(&mut self, context: Context) {
+        self.context = context;
     }
 
     pub fn process(&mut self) {
-        match self.state {
-            ADSRState::Idle => (),
-            ADSRState::Attack => {
-                self.output = self.attack_base + self.output * self.attack_coeff;
-                if self.output >= 1.0 {
-                    self.output = 1.0;
-                    self.state = ADSRState::Decay;
-                }
-            },
-            ADSRState::Decay => {
-                self.output = self.decay_base + self.output * self.decay_coeff;
-                if self.output <= self.sustain {
-                    self.output = self.sustain;
-                    self.state = ADSRState::Sustain;
-                }
-            },
-            ADSRState::Sustain => (),
-            ADSRState::Release => {
-                self.output = self.release_base + self.output * self.release_coeff;
-                if self.output <= 0.0 {
-                    self.output = 0.0;
-                    self.state = ADSRState::Idle;
+        let step = self.context.sample_period() / SUSTAIN_SLEW_TIME;
+        self.sustain_level += (self.sustain - self.sustain_level).clamp(-step, step);
+        self.process_level();
+        self.output = self.level * self.amount;
+    }
+
+    fn process_level(&mut self) {
+        self.stage_time += self.context.sample_period();
+        // stages with no length are skipped within the same sample
+        loop {
+            let (length, target, curve) = match self.state {
+                ADSRState::Idle => return,
+                ADSRState::Fade => (RESET_FADE_TIME, 0.0, 0.0),
+                ADSRState::Delay => (self.delay, self.stage_start, 0.0),
+                ADSRState::Attack => (self.attack * self.attack_scale, 1.0, self.attack_curve),
+                ADSRState::Hold => (self.hold, 1.0, 0.0),
+                // the decay heads for the sustain level as it is now, so it follows the knob mid-decay
+                ADSRState::Decay => (self.decay * self.decay_scale, self.sustain_level, self.decay_curve),
+                ADSRState::Sustain => {
+                    self.level = self.sustain_level;
+                    return;
                 }
+                ADSRState::Release => (self.release * self.decay_scale, 0.0, self.release_curve)
+            };
+            if self.stage_time < length {
+                self.level = self.stage_start + ((target - self.stage_start) * shape(self.stage_time / length, curve));
+                return;
             }
-        }        
+            self.level = target;
+            // carry the time left over into the next stage
+            self.stage_time -= length;
+            self.stage_start = target;
+            self.state = match self.state {
+                ADSRState::Fade => ADSRState::Delay,
+                ADSRState::Delay => ADSRState::Attack,
+                ADSRState::Attack => ADSRState::Hold,
+                ADSRState::Hold => ADSRState::Decay,
+                ADSRState::Decay => ADSRState::Sustain,
+                _ => ADSRState::Idle
+            };
+        }
     }
-    
-    fn calculate_attack(&mut self, attack: f32) {
-        self.attack = attack;
-        self.attack_coeff = calculate_coefficient(self.attack, self.attack_target_ratio);
-        self.attack_base = (1.0 + self.attack_target_ratio) * (1.0 - self.attack_coeff);
+
+    fn start_stage(&mut self, state: ADSRState) {
+        self.state = state;
+        self.stage_time = 0.0;
+        self.stage_start = self.level;
     }
+}
 
-    fn calculate_decay(&mut self, decay: f32) {
-        self.decay = decay;
-        self.decay_coeff = calculate_coefficient(self.decay, self.dr_target_ratio);
-        self.decay_base = (self.sustain - self.dr_target_ratio) * (1.0 - self.decay_coeff);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 10000.0;
+
+    fn envelope(attack: f32, decay: f32, sustain: f32, release: f32) -> ADSR {
+        let mut envelope = ADSR::default();
+        envelope.set_context(Context::new(SAMPLE_RATE));
+        envelope.set_params(attack, decay, sustain, release);
+        envelope.set_curves(0.0, 0.0, 0.0);
+        envelope
     }
 
-    fn calculate_sustain(&mut self, sustain: f32) {
-        self.sustain = sustain;
-        self.decay_base = (self.sustain - self.dr_target_ratio) * (1.0 - self.decay_coeff);
+    // runs for a number of seconds, returns the biggest change between two samples
+    fn run(envelope: &mut ADSR, seconds: f32) -> f32 {
+        let mut biggest_step = 0.0f32;
+        for _ in 0..(seconds * SAMPLE_RATE).round() as usize {
+            let previous = envelope.get_output();
+            envelope.process();
+            biggest_step = biggest_step.max((envelope.get_output() - previous).abs());
+        }
+        biggest_step
     }
 
-    fn calculate_release(&mut self, release: f32) {
-        self.release = release;
-        self.release_coeff = calculate_coefficient(self.release, self.dr_target_ratio);
-        self.release_base = -self.dr_target_ratio * (1.0 - self.release_coeff);
-    }    
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "expected {}, got {}", expected, actual);
+    }
 
-    fn set_attack_target_ratio(&mut self, mut ratio: f32) {
-        if ratio < 0.000000001 {
-            ratio = 0.000000001;
-        }
-        self.attack_target_ratio = ratio;
-        self.attack_base = (1.0 + self.attack_target_ratio) * (1.0 - self.attack_coeff);
+    #[test]
+    fn stages_run_in_order() {
+        let mut envelope = envelope(0.1, 0.1, 0.5, 0.1);
+        envelope.set_delay_hold(0.05, 0.05);
+        envelope.start_note();
+        run(&mut envelope, 0.025);
+        assert_eq!(envelope.state, ADSRState::Delay);
+        assert_near(envelope.get_output(), 0.0);
+        run(&mut envelope, 0.075);
+        assert_eq!(envelope.state, ADSRState::Attack);
+        assert_near(envelope.get_output(), 0.5);
+        run(&mut envelope, 0.075);
+        assert_eq!(envelope.state, ADSRState::Hold);
+        assert_near(envelope.get_output(), 1.0);
+        run(&mut envelope, 0.075);
+        assert_eq!(envelope.state, ADSRState::Decay);
+        assert_near(envelope.get_output(), 0.75);
+        run(&mut envelope, 0.1);
+        assert_eq!(envelope.state, ADSRState::Sustain);
+        assert_near(envelope.get_output(), 0.5);
+        envelope.end_note();
+        run(&mut envelope, 0.05);
+        assert_eq!(envelope.state, ADSRState::Release);
+        assert_near(envelope.get_output(), 0.25);
+        run(&mut envelope, 0.1);
+        assert_eq!(envelope.state, ADSRState::Idle);
+        assert_near(envelope.get_output(), 0.0);
     }
 
-    fn set_dr_target_ratio(&mut self, mut ratio: f32) {
-        if ratio < 0.000000001 {
-            ratio = 0.000000001;
-        }
-        self.dr_target_ratio = ratio;
-        self.decay_base = (self.sustain - self.dr_target_ratio) * (1.0 - self.decay_coeff);
-        self.release_base = -self.dr_target_ratio * (1.0 - self.release_coeff);
+    #[test]
+    fn zero_length_stages_are_skipped() {
+        let mut envelope = envelope(0.0, 0.0, 0.7, 0.0);
+        envelope.start_note();
+        envelope.process();
+        assert_eq!(envelope.state, ADSRState::Sustain);
+        assert_near(envelope.get_output(), 0.7);
+        envelope.end_note();
+        envelope.process();
+        assert_eq!(envelope.state, ADSRState::Idle);
+    }
+
+    #[test]
+    fn sustain_changes_mid_decay() {
+        let mut envelope = envelope(0.0, 0.2, 0.2, 0.1);
+        envelope.start_note();
+        run(&mut envelope, 0.1);
+        // half way from 1 to 0.2, then the sustain goes up above where the decay has got to
+        assert_near(envelope.get_output(), 0.6);
+        envelope.set_params(0.0, 0.2, 0.8, 0.1);
+        let step = run(&mut envelope, 0.1);
+        assert!(step < 0.05, "jumped by {}", step);
+        assert_eq!(envelope.state, ADSRState::Sustain);
+        assert_near(envelope.get_output(), 0.8);
+    }
+
+    #[test]
+    fn sustain_changes_while_sustaining() {
+        let mut envelope = envelope(0.0, 0.0, 1.0, 0.1);
+        envelope.start_note();
+        run(&mut envelope, 0.01);
+        envelope.set_params(0.0, 0.0, 0.0, 0.1);
+        let step = run(&mut envelope, 0.1);
+        assert!(step <= 1.0 / (SUSTAIN_SLEW_TIME * SAMPLE_RATE) + 1e-6, "jumped by {}", step);
+        assert_near(envelope.get_output(), 0.0);
+        assert_eq!(envelope.state, ADSRState::Sustain);
+    }
+
+    #[test]
+    fn retrigger_from_current_level() {
+        let mut envelope = envelope(0.1, 0.1, 0.5, 0.2);
+        envelope.start_note();
+        run(&mut envelope, 0.3);
+        envelope.end_note();
+        run(&mut envelope, 0.1);
+        assert_near(envelope.get_output(), 0.25);
+        // the new attack carries on up from 0.25 and still takes the whole attack time
+        envelope.start_note();
+        let step = run(&mut envelope, 0.05);
+        assert!(step < 0.01, "jumped by {}", step);
+        assert_near(envelope.get_output(), 0.625);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reset_fades_out_before_the_attack() {
+        let mut envelope = envelope(0.1, 0.1, 1.0, 0.2);
+        envelope.set_retrigger(Retrigger::Reset);
+        envelope.start_note();
+        run(&mut envelope, 0.2);
+        envelope.start_note();
+        assert_eq!(envelope.state, ADSRState::Fade);
+        let step = run(&mut envelope, RESET_FADE_TIME);
+        assert!(step < 0.5, "jumped by {}", step);
+        assert_eq!(envelope.state, ADSRState::Attack);
+        assert_near(envelope.get_output(), 0.0);
+        run(&mut envelope, 0.1);
+        assert_near(envelope.get_output(), 1.0);
+    }
+}
@@ -0,0 +1,34 @@
+pub const DEFAULT_SAMPLE_RATE: f32 = 44100.0;
+
+// what every time based part of the synth needs to know about the stream it's running in
+// the synth owns the real one and hands copies down whenever it changes, so no part can be left at the wrong rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Context {
+    pub sample_rate: f32
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Context::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl Context {
+    pub fn new(sample_rate: f32) -> Self {
+        Context { sample_rate }
+    }
+
+    // seconds per sample
+    pub fn sample_period(&self) -> f32 {
+        1.0 / self.sample_rate
+    }
+
+    // cycles per sample of something running at freq Hz
+    pub fn phase_increment(&self, freq: f32) -> f32 {
+        freq / self.sample_rate
+    }
+
+    pub fn nyquist(&self) -> f32 {
+        self.sample_rate * 0.5
+    }
+}
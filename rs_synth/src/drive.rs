@@ -0,0 +1,116 @@
+use rs_common::waveshaper::{self, ShaperType};
+
+// full drive pushes the signal this far into the shaper
+pub const MAX_DRIVE_DB: f32 = 36.0;
+
+// the first few dB of drive fade from the dry signal into the shaped one, so turning it up from 0 doesn't jump
+const FADE_DB: f32 = 6.0;
+
+// how much of T3 goes on top of the clipped signal - below a quarter, so quiet signals never come out inverted
+const CHEBYSHEV_DEPTH: f32 = 1.0 / 6.0;
+
+// gain into the shaper, then back out by what the shaper makes of a full-scale signal, so a full-scale signal
+// comes out at full scale whichever shaper it is and more drive only squashes it harder - quieter signals come
+// up with the drive, the way they do pushing any real stage harder
+#[derive(Clone)]
+pub struct Drive {
+    shaper: ShaperType,
+    gain: f32,
+    makeup: f32,
+    wet: f32
+}
+
+impl Default for Drive {
+    fn default() -> Self {
+        Drive {
+            shaper: ShaperType::Tanh,
+            gain: 1.0,
+            makeup: 1.0,
+            wet: 0.0
+        }
+    }
+}
+
+impl Drive {
+    // amount is in dB, 0 bypasses the stage entirely
+    pub fn set_params(&mut self, shaper: ShaperType, amount: f32) {
+        self.shaper = shaper;
+        self.gain = 10.0f32.powf(amount / 20.0);
+        self.makeup = 1.0 / self.curve(self.gain);
+        self.wet = (amount / FADE_DB).clamp(0.0, 1.0);
+    }
+
+    pub fn process(&self, input: f32) -> f32 {
+        if self.wet <= 0.0 {
+            return input;
+        }
+        let shaped = self.curve(input * self.gain) * self.makeup;
+        input + (shaped - input) * self.wet
+    }
+
+    // T3 goes negative through 0, so it can't be driven like the others - the signal is clipped instead and T3
+    // adds its third harmonic on top, which keeps the curve rising all the way from -1 to 1
+    fn curve(&self, sample: f32) -> f32 {
+        match self.shaper {
+            ShaperType::Chebyshev => {
+                let clipped = sample.clamp(-1.0, 1.0);
+                clipped + (waveshaper::shape(ShaperType::Chebyshev, clipped) - clipped) * CHEBYSHEV_DEPTH
+            }
+            shaper => waveshaper::shape(shaper, sample)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rs_common::parameter::Choice;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn full_scale_signals_keep_their_level() {
+        // a whole number of cycles of a full-scale sine
+        let sine: Vec<f32> = (0..1000).map(|n| (n as f32 * std::f32::consts::PI / 50.0).sin()).collect();
+        for shaper in ShaperType::ALL.iter() {
+            for amount in [1.0, MAX_DRIVE_DB / 2.0, MAX_DRIVE_DB].iter() {
+                let mut drive = Drive::default();
+                drive.set_params(*shaper, *amount);
+                let output: Vec<f32> = sine.iter().map(|s| drive.process(*s)).collect();
+                let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+                let change = 20.0 * (rms(&output) / rms(&sine)).log10();
+                assert!((peak - 1.0).abs() < 0.001, "{:?} at {} dB peaks at {}", shaper, amount, peak);
+                // squashing a sine towards a square puts up to 3 dB on it, and nothing should lose much
+                assert!((-3.0..=3.1).contains(&change), "{:?} at {} dB changed the level by {} dB", shaper, amount, change);
+            }
+        }
+    }
+
+    #[test]
+    fn no_drive_leaves_the_signal_alone() {
+        for shaper in ShaperType::ALL.iter() {
+            let mut drive = Drive::default();
+            drive.set_params(*shaper, 0.0);
+            assert_eq!(drive.process(0.5), 0.5);
+        }
+    }
+
+    #[test]
+    fn quiet_signals_keep_their_polarity_and_come_up_smoothly() {
+        // -40 dBFS
+        let sine: Vec<f32> = (0..1000).map(|n| 0.01 * (n as f32 * std::f32::consts::PI / 50.0).sin()).collect();
+        for shaper in ShaperType::ALL.iter() {
+            for (amount, most) in [(0.0, 0.0), (1.0, 1.0), (MAX_DRIVE_DB, MAX_DRIVE_DB)].iter() {
+                let mut drive = Drive::default();
+                drive.set_params(*shaper, *amount);
+                let output: Vec<f32> = sine.iter().map(|s| drive.process(*s)).collect();
+                let correlation: f32 = sine.iter().zip(output.iter()).map(|(i, o)| i * o).sum();
+                let change = 20.0 * (rms(&output) / rms(&sine)).log10();
+                assert!(correlation > 0.0, "{:?} at {} dB flipped a quiet signal", shaper, amount);
+                assert!(change.abs() <= most + 0.001, "{:?} at {} dB changed a quiet signal by {} dB", shaper, amount, change);
+            }
+        }
+    }
+}
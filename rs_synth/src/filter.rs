@@ -1,14 +1,33 @@
-use crate::adsr;
+use crate::context::{self, Context};
+
+use rs_common::parameter::Choice;
+
+// key tracking is centered on middle C, so the cutoff knob is exact for that note
+const KEY_TRACK_REFERENCE: u8 = 60;
+// how far down the cutoff can be pulled by a soft note at full velocity amount
+const VELOCITY_RANGE_OCTAVES: f32 = 4.0;
+// the envelope amount knob sweeps this many octaves either side of the base cutoff
+pub const ENV_AMOUNT_OCTAVES: f32 = 8.0;
+const MIN_CUTOFF: f32 = 20.0;
+// keep well clear of nyquist, tan() blows up as the cutoff approaches it
+const MAX_CUTOFF_RATIO: f32 = 0.45;
 
 #[derive(Clone)]
-pub struct Filter {    
-    sample_rate: f32,
+pub struct Filter {
+    context: Context,
     y: [f32; 3],
     s1: f32,
     s2: f32,
+    // tuned pitch of the note, so key tracking follows microtunings
+    note: f32,
+    velocity: f32,
+    cutoff: f32,
+    resonance: f32,
+    key_track: f32,
+    velocity_amount: f32,
+    env_amount: f32,
     filter_state: FilterState,
-    filter_type: FilterType,
-    envelope: adsr::ADSR
+    filter_type: FilterType
 }
 
 #[derive(Clone)]
@@ -16,72 +35,102 @@ struct FilterState {
     g: f32,
     r2: f32,
     h: f32,
-    sample_rate: f32
+    frequency: f32,
+    resonance: f32,
+    context: Context
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum FilterType {
     Lowpass, Bandpass, Highpass
 }
 
+impl Choice for FilterType {
+    const ALL: &'static [FilterType] = &[FilterType::Lowpass, FilterType::Bandpass, FilterType::Highpass];
+    const NAMES: &'static [&'static str] = &["Lowpass", "Bandpass", "Highpass"];
+}
+
 impl Default for FilterState {
     fn default() -> Self {
-        let g = (std::f32::consts::PI * 200.0 / 44100.0).tan();
+        let g = (std::f32::consts::PI * 200.0 / context::DEFAULT_SAMPLE_RATE).tan();
         let r2 = std::f32::consts::SQRT_2;
         let h = 1.0 / (1.0 + r2 * g + g * g);
         FilterState {
-            g: g,
-            r2: r2,
-            h: h,
-            sample_rate: 44100.0
+            g,
+            r2,
+            h,
+            frequency: 200.0,
+            resonance: 1.0 / r2,
+            context: Context::default()
         }
     }
 }
 
 impl FilterState {
+    // called every sample while the cutoff is being modulated, so skip the tan() when nothing moved
     pub fn set_params(&mut self, freq: f32, resonance: f32) {
-        self.g = (std::f32::consts::PI * freq / self.sample_rate).tan();
+        if freq == self.frequency && resonance == self.resonance {
+            return;
+        }
+        self.frequency = freq;
+        self.resonance = resonance;
+        self.g = (std::f32::consts::PI * freq / self.context.sample_rate).tan();
         self.r2 = 1.0 / resonance;
         self.h = 1.0 / (1.0 + self.r2 * self.g + self.g * self.g);
     }
 
-    pub fn set_sample_rate(&mut self, sr: f32) {
-        self.sample_rate = sr;
+    pub fn set_context(&mut self, context: Context) {
+        self.context = context;
+        // force the coefficients to be recalculated at the new rate
+        self.frequency = 0.0;
     }
 }
 
 impl Default for Filter {
     fn default() -> Self {
         Filter {
-            sample_rate: 44100.0,
+            context: Context::default(),
             y: [0.0, 0.0, 0.0],
             s1: 0.0,
             s2: 0.0,
+            note: f32::from(KEY_TRACK_REFERENCE),
+            velocity: 1.0,
+            cutoff: 20000.0,
+            resonance: 0.707,
+            key_track: 0.0,
+            velocity_amount: 0.0,
+            env_amount: 0.0,
             filter_state: FilterState::default(),
-            filter_type: FilterType::Lowpass,
-            envelope: adsr::ADSR::default()
+            filter_type: FilterType::Lowpass
         }
     }
 }
 
 impl Filter {
-    pub fn set_params(&mut self, cutoff: f32, res: f32, new_type: f32, attack: f32, decay: f32, sustain: f32, release: f32) {
-        self.filter_state.set_params(((cutoff * 19980.0) + 20.0) * self.envelope.get_output(), (res * 9.9) + 0.1);
-        self.filter_type = match new_type {
-            t if t < 0.33 => FilterType::Lowpass,
-            t if t < 0.66 => FilterType::Bandpass,
-            _ => FilterType::Highpass
-        };
-        self.envelope.set_params(attack, decay, sustain, release);
+    // cutoff is in Hz and resonance is Q, key_track is 0 - 2 (0 - 200%), velocity_amount is 0 - 1, env_amount is in
+    // octaves and can be negative
+    pub fn set_params(&mut self, cutoff: f32, resonance: f32, filter_type: FilterType, key_track: f32, velocity_amount: f32, env_amount: f32) {
+        self.cutoff = cutoff;
+        self.resonance = resonance;
+        self.key_track = key_track;
+        self.velocity_amount = velocity_amount;
+        self.env_amount = env_amount;
+        self.filter_type = filter_type;
     }
 
-    pub fn set_sample_rate(&mut self, rate: f32) {
-        self.sample_rate = rate;
-        self.filter_state.set_sample_rate(self.sample_rate);
+    pub fn set_context(&mut self, context: Context) {
+        self.context = context;
+        self.filter_state.set_context(context);
     }
-    
-    pub fn process(&mut self, input: f32) -> f32 {
-        self.envelope.process();
+
+    // envelope is the 0 - 1 output of the voice's filter envelope, scaled here by this filter's env amount
+    // cutoff_mod is in octaves - LFOs, filter FM etc. get summed by the caller
+    pub fn process(&mut self, input: f32, envelope: f32, cutoff_mod: f32) -> f32 {
+        let key_octaves = ((self.note - f32::from(KEY_TRACK_REFERENCE)) / 12.0) * self.key_track;
+        let velocity_octaves = (self.velocity - 1.0) * self.velocity_amount * VELOCITY_RANGE_OCTAVES;
+        let env_octaves = envelope * self.env_amount;
+        let freq = self.cutoff * (key_octaves + velocity_octaves + env_octaves + cutoff_mod).exp2();
+        self.filter_state.set_params(freq.max(MIN_CUTOFF).min(self.context.sample_rate * MAX_CUTOFF_RATIO), self.resonance);
 
         self.y[2] = (input - self.s1 * self.filter_state.r2 - self.s1 * self.filter_state.g - self.s2) * self.filter_state.h;
 
@@ -100,11 +149,13 @@ impl Filter {
         }
     }
 
-    pub fn start_note(&mut self) {
-        self.envelope.start_note();
+    pub fn start_note(&mut self, pitch: f32, vel: u8) {
+        self.note = pitch;
+        self.velocity = vel as f32 / 127.0;
     }
 
-    pub fn end_note(&mut self) {
-        self.envelope.end_note();
+    // legato - key tracking follows the new note, the velocity stays with the note that started it
+    pub fn change_note(&mut self, pitch: f32) {
+        self.note = pitch;
     }
 }
@@ -1,19 +1,39 @@
-#[macro_use]
 extern crate vst;
+#[macro_use]
+extern crate rs_common;
 
-use oscillator::OscillatorType;
+use oscillator::{OscillatorType, PhaseMode, SubOscillatorType};
 use vst::plugin::{HostCallback, Info, Plugin, Category, PluginParameters, CanDo};
 use vst::util::AtomicFloat;
-use vst::buffer::AudioBuffer;
-use vst::api::{Events, Supported};
+use vst::buffer::{AudioBuffer, Outputs};
+use vst::api::{Events, Supported, TimeInfoFlags};
+use vst::host::Host;
 use vst::event::Event;
 
-use std::sync::Arc;
+use rs_common::parameter::{self, Choice, Parameters};
+use rs_common::preset::{self, Library};
+use rs_common::program::{self, Bank};
+use rs_common::state::{Migration, State};
+use rs_common::waveshaper::ShaperType;
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::vec::Vec;
 
 mod adsr;
+mod context;
+mod drive;
 mod oscillator;
 mod filter;
+mod mixer;
+mod mseg;
+mod noise;
+mod presets;
+mod random;
+mod tuning;
+mod voice;
 
 /*
 *   Poly dual osc synth with switchable waveforms and resonant lowpass/highpass/bandpass filter
@@ -21,27 +41,76 @@ mod filter;
 *   TODO:
 *   Pitch envelope
 *   Cross Modulation
-*   Filter/freq lfo
 */
 
 const VOICES: usize = 8;
+const PARAMETERS: i32 = SynthParameters::TABLE.len() as i32;
+// add one whenever parameters are moved or removed or what a value means changes, the saved state's version is
+// how many there are - new parameters take the next ID and don't need one, they're missing from older states and
+// keep their defaults
+const MIGRATIONS: [Migration; 1] = [|parameters| from_uneven_steps(parameters)];
+const TUNING_SECTION: [u8; 4] = *b"TUNE";
+// room made in resume for the events of one buffer - more than this from the host grows it, but a SysEx payload
+// that doesn't fit in what's left of its room is dropped instead, ten MTS bulk dumps (408 bytes each) fit
+const EVENTS: usize = 512;
+const SYSEX_BYTES: usize = 4096;
+// max depth of the filter LFO and filter FM, in octaves
+const FILTER_MOD_OCTAVES: f32 = 4.0;
+// max depth of the pitch LFO and the widest bend range, in semitones
+const PITCH_LFO_SEMITONES: f32 = 12.0;
+const MAX_BEND_RANGE: f32 = 24.0;
+// the oscillator octaves and semitones, and the transpose, go this far either way
+const OCTAVES: i8 = 2;
+const SEMITONES: i8 = 24;
+// used for tempo sync when the host doesn't say
+const DEFAULT_TEMPO: f32 = 120.0;
+// tempo synced mseg segment lengths in quarter notes
+const SYNC_DIVISIONS: [(f32, &str); 8] = [
+    (0.0625, "1/64"), (0.125, "1/32"), (0.25, "1/16"), (0.5, "1/8"), (1.0, "1/4"), (2.0, "1/2"), (4.0, "1 Bar"), (8.0, "2 Bars")
+];
 
 #[derive(Default)]
-struct Synth {
-    oscillators_one: Vec<oscillator::Oscillator>,    
-    oscillators_two: Vec<oscillator::Oscillator>,    
+pub struct Synth {
+    host: HostCallback,
+    voices: Vec<voice::Voice>,
     pitch_lfo: oscillator::LFO,
+    filter_lfo: oscillator::LFO,
+    pwm_lfo: oscillator::LFO,
     params: Arc<SynthParameters>,
     last_played_osc_index: usize,
     current_num_voices: usize,
     monophonic: bool,
-    filters: Vec<filter::Filter>,
     active_notes: Vec<u8>,
     active_velocities: Vec<u8>,
-    sample_rate: f32
+    // the sample rate etc., every voice and LFO gets a copy
+    context: context::Context,
+    tuning: tuning::Tuning,
+    // which of params.tunings was last picked with the tuning parameter
+    tuning_index: usize,
+    // the tuning has changed since it was last copied to params.current_tuning
+    tuning_changed: bool,
+    // -1 - 1 from the pitch wheel, scaled by the bend range
+    pitch_bend: f32,
+    // from a MIDI program change, waiting for the bank to be free
+    pending_program: Option<usize>,
+    // the values that go into the bank and come back out on a program change, with room made in resume so
+    // switching on the audio thread doesn't allocate
+    program_values: Vec<f32>,
+    // from the host, each with the frame it lands on in the next buffer, in frame order
+    events: Vec<(usize, PendingEvent)>,
+    // the payloads of the SysEx events waiting in events, one after another
+    sysex: Vec<u8>
+}
+
+// an event held until its frame comes round
+enum PendingEvent {
+    Midi([u8; 3]),
+    // where its payload is in sysex
+    SysEx(Range<usize>)
 }
 
-struct SynthParameters {
+// public for its named parameter IDs, so rs_render can use them too
+pub struct SynthParameters {
     oscillator_one_type: AtomicFloat,
     oscillator_one_pulsewidth: AtomicFloat,
     oscillator_one_octave: AtomicFloat,
@@ -69,231 +138,566 @@ struct SynthParameters {
     pitch_lfo_rate: AtomicFloat,
     num_voices: AtomicFloat,
     portamento: AtomicFloat,
+    filter_key_track: AtomicFloat,
+    filter_velocity: AtomicFloat,
+    filter_env_amount: AtomicFloat,
+    filter_lfo_depth: AtomicFloat,
+    filter_lfo_rate: AtomicFloat,
+    filter_fm_amount: AtomicFloat,
+    filter_fm_source: AtomicFloat,
+    filter_two_type: AtomicFloat,
+    filter_two_cutoff: AtomicFloat,
+    filter_two_resonance: AtomicFloat,
+    filter_two_env_amount: AtomicFloat,
+    filter_routing: AtomicFloat,
+    filter_balance: AtomicFloat,
+    drive: AtomicFloat,
+    drive_type: AtomicFloat,
+    saturation: AtomicFloat,
+    saturation_type: AtomicFloat,
+    noise_type: AtomicFloat,
+    noise_volume: AtomicFloat,
+    sub_type: AtomicFloat,
+    sub_octave: AtomicFloat,
+    sub_volume: AtomicFloat,
+    oscillator_one_velocity: AtomicFloat,
+    oscillator_two_velocity: AtomicFloat,
+    sub_velocity: AtomicFloat,
+    noise_velocity: AtomicFloat,
+    velocity_curve: AtomicFloat,
+    pwm_source: AtomicFloat,
+    pwm_depth: AtomicFloat,
+    pwm_lfo_rate: AtomicFloat,
+    pwm_attack: AtomicFloat,
+    pwm_decay: AtomicFloat,
+    pwm_sustain: AtomicFloat,
+    pwm_release: AtomicFloat,
+    oscillator_one_phase_mode: AtomicFloat,
+    oscillator_one_start_phase: AtomicFloat,
+    oscillator_two_phase_mode: AtomicFloat,
+    oscillator_two_start_phase: AtomicFloat,
+    analog: AtomicFloat,
+    transpose: AtomicFloat,
+    master_tune: AtomicFloat,
+    tuning: AtomicFloat,
+    bend_range: AtomicFloat,
+    envelope_delay: AtomicFloat,
+    envelope_hold: AtomicFloat,
+    attack_curve: AtomicFloat,
+    decay_curve: AtomicFloat,
+    release_curve: AtomicFloat,
+    filter_delay: AtomicFloat,
+    filter_hold: AtomicFloat,
+    filter_attack_curve: AtomicFloat,
+    filter_decay_curve: AtomicFloat,
+    filter_release_curve: AtomicFloat,
+    pwm_delay: AtomicFloat,
+    pwm_hold: AtomicFloat,
+    pwm_attack_curve: AtomicFloat,
+    pwm_decay_curve: AtomicFloat,
+    pwm_release_curve: AtomicFloat,
+    envelope_velocity_to_attack: AtomicFloat,
+    envelope_velocity_to_amount: AtomicFloat,
+    envelope_key_follow: AtomicFloat,
+    filter_velocity_to_attack: AtomicFloat,
+    filter_velocity_to_amount: AtomicFloat,
+    filter_key_follow: AtomicFloat,
+    pwm_velocity_to_attack: AtomicFloat,
+    pwm_velocity_to_amount: AtomicFloat,
+    pwm_key_follow: AtomicFloat,
+    retrigger: AtomicFloat,
+    mseg_points: AtomicFloat,
+    mseg_loop_start: AtomicFloat,
+    mseg_loop_end: AtomicFloat,
+    mseg_sync: AtomicFloat,
+    mseg_destination: AtomicFloat,
+    mseg_amount: AtomicFloat,
+    mseg_level: [AtomicFloat; mseg::MAX_POINTS],
+    mseg_time: [AtomicFloat; mseg::MAX_POINTS],
+    mseg_curve: [AtomicFloat; mseg::MAX_POINTS],
+    // 12-TET and the .scl files from the config folder, loaded once when the plugin starts
+    tunings: Vec<tuning::Tuning>,
+    // the tuning that's playing including any MTS changes, kept here so it's saved with the state
+    current_tuning: Mutex<tuning::Tuning>,
+    // a saved state was just loaded, the audio thread should switch to current_tuning
+    tuning_loaded: AtomicBool,
+    bank: Bank
 }
 
 impl Default for SynthParameters {
     fn default() -> SynthParameters {
-        SynthParameters {
-            oscillator_one_type: AtomicFloat::new(0.0),
-            oscillator_one_pulsewidth: AtomicFloat::new(0.5),
-            oscillator_one_octave: AtomicFloat::new(0.5),
-            oscillator_one_semitone: AtomicFloat::new(0.5),
-            oscillator_one_fine: AtomicFloat::new(0.5),
-            oscillator_one_volume: AtomicFloat::new(0.05),
-            oscillator_two_type: AtomicFloat::new(0.0),
-            oscillator_two_pulsewidth: AtomicFloat::new(0.5),
-            oscillator_two_octave: AtomicFloat::new(0.5),
-            oscillator_two_semitone: AtomicFloat::new(0.5),
-            oscillator_two_fine: AtomicFloat::new(0.5),
-            oscillator_two_volume: AtomicFloat::new(0.05),            
-            attack: AtomicFloat::new(0.0),
-            decay: AtomicFloat::new(0.0),
-            sustain: AtomicFloat::new(1.0),
-            release: AtomicFloat::new(0.0),
-            filter_type: AtomicFloat::new(0.0),
-            filter_cutoff: AtomicFloat::new(1.0),
-            filter_resonance: AtomicFloat::new(0.07),
-            filter_attack: AtomicFloat::new(0.0),
-            filter_decay: AtomicFloat::new(0.0),
-            filter_sustain: AtomicFloat::new(1.0),
-            filter_release: AtomicFloat::new(0.0),
-            pitch_lfo_depth: AtomicFloat::new(0.0),
-            pitch_lfo_rate: AtomicFloat::new(0.25),
-            num_voices: AtomicFloat::new(1.0),
-            portamento: AtomicFloat::new(0.0) 
+        let mut params = SynthParameters {
+            oscillator_one_type: AtomicFloat::default(),
+            oscillator_one_pulsewidth: AtomicFloat::default(),
+            oscillator_one_octave: AtomicFloat::default(),
+            oscillator_one_semitone: AtomicFloat::default(),
+            oscillator_one_fine: AtomicFloat::default(),
+            oscillator_one_volume: AtomicFloat::default(),
+            oscillator_two_type: AtomicFloat::default(),
+            oscillator_two_pulsewidth: AtomicFloat::default(),
+            oscillator_two_octave: AtomicFloat::default(),
+            oscillator_two_semitone: AtomicFloat::default(),
+            oscillator_two_fine: AtomicFloat::default(),
+            oscillator_two_volume: AtomicFloat::default(),
+            attack: AtomicFloat::default(),
+            decay: AtomicFloat::default(),
+            sustain: AtomicFloat::default(),
+            release: AtomicFloat::default(),
+            filter_type: AtomicFloat::default(),
+            filter_cutoff: AtomicFloat::default(),
+            filter_resonance: AtomicFloat::default(),
+            filter_attack: AtomicFloat::default(),
+            filter_decay: AtomicFloat::default(),
+            filter_sustain: AtomicFloat::default(),
+            filter_release: AtomicFloat::default(),
+            pitch_lfo_depth: AtomicFloat::default(),
+            pitch_lfo_rate: AtomicFloat::default(),
+            num_voices: AtomicFloat::default(),
+            portamento: AtomicFloat::default(),
+            filter_key_track: AtomicFloat::default(),
+            filter_velocity: AtomicFloat::default(),
+            filter_env_amount: AtomicFloat::default(),
+            filter_lfo_depth: AtomicFloat::default(),
+            filter_lfo_rate: AtomicFloat::default(),
+            filter_fm_amount: AtomicFloat::default(),
+            filter_fm_source: AtomicFloat::default(),
+            filter_two_type: AtomicFloat::default(),
+            filter_two_cutoff: AtomicFloat::default(),
+            filter_two_resonance: AtomicFloat::default(),
+            filter_two_env_amount: AtomicFloat::default(),
+            filter_routing: AtomicFloat::default(),
+            filter_balance: AtomicFloat::default(),
+            drive: AtomicFloat::default(),
+            drive_type: AtomicFloat::default(),
+            saturation: AtomicFloat::default(),
+            saturation_type: AtomicFloat::default(),
+            noise_type: AtomicFloat::default(),
+            noise_volume: AtomicFloat::default(),
+            sub_type: AtomicFloat::default(),
+            sub_octave: AtomicFloat::default(),
+            sub_volume: AtomicFloat::default(),
+            oscillator_one_velocity: AtomicFloat::default(),
+            oscillator_two_velocity: AtomicFloat::default(),
+            sub_velocity: AtomicFloat::default(),
+            noise_velocity: AtomicFloat::default(),
+            velocity_curve: AtomicFloat::default(),
+            pwm_source: AtomicFloat::default(),
+            pwm_depth: AtomicFloat::default(),
+            pwm_lfo_rate: AtomicFloat::default(),
+            pwm_attack: AtomicFloat::default(),
+            pwm_decay: AtomicFloat::default(),
+            pwm_sustain: AtomicFloat::default(),
+            pwm_release: AtomicFloat::default(),
+            oscillator_one_phase_mode: AtomicFloat::default(),
+            oscillator_one_start_phase: AtomicFloat::default(),
+            oscillator_two_phase_mode: AtomicFloat::default(),
+            oscillator_two_start_phase: AtomicFloat::default(),
+            analog: AtomicFloat::default(),
+            transpose: AtomicFloat::default(),
+            master_tune: AtomicFloat::default(),
+            tuning: AtomicFloat::default(),
+            bend_range: AtomicFloat::default(),
+            envelope_delay: AtomicFloat::default(),
+            envelope_hold: AtomicFloat::default(),
+            attack_curve: AtomicFloat::default(),
+            decay_curve: AtomicFloat::default(),
+            release_curve: AtomicFloat::default(),
+            filter_delay: AtomicFloat::default(),
+            filter_hold: AtomicFloat::default(),
+            filter_attack_curve: AtomicFloat::default(),
+            filter_decay_curve: AtomicFloat::default(),
+            filter_release_curve: AtomicFloat::default(),
+            pwm_delay: AtomicFloat::default(),
+            pwm_hold: AtomicFloat::default(),
+            pwm_attack_curve: AtomicFloat::default(),
+            pwm_decay_curve: AtomicFloat::default(),
+            pwm_release_curve: AtomicFloat::default(),
+            envelope_velocity_to_attack: AtomicFloat::default(),
+            envelope_velocity_to_amount: AtomicFloat::default(),
+            envelope_key_follow: AtomicFloat::default(),
+            filter_velocity_to_attack: AtomicFloat::default(),
+            filter_velocity_to_amount: AtomicFloat::default(),
+            filter_key_follow: AtomicFloat::default(),
+            pwm_velocity_to_attack: AtomicFloat::default(),
+            pwm_velocity_to_amount: AtomicFloat::default(),
+            pwm_key_follow: AtomicFloat::default(),
+            retrigger: AtomicFloat::default(),
+            mseg_points: AtomicFloat::default(),
+            mseg_loop_start: AtomicFloat::default(),
+            mseg_loop_end: AtomicFloat::default(),
+            mseg_sync: AtomicFloat::default(),
+            mseg_destination: AtomicFloat::default(),
+            mseg_amount: AtomicFloat::default(),
+            mseg_level: Default::default(),
+            mseg_time: Default::default(),
+            mseg_curve: Default::default(),
+            tunings: vec![tuning::Tuning::default()],
+            current_tuning: Mutex::new(tuning::Tuning::default()),
+            tuning_loaded: AtomicBool::new(false),
+            bank: Bank::new(vec![])
+        };
+        // the defaults are in the parameter table, and the factory programs are built from them
+        params.reset();
+        params.bank = Bank::new(presets::factory(&params));
+        params
+    }
+}
+
+type Parameter = rs_common::parameter::Parameter<SynthParameters>;
+
+// the default envelope curves as 0 - 1, the curve parameters are -100 - 100 % of adsr::shape's -1 - 1
+const ATTACK_CURVE: f32 = (adsr::DEFAULT_ATTACK_CURVE + 1.0) / 2.0;
+const DR_CURVE: f32 = (adsr::DEFAULT_DR_CURVE + 1.0) / 2.0;
+
+// 0 - 10 seconds, or a note length when synced - every segment's time is the same apart from its name
+const fn mseg_time(name: &'static str) -> Parameter {
+    Parameter::new(name, 0.1).range(0.0, 10.0).skew(2.0).text(|p, v| p.mseg_time_text(v)).parse(|p, text| p.parse_mseg_time(text))
+}
+
+parameters! {
+    SynthParameters {
+        0 => oscillator_one_type as OSCILLATOR_ONE_TYPE: Parameter::new("Osc 1 Type", 0.0).choice::<OscillatorType>(),
+        1 => oscillator_one_pulsewidth: Parameter::new("Osc 1 Pulsewidth", 0.5),
+        2 => oscillator_one_octave as OSCILLATOR_ONE_OCTAVE: Parameter::new("Osc 1 Octave", 0.5).range(-OCTAVES as f32, OCTAVES as f32).integers().signed(),
+        3 => oscillator_one_semitone as OSCILLATOR_ONE_SEMITONE: Parameter::new("Osc 1 Semitone", 0.5).range(-SEMITONES as f32, SEMITONES as f32).integers().signed(),
+        4 => oscillator_one_fine as OSCILLATOR_ONE_FINE: Parameter::new("Osc 1 Fine", 0.5).label("ct").range(-100.0, 100.0).decimals(0).signed(),
+        5 => oscillator_one_volume: Parameter::new("Osc 1 Volume", 0.05),
+        6 => oscillator_two_type: Parameter::new("Osc 2 Type", 0.0).choice::<OscillatorType>(),
+        7 => oscillator_two_pulsewidth: Parameter::new("Osc 2 Pulsewidth", 0.5),
+        8 => oscillator_two_octave as OSCILLATOR_TWO_OCTAVE: Parameter::new("Osc 2 Octave", 0.5).range(-OCTAVES as f32, OCTAVES as f32).integers().signed(),
+        9 => oscillator_two_semitone as OSCILLATOR_TWO_SEMITONE: Parameter::new("Osc 2 Semitone", 0.5).range(-SEMITONES as f32, SEMITONES as f32).integers().signed(),
+        10 => oscillator_two_fine as OSCILLATOR_TWO_FINE: Parameter::new("Osc 2 Fine", 0.5).label("ct").range(-100.0, 100.0).decimals(0).signed(),
+        11 => oscillator_two_volume: Parameter::new("Osc 2 Volume", 0.05),
+        12 => attack as ATTACK: Parameter::new("Envelope Attack", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        13 => decay as DECAY: Parameter::new("Envelope Decay", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        14 => sustain: Parameter::new("Envelope Sustain", 1.0),
+        15 => release as RELEASE: Parameter::new("Envelope Release", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        16 => filter_type as FILTER_TYPE: Parameter::new("Filter Type", 0.0).choice::<filter::FilterType>(),
+        17 => filter_cutoff as FILTER_CUTOFF: Parameter::new("Filter Cutoff", 1.0).label("Hz").range(20.0, 20000.0).skew(3.0),
+        18 => filter_resonance as FILTER_RESONANCE: Parameter::new("Filter Resonance", 0.07).range(0.1, 10.0),
+        19 => filter_attack as FILTER_ATTACK: Parameter::new("Filter Attack", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        20 => filter_decay as FILTER_DECAY: Parameter::new("Filter Decay", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        21 => filter_sustain: Parameter::new("Filter Sustain", 1.0),
+        22 => filter_release as FILTER_RELEASE: Parameter::new("Filter Release", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        23 => pitch_lfo_depth as PITCH_LFO_DEPTH: Parameter::new("Pitch LFO Depth", 0.0).label("st").range(0.0, PITCH_LFO_SEMITONES).skew(2.0),
+        24 => pitch_lfo_rate as PITCH_LFO_RATE: Parameter::new("Pitch LFO Rate", 0.25).label("Hz").range(0.1, 20.0),
+        25 => num_voices as NUM_VOICES: Parameter::new("Voices", 1.0).range(1.0, VOICES as f32).integers(),
+        26 => portamento as PORTAMENTO: Parameter::new("Portamento Time", 0.0).label("s").range(0.001, 10.0).skew(4.0),
+        27 => filter_key_track as FILTER_KEY_TRACK: Parameter::new("Filter Key Track", 0.0).label("%").range(0.0, 200.0).decimals(0),
+        28 => filter_velocity as FILTER_VELOCITY: Parameter::new("Filter Velocity", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        29 => filter_env_amount as FILTER_ENV_AMOUNT: Parameter::new("Filter Env Amount", 0.5).label("oct").range(-filter::ENV_AMOUNT_OCTAVES, filter::ENV_AMOUNT_OCTAVES).signed(),
+        30 => filter_lfo_depth as FILTER_LFO_DEPTH: Parameter::new("Filter LFO Depth", 0.0).label("oct").range(0.0, FILTER_MOD_OCTAVES).skew(2.0),
+        31 => filter_lfo_rate as FILTER_LFO_RATE: Parameter::new("Filter LFO Rate", 0.25).label("Hz").range(0.1, 20.0),
+        32 => filter_fm_amount as FILTER_FM_AMOUNT: Parameter::new("Filter FM Amount", 0.0).label("oct").range(0.0, FILTER_MOD_OCTAVES).skew(2.0),
+        33 => filter_fm_source: Parameter::new("Filter FM Source", 0.0).choices(&["Osc 1", "Osc 2"]),
+        34 => filter_two_type: Parameter::new("Filter 2 Type", 0.0).choice::<filter::FilterType>(),
+        35 => filter_two_cutoff as FILTER_TWO_CUTOFF: Parameter::new("Filter 2 Cutoff", 1.0).label("Hz").range(20.0, 20000.0).skew(3.0),
+        36 => filter_two_resonance as FILTER_TWO_RESONANCE: Parameter::new("Filter 2 Resonance", 0.07).range(0.1, 10.0),
+        37 => filter_two_env_amount as FILTER_TWO_ENV_AMOUNT: Parameter::new("Filter 2 Env Amount", 0.5).label("oct").range(-filter::ENV_AMOUNT_OCTAVES, filter::ENV_AMOUNT_OCTAVES).signed(),
+        38 => filter_routing as FILTER_ROUTING: Parameter::new("Filter Routing", 0.0).choice::<voice::FilterRouting>(),
+        39 => filter_balance as FILTER_BALANCE: Parameter::new("Filter Balance", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        40 => drive as DRIVE: Parameter::new("Drive", 0.0).label("dB").range(0.0, drive::MAX_DRIVE_DB).decimals(1),
+        41 => drive_type as DRIVE_TYPE: Parameter::new("Drive Type", 0.0).choice::<ShaperType>(),
+        42 => saturation as SATURATION: Parameter::new("Saturation", 0.0).label("dB").range(0.0, drive::MAX_DRIVE_DB).decimals(1),
+        43 => saturation_type: Parameter::new("Saturation Type", 0.0).choice::<ShaperType>(),
+        44 => noise_type: Parameter::new("Noise Type", 0.0).choice::<noise::NoiseType>(),
+        45 => noise_volume: Parameter::new("Noise Volume", 0.0),
+        46 => sub_type: Parameter::new("Sub Type", 0.0).choice::<SubOscillatorType>(),
+        47 => sub_octave: Parameter::new("Sub Octave", 0.0).choices(&["-1", "-2"]),
+        48 => sub_volume: Parameter::new("Sub Volume", 0.0),
+        49 => oscillator_one_velocity as OSCILLATOR_ONE_VELOCITY: Parameter::new("Osc 1 Velocity", 1.0).label("%").range(0.0, 100.0).decimals(0),
+        50 => oscillator_two_velocity as OSCILLATOR_TWO_VELOCITY: Parameter::new("Osc 2 Velocity", 1.0).label("%").range(0.0, 100.0).decimals(0),
+        51 => sub_velocity as SUB_VELOCITY: Parameter::new("Sub Velocity", 1.0).label("%").range(0.0, 100.0).decimals(0),
+        52 => noise_velocity as NOISE_VELOCITY: Parameter::new("Noise Velocity", 1.0).label("%").range(0.0, 100.0).decimals(0),
+        53 => velocity_curve: Parameter::new("Velocity Curve", 0.0).choice::<mixer::VelocityCurve>(),
+        54 => pwm_source: Parameter::new("PWM Source", 0.0).choice::<voice::PwmSource>(),
+        55 => pwm_depth as PWM_DEPTH: Parameter::new("PWM Depth", 0.0).range(0.0, 0.5),
+        56 => pwm_lfo_rate as PWM_LFO_RATE: Parameter::new("PWM LFO Rate", 0.25).label("Hz").range(0.1, 20.0),
+        57 => pwm_attack as PWM_ATTACK: Parameter::new("PWM Attack", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        58 => pwm_decay as PWM_DECAY: Parameter::new("PWM Decay", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        59 => pwm_sustain: Parameter::new("PWM Sustain", 1.0),
+        60 => pwm_release as PWM_RELEASE: Parameter::new("PWM Release", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        61 => oscillator_one_phase_mode: Parameter::new("Osc 1 Phase Mode", 0.0).choice::<PhaseMode>(),
+        62 => oscillator_one_start_phase as OSCILLATOR_ONE_START_PHASE: Parameter::new("Osc 1 Start Phase", 0.0).label("deg").range(0.0, 360.0).decimals(0),
+        63 => oscillator_two_phase_mode: Parameter::new("Osc 2 Phase Mode", 0.0).choice::<PhaseMode>(),
+        64 => oscillator_two_start_phase as OSCILLATOR_TWO_START_PHASE: Parameter::new("Osc 2 Start Phase", 0.0).label("deg").range(0.0, 360.0).decimals(0),
+        65 => analog as ANALOG: Parameter::new("Analog", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        66 => transpose as TRANSPOSE: Parameter::new("Transpose", 0.5).range(-SEMITONES as f32, SEMITONES as f32).integers().signed(),
+        67 => master_tune as MASTER_TUNE: Parameter::new("Master Tune", 0.5).label("Hz").range(400.0, 480.0).decimals(1),
+        // which tunings there are depends on the files in the config folder, so it isn't automated
+        68 => tuning as TUNING: Parameter::new("Tuning", 0.0).text(|p, v| p.tunings[p.tuning_at(v)].name().to_string()).fixed(),
+        69 => bend_range as BEND_RANGE: Parameter::new("Bend Range", 2.0 / MAX_BEND_RANGE).label("st").range(0.0, MAX_BEND_RANGE).integers(),
+        70 => envelope_delay as ENVELOPE_DELAY: Parameter::new("Envelope Delay", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        71 => envelope_hold as ENVELOPE_HOLD: Parameter::new("Envelope Hold", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        72 => attack_curve as ATTACK_CURVE: Parameter::new("Envelope Attack Curve", ATTACK_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        73 => decay_curve as DECAY_CURVE: Parameter::new("Envelope Decay Curve", DR_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        74 => release_curve as RELEASE_CURVE: Parameter::new("Envelope Release Curve", DR_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        75 => filter_delay as FILTER_DELAY: Parameter::new("Filter Delay", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        76 => filter_hold as FILTER_HOLD: Parameter::new("Filter Hold", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        77 => filter_attack_curve as FILTER_ATTACK_CURVE: Parameter::new("Filter Attack Curve", ATTACK_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        78 => filter_decay_curve as FILTER_DECAY_CURVE: Parameter::new("Filter Decay Curve", DR_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        79 => filter_release_curve as FILTER_RELEASE_CURVE: Parameter::new("Filter Release Curve", DR_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        80 => pwm_delay as PWM_DELAY: Parameter::new("PWM Delay", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        81 => pwm_hold as PWM_HOLD: Parameter::new("PWM Hold", 0.0).label("s").range(0.0, 10.0).skew(2.0),
+        82 => pwm_attack_curve as PWM_ATTACK_CURVE: Parameter::new("PWM Attack Curve", ATTACK_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        83 => pwm_decay_curve as PWM_DECAY_CURVE: Parameter::new("PWM Decay Curve", DR_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        84 => pwm_release_curve as PWM_RELEASE_CURVE: Parameter::new("PWM Release Curve", DR_CURVE).label("%").range(-100.0, 100.0).decimals(0),
+        85 => envelope_velocity_to_attack as ENVELOPE_VELOCITY_TO_ATTACK: Parameter::new("Envelope Vel to Attack", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        86 => envelope_velocity_to_amount as ENVELOPE_VELOCITY_TO_AMOUNT: Parameter::new("Envelope Vel to Amount", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        87 => envelope_key_follow as ENVELOPE_KEY_FOLLOW: Parameter::new("Envelope Key Follow", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        88 => filter_velocity_to_attack as FILTER_VELOCITY_TO_ATTACK: Parameter::new("Filter Vel to Attack", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        89 => filter_velocity_to_amount as FILTER_VELOCITY_TO_AMOUNT: Parameter::new("Filter Vel to Amount", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        90 => filter_key_follow as FILTER_KEY_FOLLOW: Parameter::new("Filter Key Follow", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        91 => pwm_velocity_to_attack as PWM_VELOCITY_TO_ATTACK: Parameter::new("PWM Vel to Attack", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        92 => pwm_velocity_to_amount as PWM_VELOCITY_TO_AMOUNT: Parameter::new("PWM Vel to Amount", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        93 => pwm_key_follow as PWM_KEY_FOLLOW: Parameter::new("PWM Key Follow", 0.0).label("%").range(0.0, 100.0).decimals(0),
+        94 => retrigger as RETRIGGER: Parameter::new("Retrigger", 0.0).choice::<RetriggerMode>(),
+        95 => mseg_points as MSEG_POINTS: Parameter::new("MSEG Points", 0.5).range(2.0, mseg::MAX_POINTS as f32).integers(),
+        96 => mseg_loop_start as MSEG_LOOP_START: Parameter::new("MSEG Loop Start", 0.0).range(1.0, mseg::MAX_POINTS as f32).integers(),
+        97 => mseg_loop_end: Parameter::new("MSEG Loop End", 0.0).text(|p, v| p.mseg_loop_end_text(v)),
+        98 => mseg_sync as MSEG_SYNC: Parameter::new("MSEG Sync", 0.0).choices(&["Off", "On"]),
+        99 => mseg_destination: Parameter::new("MSEG Destination", 0.0).choice::<mseg::Destination>(),
+        100 => mseg_amount as MSEG_AMOUNT: Parameter::new("MSEG Amount", 0.5).label("%").range(-100.0, 100.0).decimals(0),
+        101 => mseg_level[0]: Parameter::new("MSEG 1 Level", 1.0),
+        102 => mseg_level[1]: Parameter::new("MSEG 2 Level", 0.5),
+        103 => mseg_level[2]: Parameter::new("MSEG 3 Level", 0.5),
+        104 => mseg_level[3]: Parameter::new("MSEG 4 Level", 0.0),
+        105 => mseg_level[4]: Parameter::new("MSEG 5 Level", 0.0),
+        106 => mseg_level[5]: Parameter::new("MSEG 6 Level", 0.0),
+        107 => mseg_level[6]: Parameter::new("MSEG 7 Level", 0.0),
+        108 => mseg_level[7]: Parameter::new("MSEG 8 Level", 0.0),
+        109 => mseg_time[0] as MSEG_1_TIME: mseg_time("MSEG 1 Time"),
+        110 => mseg_time[1]: mseg_time("MSEG 2 Time"),
+        111 => mseg_time[2]: mseg_time("MSEG 3 Time"),
+        112 => mseg_time[3]: mseg_time("MSEG 4 Time"),
+        113 => mseg_time[4]: mseg_time("MSEG 5 Time"),
+        114 => mseg_time[5]: mseg_time("MSEG 6 Time"),
+        115 => mseg_time[6]: mseg_time("MSEG 7 Time"),
+        116 => mseg_time[7]: mseg_time("MSEG 8 Time"),
+        117 => mseg_curve[0] as MSEG_1_CURVE: Parameter::new("MSEG 1 Curve", 0.5).label("%").range(-100.0, 100.0).decimals(0),
+        118 => mseg_curve[1] as MSEG_2_CURVE: Parameter::new("MSEG 2 Curve", 0.5).label("%").range(-100.0, 100.0).decimals(0),
+        119 => mseg_curve[2] as MSEG_3_CURVE: Parameter::new("MSEG 3 Curve", 0.5).label("%").range(-100.0, 100.0).decimals(0),
+        120 => mseg_curve[3] as MSEG_4_CURVE: Parameter::new("MSEG 4 Curve", 0.5).label("%").range(-100.0, 100.0).decimals(0),
+        121 => mseg_curve[4] as MSEG_5_CURVE: Parameter::new("MSEG 5 Curve", 0.5).label("%").range(-100.0, 100.0).decimals(0),
+        122 => mseg_curve[5] as MSEG_6_CURVE: Parameter::new("MSEG 6 Curve", 0.5).label("%").range(-100.0, 100.0).decimals(0),
+        123 => mseg_curve[6] as MSEG_7_CURVE: Parameter::new("MSEG 7 Curve", 0.5).label("%").range(-100.0, 100.0).decimals(0),
+        124 => mseg_curve[7] as MSEG_8_CURVE: Parameter::new("MSEG 8 Curve", 0.5).label("%").range(-100.0, 100.0).decimals(0)
+    }
+}
+
+impl SynthParameters {
+    fn tuning_index(&self) -> usize {
+        self.tuning_at(self.tuning.get())
+    }
+
+    // which of the tunings the tuning parameter picks at value
+    fn tuning_at(&self, value: f32) -> usize {
+        parameter::step(value, self.tunings.len())
+    }
+
+    fn parameter_values(&self) -> Vec<f32> {
+        (0..PARAMETERS).map(|i| self.get_parameter(i)).collect()
+    }
+
+    // anything past the parameters this version has is skipped
+    fn set_parameter_values(&self, values: &[f32]) {
+        for (i, value) in values.iter().enumerate().take(PARAMETERS as usize) {
+            self.set_parameter(i as i32, *value);
+        }
+    }
+
+    // the current program - its parameters, name and the tuning
+    fn program_state(&self) -> State {
+        let mut state = State::new(MIGRATIONS.len() as u32, self.parameter_values());
+        let mut tuning = vec![];
+        self.current_tuning.lock().unwrap().write(&mut tuning);
+        state.add_section(TUNING_SECTION, tuning);
+        state.add_section(program::NAME_SECTION, self.bank.name(self.bank.current()).into_bytes());
+        state
+    }
+
+    fn load_program_state(&self, mut state: State) {
+        state.migrate(&MIGRATIONS);
+        self.set_parameter_values(&state.parameters);
+        if let Some(tuning) = state.section(TUNING_SECTION).and_then(tuning::Tuning::read) {
+            *self.current_tuning.lock().unwrap() = tuning;
+            self.tuning_loaded.store(true, Ordering::Release);
+        }
+        if let Some(name) = state.section(program::NAME_SECTION).and_then(|n| String::from_utf8(n.to_vec()).ok()) {
+            self.bank.rename_current(name);
+        }
+    }
+
+    // the defaults, plus the tunings and user presets from the config folder if the library feature is on
+    fn with_library() -> Self {
+        if !cfg!(feature = "library") {
+            return SynthParameters::default();
         }
+        let mut params = SynthParameters {
+            tunings: tuning::load_library(),
+            ..Default::default()
+        };
+        if let Some(dir) = preset::library_dir("oscicrate") {
+            params.bank = Bank::new(presets::with_library(&params, &dir));
+        }
+        params
     }
+
+    // a percentage as a fraction, -100 - 100 % is -1 - 1
+    fn fraction(&self, id: i32) -> f32 {
+        self.plain(id) / 100.0
+    }
+
+    fn mseg_synced(&self) -> bool {
+        parameter::step(self.mseg_sync.get(), 2) == 1
+    }
+
+    fn mseg_time_text(&self, value: f32) -> String {
+        if self.mseg_synced() {
+            SYNC_DIVISIONS[sync_division(value)].1.to_string()
+        }
+        else {
+            format!("{:.2} s", mseg_time("").value(value))
+        }
+    }
+
+    // a note length when synced, otherwise seconds
+    fn parse_mseg_time(&self, text: &str) -> Option<f32> {
+        if self.mseg_synced() {
+            let division = SYNC_DIVISIONS.iter().position(|(_, name)| name.eq_ignore_ascii_case(text))?;
+            Some(parameter::step_value(division, SYNC_DIVISIONS.len()))
+        }
+        else {
+            parameter::parse_number(text, "s").map(|seconds| mseg_time("").normalize(seconds))
+        }
+    }
+
+    // the loop is off unless the end comes after the start
+    fn mseg_loop_end_text(&self, value: f32) -> String {
+        let end = mseg_loop_point(value);
+        if end > mseg_loop_point(self.mseg_loop_start.get()) { format!("{}", end + 1) } else { "Off".to_string() }
+    }
+
+    // a segment's length in seconds, tempo is in bpm
+    fn mseg_segment_time(&self, point: usize, tempo: f32) -> f32 {
+        let value = self.mseg_time[point].get();
+        if self.mseg_synced() {
+            SYNC_DIVISIONS[sync_division(value)].0 * 60.0 / tempo
+        }
+        else {
+            mseg_time("").value(value)
+        }
+    }
+}
+
+// the discrete parameters used to be split at 0.33 and 0.66, or rounded or truncated to count - 1 steps, and
+// now every step gets an equal share of the range - each value is moved to the one that picks the same step
+// the tuning can't be moved, how many tunings there are depends on the user's library
+fn from_uneven_steps(parameters: &mut [f32]) {
+    const THIRDS: [usize; 11] = [16, 34, 38, 41, 43, 44, 53, 54, 61, 63, 94];
+    // the index and how many steps it has
+    const SEMITONE_STEPS: usize = (SEMITONES * 2 + 1) as usize;
+    const TRUNCATED: [(usize, usize); 4] = [(3, SEMITONE_STEPS), (9, SEMITONE_STEPS), (66, SEMITONE_STEPS), (25, VOICES)];
+    const ROUNDED: [(usize, usize); 4] = [(69, MAX_BEND_RANGE as usize + 1), (95, mseg::MAX_POINTS - 1), (96, mseg::MAX_POINTS), (97, mseg::MAX_POINTS)];
+    let mseg_synced = parameters.get(98).is_some_and(|sync| *sync >= 0.5);
+    let synced_times = (109..117).filter(|_| mseg_synced).map(|i| (i, SYNC_DIVISIONS.len()));
+
+    for i in THIRDS.iter() {
+        if let Some(value) = parameters.get_mut(*i) {
+            *value = parameter::step_from_thresholds(*value, &[0.33, 0.66]);
+        }
+    }
+    for (i, count) in TRUNCATED.iter() {
+        if let Some(value) = parameters.get_mut(*i) {
+            *value = parameter::step_value(((*value * (count - 1) as f32) as usize).min(count - 1), *count);
+        }
+    }
+    for (i, count) in ROUNDED.iter().cloned().chain(synced_times) {
+        if let Some(value) = parameters.get_mut(i) {
+            *value = parameter::step_value((*value * (count - 1) as f32).round() as usize, count);
+        }
+    }
+}
+
+// legato plays like retrigger, except in mono mode where a note played while another is held
+// just changes the pitch and leaves the envelopes alone
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum RetriggerMode {
+    Retrigger, Reset, Legato
+}
+
+impl Choice for RetriggerMode {
+    const ALL: &'static [RetriggerMode] = &[RetriggerMode::Retrigger, RetriggerMode::Reset, RetriggerMode::Legato];
+    const NAMES: &'static [&'static str] = &["Retrigger", "Reset", "Legato"];
+}
+
+fn retrigger(value: f32) -> adsr::Retrigger {
+    if RetriggerMode::from_value(value) == RetriggerMode::Reset { adsr::Retrigger::Reset } else { adsr::Retrigger::FromCurrent }
+}
+
+// an index into the points
+fn mseg_loop_point(value: f32) -> usize {
+    parameter::step(value, mseg::MAX_POINTS)
+}
+
+fn sync_division(value: f32) -> usize {
+    parameter::step(value, SYNC_DIVISIONS.len())
 }
 
 impl PluginParameters for SynthParameters {
-    fn get_parameter_text(&self, index: i32) -> String {
-        match index {
-            0 => match self.oscillator_one_type.get() {
-                t if t < 0.25 => "Saw",
-                t if t < 0.5 => "Pulse",
-                t if t < 0.75 => "Tri",
-                _ => "Sine"
-            }.to_string(),
-            1 => format!("{:.2}", self.oscillator_one_pulsewidth.get()),
-            2 => match self.oscillator_one_octave.get() {
-                o if o < 0.2 => "-2",
-                o if o < 0.4 => "-1",
-                o if o < 0.6 => "0",
-                o if o < 0.8 => "+1",
-                _ => "+2"
-            }.to_string(),
-            3 => format!("{}{}", if self.oscillator_one_semitone.get() > 0.5 { "+" } else { "" }, ((self.oscillator_one_semitone.get() * 48.0) - 24.0) as i8),
-            4 => format!("{}{}", if self.oscillator_one_fine.get() > 0.5 { "+" } else { "" }, ((self.oscillator_one_fine.get() * 200.0) - 100.0) as i8),
-            5 => format!("{:.2}", self.oscillator_one_volume.get()),
-            6 => match self.oscillator_two_type.get() {
-                t if t < 0.25 => "Saw",
-                t if t < 0.5 => "Pulse",
-                t if t < 0.75 => "Tri",
-                _ => "Sine"
-            }.to_string(),
-            7 => format!("{:.2}", self.oscillator_two_pulsewidth.get()),
-            8 => match self.oscillator_two_octave.get() {
-                o if o < 0.2 => "-2",
-                o if o < 0.4 => "-1",
-                o if o < 0.6 => "0",
-                o if o < 0.8 => "+1",
-                _ => "+2"
-            }.to_string(),
-            9 => format!("{}{}", if self.oscillator_two_semitone.get() > 0.5 { "+" } else { "" }, ((self.oscillator_two_semitone.get() * 48.0) - 24.0) as i8),
-            10 => format!("{}{}", if self.oscillator_two_fine.get() > 0.5 { "+" } else { "" }, ((self.oscillator_two_fine.get() * 200.0) - 100.0) as i8),            
-            11 => format!("{}", self.oscillator_two_volume.get()),
-            12 => format!("{:.2}", self.attack.get().powi(2) * 10.0),
-            13 => format!("{:.2}", self.decay.get().powi(2) * 10.0),
-            14 => format!("{:.2}", self.sustain.get()),
-            15 => format!("{:.2}", self.release.get().powi(2) * 10.0),            
-            16 => if self.filter_type.get() < 0.33 { "Lowpass" } else if self.filter_type.get() < 0.66 { "Bandpass" } else { "Highpass" }.to_string(),
-            17 => format!("{:.2}", (self.filter_cutoff.get().powi(3) * 19980.0) + 20.0),
-            18 => format!("{:.2}", (self.filter_resonance.get() * 9.9) + 0.1),
-            19 => format!("{:.2}", self.filter_attack.get().powi(2) * 10.0),
-            20 => format!("{:.2}", self.filter_decay.get().powi(2) * 10.0),
-            21 => format!("{:.2}", self.filter_sustain.get()),
-            22 => format!("{:.2}", self.filter_release.get().powi(2) * 10.0),
-            23 => format!("{:.2}", self.pitch_lfo_depth.get().powi(2) * 100.0),
-            24 => format!("{:.2}", (self.pitch_lfo_rate.get() * 19.9) + 0.1),
-            25 => format!("{}", ((self.num_voices.get() * 7.0) + 1.0) as u8),
-            26 => format!("{:.2}", (self.portamento.get().powi(4) * 9.999) + 0.001),
-            _ => "".to_string()
-        }
-    }
-
-    fn get_parameter_name(&self, index: i32) -> String {
-        match index {
-            0 => "Osc 1 Type",
-            1 => "Osc 1 Pulsewidth",
-            2 => "Osc 1 Octave",
-            3 => "Osc 1 Semitone",
-            4 => "Osc 1 Fine",
-            5 => "Osc 1 Volume",
-            6 => "Osc 2 Type",
-            7 => "Osc 2 Pulsewidth",
-            8 => "Osc 2 Octave",
-            9 => "Osc 2 Semitone",
-            10 => "Osc 2 Fine",
-            11 => "Osc 2 Volume",
-            12 => "Envelope Attack",
-            13 => "Envelope Decay",
-            14 => "Envelope Sustain",
-            15 => "Envelope Release",            
-            16 => "Filter Type",
-            17 => "Filter Cutoff",
-            18 => "Filter Resonance",
-            19 => "Filter Attack",
-            20 => "Filter Decay",
-            21 => "Filter Sustain",
-            22 => "Filter Release",
-            23 => "Pitch LFO Depth",
-            24 => "Pitch LFO Rate",
-            25 => "Voices",
-            26 => "Portamento Time",
-            _ => ""
-        }.to_string()
-    }
-
-    fn get_parameter(&self, index: i32) -> f32 {
-        match index {
-            0 => self.oscillator_one_type.get(),
-            1 => self.oscillator_one_pulsewidth.get(),
-            2 => self.oscillator_one_octave.get(),
-            3 => self.oscillator_one_semitone.get(),
-            4 => self.oscillator_one_fine.get(),
-            5 => self.oscillator_one_volume.get(),
-            6 => self.oscillator_two_type.get(),
-            7 => self.oscillator_two_pulsewidth.get(),
-            8 => self.oscillator_two_octave.get(),
-            9 => self.oscillator_two_semitone.get(),
-            10 => self.oscillator_two_fine.get(),
-            11 => self.oscillator_two_volume.get(),            
-            12 => self.attack.get(),
-            13 => self.decay.get(),
-            14 => self.sustain.get(),
-            15 => self.release.get(),
-            16 => self.filter_type.get(),
-            17 => self.filter_cutoff.get(),
-            18 => self.filter_resonance.get(),            
-            19 => self.filter_attack.get(),
-            20 => self.filter_decay.get(),
-            21 => self.filter_sustain.get(),
-            22 => self.filter_release.get(),
-            23 => self.pitch_lfo_depth.get(),
-            24 => self.pitch_lfo_rate.get(),
-            25 => self.num_voices.get(),
-            26 => self.portamento.get(),
-            _ => 0.0
-        }
-    }
-
-    fn set_parameter(&self, index: i32, value: f32) {
-        match index {
-            0 => self.oscillator_one_type.set(value),
-            1 => self.oscillator_one_pulsewidth.set(value),
-            2 => self.oscillator_one_octave.set(value),
-            3 => self.oscillator_one_semitone.set(value),
-            4 => self.oscillator_one_fine.set(value),
-            5 => self.oscillator_one_volume.set(value),
-            6 => self.oscillator_two_type.set(value),
-            7 => self.oscillator_two_pulsewidth.set(value),
-            8 => self.oscillator_two_octave.set(value),
-            9 => self.oscillator_two_semitone.set(value),
-            10 => self.oscillator_two_fine.set(value),
-            11 => self.oscillator_two_volume.set(value),            
-            12 => self.attack.set(value),
-            13 => self.decay.set(value),
-            14 => self.sustain.set(value),
-            15 => self.release.set(value),
-            16 => self.filter_type.set(value),
-            17 => self.filter_cutoff.set(value),
-            18 => self.filter_resonance.set(value),
-            19 => self.filter_attack.set(value),
-            20 => self.filter_decay.set(value),
-            21 => self.filter_sustain.set(value),
-            22 => self.filter_release.set(value),
-            23 => self.pitch_lfo_depth.set(value),
-            24 => self.pitch_lfo_rate.set(value),
-            25 => self.num_voices.set(value),
-            26 => self.portamento.set(value),
-            _ => ()
+    parameter_methods!();
+
+    fn change_preset(&self, preset: i32) {
+        let index = preset as usize;
+        if preset >= 0 && index < self.bank.count() && index != self.bank.current() {
+            self.set_parameter_values(&self.bank.switch(index, &self.parameter_values()));
         }
     }
 
-    fn get_parameter_label(&self, index: i32) -> String {
-        match index {
-            4 => "ct",
-            10 => "ct",                      
-            12 => "s",
-            13 => "s",            
-            15 => "s",            
-            17 => "Hz",
-            19 => "s",
-            20 => "s",
-            22 => "s",
-            23 => "%",
-            24 => "Hz",
-            26 => "s",
-            _ => ""
-        }.to_string()
+    fn get_preset_num(&self) -> i32 {
+        self.bank.current() as i32
+    }
+
+    fn set_preset_name(&self, name: String) {
+        self.bank.rename_current(name);
+    }
+
+    fn get_preset_name(&self, preset: i32) -> String {
+        self.bank.name(preset as usize)
+    }
+
+    // the host saves this instead of the parameter values so the tuning goes with the project
+    fn get_preset_data(&self) -> Vec<u8> {
+        self.program_state().write()
+    }
+
+    fn load_preset_data(&self, data: &[u8]) {
+        if let Some(state) = State::read(data) {
+            self.load_program_state(state);
+        }
+    }
+
+    // the current program's state plus every program in the bank
+    fn get_bank_data(&self) -> Vec<u8> {
+        let mut state = self.program_state();
+        state.add_section(program::BANK_SECTION, self.bank.write(&state.parameters));
+        state.write()
+    }
+
+    // a state saved as a preset (or before there were programs) loads into the current program
+    fn load_bank_data(&self, data: &[u8]) {
+        if let Some(state) = State::read(data) {
+            if let Some(bank) = state.section(program::BANK_SECTION) {
+                self.bank.read(bank, state.version, &MIGRATIONS);
+            }
+            self.load_program_state(state);
+        }
     }
 }
 
 impl Plugin for Synth {
-    fn new(_host: HostCallback) -> Self {        
-        Synth {
-            oscillators_one: vec![oscillator::Oscillator::default(); VOICES],            
-            oscillators_two: vec![oscillator::Oscillator::default(); VOICES],            
-            pitch_lfo: oscillator::LFO::default(),
-            params: Arc::new(SynthParameters::default()),        
-            last_played_osc_index: 0,
-            current_num_voices: 8,
-            monophonic: false,
-            filters: vec![filter::Filter::default(); VOICES],
-            active_notes: vec![],
-            active_velocities: vec![],
-            sample_rate: 44100.0
-        }
+    fn new(host: HostCallback) -> Self {
+        Synth::with_params(host, SynthParameters::with_library())
     }
 
     fn get_info(&self) -> Info {
@@ -303,20 +707,22 @@ impl Plugin for Synth {
             unique_id: 129154,
             version: 1,            
             outputs: 2,
-            parameters: 27,
+            parameters: PARAMETERS,
+            presets: self.params.bank.count() as i32,
             category: Category::Synth,
+            preset_chunks: true,
             ..Default::default()
         }
     }
 
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        type P = SynthParameters;
+        const MSEG_CURVES: [i32; mseg::MAX_POINTS] = [P::MSEG_1_CURVE, P::MSEG_2_CURVE, P::MSEG_3_CURVE, P::MSEG_4_CURVE, P::MSEG_5_CURVE, P::MSEG_6_CURVE, P::MSEG_7_CURVE, P::MSEG_8_CURVE];
         // check to see if the amount of voices has gone down - cancel any notes that we need to if it has
-        let voices = ((self.params.num_voices.get() * 7.0) + 1.0) as usize;
+        let voices = self.params.plain(P::NUM_VOICES) as usize;
         if self.current_num_voices > voices {
-            for i in voices..VOICES {
-                self.oscillators_one[i].note_off();
-                self.oscillators_two[i].note_off();
-                self.filters[i].end_note();
+            for voice in self.voices.iter_mut().skip(voices) {
+                voice.note_off();
             }
             // make sure the next note we play is on the first oscillator in the vectors
             if voices == 1 {
@@ -325,80 +731,213 @@ impl Plugin for Synth {
         }
         self.current_num_voices = voices;
         self.monophonic = self.current_num_voices == 1;
+        self.update_program();
+        self.update_tuning();
+        let tempo = self.tempo();
+        // every value is read in its own units through the parameter table, once a buffer, before any voice gets it
+        let params = &self.params;
+        let points: [mseg::Point; mseg::MAX_POINTS] = std::array::from_fn(|i| mseg::Point {
+            level: params.mseg_level[i].get(),
+            time: params.mseg_segment_time(i, tempo),
+            curve: params.fraction(MSEG_CURVES[i])
+        });
+        let analog = params.fraction(P::ANALOG);
+        let portamento = params.plain(P::PORTAMENTO);
+        let transpose = params.plain(P::TRANSPOSE) as i8;
+        let master_tune = params.plain(P::MASTER_TUNE);
+        // octave, semitone and fine for each oscillator
+        let [pitch_one, pitch_two] = [
+            [P::OSCILLATOR_ONE_OCTAVE, P::OSCILLATOR_ONE_SEMITONE, P::OSCILLATOR_ONE_FINE],
+            [P::OSCILLATOR_TWO_OCTAVE, P::OSCILLATOR_TWO_SEMITONE, P::OSCILLATOR_TWO_FINE]
+        ].map(|ids| ids.map(|id| params.plain(id)));
+        let [start_phase_one, start_phase_two] = [P::OSCILLATOR_ONE_START_PHASE, P::OSCILLATOR_TWO_START_PHASE].map(|id| params.plain(id) / 360.0);
+        // attack, decay, release, delay and hold for each envelope
+        let [amp_times, filter_times, pwm_times] = [
+            [P::ATTACK, P::DECAY, P::RELEASE, P::ENVELOPE_DELAY, P::ENVELOPE_HOLD],
+            [P::FILTER_ATTACK, P::FILTER_DECAY, P::FILTER_RELEASE, P::FILTER_DELAY, P::FILTER_HOLD],
+            [P::PWM_ATTACK, P::PWM_DECAY, P::PWM_RELEASE, P::PWM_DELAY, P::PWM_HOLD]
+        ].map(|ids| ids.map(|id| params.plain(id)));
+        // attack, decay and release curves for each envelope
+        let [amp_curves, filter_curves, pwm_curves] = [
+            [P::ATTACK_CURVE, P::DECAY_CURVE, P::RELEASE_CURVE],
+            [P::FILTER_ATTACK_CURVE, P::FILTER_DECAY_CURVE, P::FILTER_RELEASE_CURVE],
+            [P::PWM_ATTACK_CURVE, P::PWM_DECAY_CURVE, P::PWM_RELEASE_CURVE]
+        ].map(|ids| ids.map(|id| params.fraction(id)));
+        // velocity to attack, velocity to amount and key follow for each envelope
+        let [amp_scaling, filter_scaling, pwm_scaling] = [
+            [P::ENVELOPE_VELOCITY_TO_ATTACK, P::ENVELOPE_VELOCITY_TO_AMOUNT, P::ENVELOPE_KEY_FOLLOW],
+            [P::FILTER_VELOCITY_TO_ATTACK, P::FILTER_VELOCITY_TO_AMOUNT, P::FILTER_KEY_FOLLOW],
+            [P::PWM_VELOCITY_TO_ATTACK, P::PWM_VELOCITY_TO_AMOUNT, P::PWM_KEY_FOLLOW]
+        ].map(|ids| ids.map(|id| params.fraction(id)));
+        // cutoff, resonance and envelope amount for each filter
+        let [filter_one, filter_two] = [
+            [P::FILTER_CUTOFF, P::FILTER_RESONANCE, P::FILTER_ENV_AMOUNT],
+            [P::FILTER_TWO_CUTOFF, P::FILTER_TWO_RESONANCE, P::FILTER_TWO_ENV_AMOUNT]
+        ].map(|ids| ids.map(|id| params.plain(id)));
+        let filter_key_track = params.fraction(P::FILTER_KEY_TRACK);
+        let filter_velocity = params.fraction(P::FILTER_VELOCITY);
+        let [oscillator_one_velocity, oscillator_two_velocity, sub_velocity, noise_velocity] =
+            [P::OSCILLATOR_ONE_VELOCITY, P::OSCILLATOR_TWO_VELOCITY, P::SUB_VELOCITY, P::NOISE_VELOCITY].map(|id| params.fraction(id));
+        let filter_fm_amount = params.plain(P::FILTER_FM_AMOUNT);
+        let filter_balance = params.fraction(P::FILTER_BALANCE);
+        let pwm_depth = params.plain(P::PWM_DEPTH);
+        let drive = params.plain(P::DRIVE);
+        let saturation = params.plain(P::SATURATION);
+        let mseg_points = params.plain(P::MSEG_POINTS) as usize;
+        let mseg_loop_start = params.plain(P::MSEG_LOOP_START) as usize - 1;
+        let mseg_amount = params.fraction(P::MSEG_AMOUNT);
         // i would very much like to NOT have to calculate these each buffer - need a way to call from the parameters set_parameter...        
-        for i in 0..VOICES {
-            self.oscillators_one[i].set_params(
-                    match self.params.oscillator_one_type.get() {
-                    t if t < 0.25 => OscillatorType::Saw,
-                    t if t < 0.5 => OscillatorType::Pulse,
-                    t if t < 0.75 => OscillatorType::Triangle,
-                    _ => OscillatorType::Sin
-                }, 
-                self.params.oscillator_one_pulsewidth.get(), 
-                self.params.oscillator_one_octave.get(),
-                (self.params.portamento.get().powi(4) * 9.999) + 0.001,
-                (self.params.oscillator_one_semitone.get() * 48.0) as i8 - 24,
-                (self.params.oscillator_one_fine.get() * 200.0) - 100.0
+        for voice in self.voices.iter_mut() {
+            voice.set_analog(analog);
+            let time_scale = voice.get_envelope_time_scale();
+
+            voice.oscillator_one.set_params(
+                OscillatorType::from_value(params.oscillator_one_type.get()),
+                params.oscillator_one_pulsewidth.get(), 
+                portamento
+            );
+
+            voice.oscillator_one.set_pitch(
+                pitch_one[0] as i8,
+                pitch_one[1] as i8,
+                pitch_one[2],
+                transpose,
+                master_tune
+            );
+
+            voice.oscillator_one.set_phase_params(PhaseMode::from_value(params.oscillator_one_phase_mode.get()), start_phase_one);
+
+            voice.oscillator_two.set_params(
+                OscillatorType::from_value(params.oscillator_two_type.get()),
+                params.oscillator_two_pulsewidth.get(), 
+                portamento
+            );
+
+            voice.oscillator_two.set_pitch(
+                pitch_two[0] as i8,
+                pitch_two[1] as i8,
+                pitch_two[2],
+                transpose,
+                master_tune
+            );
+
+            voice.oscillator_two.set_phase_params(PhaseMode::from_value(params.oscillator_two_phase_mode.get()), start_phase_two);
+
+            voice.amp_envelope.set_params(
+                amp_times[0] * time_scale, 
+                amp_times[1] * time_scale, 
+                params.sustain.get(), 
+                amp_times[2] * time_scale
+            );
+
+            voice.amp_envelope.set_delay_hold(amp_times[3] * time_scale, amp_times[4] * time_scale);
+            voice.amp_envelope.set_curves(amp_curves[0], amp_curves[1], amp_curves[2]);
+            voice.amp_envelope.set_scaling(amp_scaling[0], amp_scaling[1], amp_scaling[2]);
+            voice.amp_envelope.set_retrigger(retrigger(params.retrigger.get()));
+
+            voice.filter_one.set_params(
+                filter_one[0], 
+                filter_one[1], 
+                filter::FilterType::from_value(params.filter_type.get()),
+                filter_key_track,
+                filter_velocity,
+                filter_one[2]
+            );
+
+            voice.filter_two.set_params(
+                filter_two[0], 
+                filter_two[1], 
+                filter::FilterType::from_value(params.filter_two_type.get()),
+                filter_key_track,
+                filter_velocity,
+                filter_two[2]
             );
 
-            self.oscillators_one[i].envelope.set_params(
-                self.params.attack.get().powi(2) * 10.0, 
-                self.params.decay.get().powi(2) * 10.0, 
-                self.params.sustain.get(), 
-                self.params.release.get().powi(2) * 10.0
+            voice.filter_envelope.set_params(
+                filter_times[0] * time_scale, 
+                filter_times[1] * time_scale, 
+                params.filter_sustain.get(), 
+                filter_times[2] * time_scale
             );
 
-            self.oscillators_two[i].set_params(
-                match self.params.oscillator_two_type.get() {
-                    t if t < 0.25 => OscillatorType::Saw,
-                    t if t < 0.5 => OscillatorType::Pulse,
-                    t if t < 0.75 => OscillatorType::Triangle,
-                    _ => OscillatorType::Sin
-                }, 
-                self.params.oscillator_two_pulsewidth.get(), 
-                self.params.oscillator_two_octave.get(),
-                (self.params.portamento.get().powi(4) * 9.999) + 0.001,
-                (self.params.oscillator_two_semitone.get() * 48.0) as i8 - 24,
-                (self.params.oscillator_two_fine.get() * 200.0) - 100.0
+            voice.filter_envelope.set_delay_hold(filter_times[3] * time_scale, filter_times[4] * time_scale);
+            voice.filter_envelope.set_curves(filter_curves[0], filter_curves[1], filter_curves[2]);
+            voice.filter_envelope.set_scaling(filter_scaling[0], filter_scaling[1], filter_scaling[2]);
+            voice.filter_envelope.set_retrigger(retrigger(params.retrigger.get()));
+
+            voice.mixer.set_channel(mixer::Source::OscillatorOne, params.oscillator_one_volume.get(), oscillator_one_velocity);
+            voice.mixer.set_channel(mixer::Source::OscillatorTwo, params.oscillator_two_volume.get(), oscillator_two_velocity);
+            voice.mixer.set_channel(mixer::Source::SubOscillator, params.sub_volume.get(), sub_velocity);
+            voice.mixer.set_channel(mixer::Source::Noise, params.noise_volume.get(), noise_velocity);
+            voice.mixer.set_curve(mixer::VelocityCurve::from_value(params.velocity_curve.get()));
+
+            voice.set_params(
+                filter_fm_amount,
+                parameter::step(params.filter_fm_source.get(), 2) == 0,
+                voice::FilterRouting::from_value(params.filter_routing.get()),
+                filter_balance,
+                voice::PwmSource::from_value(params.pwm_source.get()),
+                pwm_depth
             );
 
-            self.oscillators_two[i].envelope.set_params(
-                self.params.attack.get().powi(2) * 10.0, 
-                self.params.decay.get().powi(2) * 10.0, 
-                self.params.sustain.get(), 
-                self.params.release.get().powi(2) * 10.0
+            voice.pwm_envelope.set_params(
+                pwm_times[0] * time_scale, 
+                pwm_times[1] * time_scale, 
+                params.pwm_sustain.get(), 
+                pwm_times[2] * time_scale
             );
 
-            self.filters[i].set_params(
-                self.params.filter_cutoff.get().powi(3), 
-                self.params.filter_resonance.get(), 
-                self.params.filter_type.get(), 
-                self.params.filter_attack.get().powi(2) * 10.0, 
-                self.params.filter_decay.get().powi(2) * 10.0, 
-                self.params.filter_sustain.get(), 
-                self.params.filter_release.get().powi(2) * 10.0
-            );   
+            voice.pwm_envelope.set_delay_hold(pwm_times[3] * time_scale, pwm_times[4] * time_scale);
+            voice.pwm_envelope.set_curves(pwm_curves[0], pwm_curves[1], pwm_curves[2]);
+            voice.pwm_envelope.set_scaling(pwm_scaling[0], pwm_scaling[1], pwm_scaling[2]);
+            voice.pwm_envelope.set_retrigger(retrigger(params.retrigger.get()));
+
+            voice.drive.set_params(ShaperType::from_value(params.drive_type.get()), drive);
+            voice.saturation.set_params(ShaperType::from_value(params.saturation_type.get()), saturation);
+
+            voice.noise.set_params(noise::NoiseType::from_value(params.noise_type.get()));
+
+            voice.sub_oscillator.set_params(
+                SubOscillatorType::from_value(params.sub_type.get()),
+                parameter::step(params.sub_octave.get(), 2) as u8 + 1
+            );
+
+            for (i, point) in points.iter().enumerate() {
+                voice.mseg.set_point(i, *point);
+            }
+            voice.mseg.set_shape(
+                mseg_points,
+                mseg_loop_start,
+                mseg_loop_point(params.mseg_loop_end.get())
+            );
+            voice.set_mseg_routing(mseg::Destination::from_value(params.mseg_destination.get()), mseg_amount);
         }        
 
-        self.pitch_lfo.set_params(self.params.pitch_lfo_depth.get().powi(2), (self.params.pitch_lfo_rate.get() * 19.9) + 0.1);
+        self.pitch_lfo.set_params(params.plain(P::PITCH_LFO_DEPTH), params.plain(P::PITCH_LFO_RATE));
+        let bend_range = params.plain(P::BEND_RANGE);
+        self.filter_lfo.set_params(params.plain(P::FILTER_LFO_DEPTH), params.plain(P::FILTER_LFO_RATE));
+        self.pwm_lfo.set_params(1.0, params.plain(P::PWM_LFO_RATE));
         let samples = buffer.samples();
         let (_, mut outputs) = buffer.split();
-        let output_count = outputs.len();                
 
-        for sample in 0..samples {
-            let pitch_lfo_amt = self.pitch_lfo.process();
-            let mut sample_value = 0.0;
-            for i in 0..VOICES {
-                let samp = 
-                    (self.oscillators_one[i].process_with_pitch_mod(pitch_lfo_amt) * self.params.oscillator_one_volume.get()) 
-                    + (self.oscillators_two[i].process_with_pitch_mod(pitch_lfo_amt) * self.params.oscillator_two_volume.get());
-                sample_value += self.filters[i].process(samp);
-            }
-            for buf_idx in 0..output_count {
-                let buff = outputs.get_mut(buf_idx);
-                buff[sample] = sample_value;
+        // render up to each event's frame, then apply it - anything past the end of the buffer lands on the last frame
+        let mut events = std::mem::take(&mut self.events);
+        let mut sysex = std::mem::take(&mut self.sysex);
+        let mut start = 0;
+        for (frame, event) in events.drain(..) {
+            let frame = frame.min(samples);
+            self.render(&mut outputs, start, frame, bend_range);
+            start = frame;
+            match event {
+                PendingEvent::Midi(data) => self.process_midi_event(data),
+                PendingEvent::SysEx(payload) => self.process_sysex_event(&sysex[payload])
             }
         }
+        self.render(&mut outputs, start, samples, bend_range);
+        // hand the vecs back so their space gets reused
+        sysex.clear();
+        self.events = events;
+        self.sysex = sysex;
     }
 
     fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
@@ -408,42 +947,200 @@ impl Plugin for Synth {
     fn can_do(&self, can_do: CanDo) -> Supported {
         match can_do {
             CanDo::ReceiveMidiEvent => Supported::Yes,
+            CanDo::ReceiveSysExEvent => Supported::Yes,
             _ => Supported::Maybe
         }
     }
 
+    // start from the same random state every time processing starts, so an offline render of the
+    // same project with analog drift/noise/random phase comes out identical
+    fn resume(&mut self) {
+        self.voices = Synth::create_voices();
+        self.pitch_lfo = oscillator::LFO::default();
+        self.filter_lfo = oscillator::LFO::default();
+        self.pwm_lfo = oscillator::LFO::default();
+        self.update_context();
+        self.last_played_osc_index = 0;
+        self.active_notes.clear();
+        self.active_velocities.clear();
+        self.program_values = Vec::with_capacity(PARAMETERS as usize);
+        self.events = Vec::with_capacity(EVENTS);
+        self.sysex = Vec::with_capacity(SYSEX_BYTES);
+    }
+
     fn set_sample_rate(&mut self, rate: f32) {       
-        self.sample_rate = rate;
-        for i in 0..VOICES {
-            self.oscillators_one[i].set_sample_rate(self.sample_rate);
-            self.oscillators_two[i].set_sample_rate(self.sample_rate);
-            self.filters[i].set_sample_rate(self.sample_rate);
-        }                
+        self.context = context::Context::new(rate);
+        self.update_context();
     }
 
+    // held until process, which applies each one at its frame
     fn process_events(&mut self, events: &Events) {
         for event in events.events() {
             match event {
-                Event::Midi(ev) => self.process_midi_event(ev.data),
+                Event::Midi(ev) => self.queue_event(ev.delta_frames, PendingEvent::Midi(ev.data)),
+                Event::SysEx(ev) => self.queue_sysex(ev.delta_frames, ev.payload),
                 _ => ()
             }
         }
     }
 }
 
+// a preset saved into the user library is listed after the factory programs the next time the plugin loads
+impl Library for Synth {
+    fn save_preset(&self, dir: &Path) -> Result<PathBuf, String> {
+        let preset = presets::current(&self.params);
+        preset.save(dir).map_err(|e| format!("couldn't save {} in {}: {}", preset.name, dir.display(), e))
+    }
+
+    fn import_preset(&self, path: &Path) -> Result<(), String> {
+        let program = presets::import(path, &self.params).map_err(|e| format!("couldn't import {}: {}", path.display(), e))?;
+        self.params.bank.rename_current(program.name);
+        Ok(())
+    }
+}
+
 impl Synth {
+    // new picks up the user's library, the tests start from the factory defaults so they don't depend on the machine
+    fn with_params(host: HostCallback, params: SynthParameters) -> Synth {
+        Synth {
+            host,
+            voices: Synth::create_voices(),
+            pitch_lfo: oscillator::LFO::default(),
+            filter_lfo: oscillator::LFO::default(),
+            pwm_lfo: oscillator::LFO::default(),
+            params: Arc::new(params),
+            last_played_osc_index: 0,
+            current_num_voices: 8,
+            monophonic: false,
+            active_notes: vec![],
+            active_velocities: vec![],
+            context: context::Context::default(),
+            tuning: tuning::Tuning::default(),
+            tuning_index: 0,
+            tuning_changed: false,
+            pitch_bend: 0.0,
+            pending_program: None,
+            program_values: vec![],
+            events: vec![],
+            sysex: vec![]
+        }
+    }
+
+    // the frames from start up to end, with the parameters as process left them
+    fn render(&mut self, outputs: &mut Outputs<f32>, start: usize, end: usize, bend_range: f32) {
+        let bend = self.pitch_bend * bend_range;
+        for sample in start..end {
+            let pitch_mod = self.pitch_lfo.process() + bend;
+            let filter_lfo_amt = self.filter_lfo.process();
+            let pwm_lfo_amt = self.pwm_lfo.process();
+            let mut sample_value = 0.0;
+            for voice in self.voices.iter_mut() {
+                sample_value += voice.process(pitch_mod, filter_lfo_amt, pwm_lfo_amt);
+            }
+            for buf_idx in 0..outputs.len() {
+                let buff = outputs.get_mut(buf_idx);
+                buff[sample] = sample_value;
+            }
+        }
+    }
+
+    // every voice gets a fixed seed from its index
+    fn create_voices() -> Vec<voice::Voice> {
+        (0..VOICES).map(|i| voice::Voice::new(i as u32 + 1)).collect()
+    }
+
+    // the host's tempo in bpm - without a host behind the callback (e.g. in tests) asking would panic
+    fn tempo(&self) -> f32 {
+        if self.host.raw_callback().is_none() {
+            return DEFAULT_TEMPO;
+        }
+        match self.host.get_time_info(TimeInfoFlags::TEMPO_VALID.bits()) {
+            Some(info) if TimeInfoFlags::from_bits_truncate(info.flags).contains(TimeInfoFlags::TEMPO_VALID) => info.tempo as f32,
+            _ => DEFAULT_TEMPO
+        }
+    }
+
+    // hand the context to everything that keeps time
+    fn update_context(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.set_context(self.context);
+        }
+        self.pitch_lfo.set_context(self.context);
+        self.filter_lfo.set_context(self.context);
+        self.pwm_lfo.set_context(self.context);
+    }
+
+    // any channel, the low nibble of the status is ignored
     fn process_midi_event(&mut self, data: [u8; 3]) {
-        match data[0] {
+        match data[0] & 0xf0 {
             128 => self.note_off(data[1]),
             144 => self.note_on(data[1], data[2]),
+            192 => self.program_change(data[1]),
+            224 => self.set_pitch_bend(data[1], data[2]),
             _ => ()
         }
     }
 
+    fn program_change(&mut self, program: u8) {
+        if usize::from(program) < self.params.bank.count() {
+            self.pending_program = Some(usize::from(program));
+        }
+    }
+
+    // switches to a program picked with a MIDI program change - never waits on the bank, it tries again next buffer instead
+    fn update_program(&mut self) {
+        if let Some(index) = self.pending_program {
+            let params = &self.params;
+            self.program_values.clear();
+            self.program_values.extend((0..PARAMETERS).map(|i| params.get_parameter(i)));
+            if params.bank.try_switch(index, &mut self.program_values) {
+                params.set_parameter_values(&self.program_values);
+                self.pending_program = None;
+                if self.host.raw_callback().is_some() {
+                    self.host.update_display();
+                }
+            }
+        }
+    }
+
+    // 14 bit, 8192 is the middle
+    fn set_pitch_bend(&mut self, lsb: u8, msb: u8) {
+        let value = (u16::from(msb) << 7) | u16::from(lsb);
+        self.pitch_bend = ((f32::from(value) - 8192.0) / 8192.0).max(-1.0);
+    }
+
+    // copied into the room made in resume, or dropped if there isn't enough of it left
+    fn queue_sysex(&mut self, delta_frames: i32, payload: &[u8]) {
+        if payload.len() <= self.sysex.capacity() - self.sysex.len() {
+            let start = self.sysex.len();
+            self.sysex.extend_from_slice(payload);
+            self.queue_event(delta_frames, PendingEvent::SysEx(start..self.sysex.len()));
+        }
+    }
+
+    // after anything already waiting for the same frame, so events keep the host's order without being sorted
+    fn queue_event(&mut self, delta_frames: i32, event: PendingEvent) {
+        let frame = delta_frames.max(0) as usize;
+        let at = self.events.iter().rposition(|(f, _)| *f <= frame).map_or(0, |i| i + 1);
+        self.events.insert(at, (frame, event));
+    }
+
+    // only MTS tuning messages for now
+    fn process_sysex_event(&mut self, payload: &[u8]) {
+        if self.tuning.apply_sysex(payload) {
+            self.tuning_changed = true;
+            self.retune();
+        }
+    }
+
     fn note_on(&mut self, note: u8, vel: u8) {    
-        self.oscillators_one[self.last_played_osc_index].note_on(note, vel, self.monophonic); 
-        self.oscillators_two[self.last_played_osc_index].note_on(note, vel, self.monophonic);        
-        self.filters[self.last_played_osc_index].start_note();
+        // keys the tuning leaves unmapped don't play
+        let pitch = match self.tuning.pitch(note) {
+            Some(pitch) => pitch,
+            None => return
+        };
+        let legato = self.monophonic && RetriggerMode::from_value(self.params.retrigger.get()) == RetriggerMode::Legato && !self.active_notes.is_empty();
+        self.voices[self.last_played_osc_index].note_on(note, pitch, vel, self.monophonic, legato);
         self.last_played_osc_index = (self.last_played_osc_index + 1) % self.current_num_voices;
         if !self.active_notes.contains(&note) {
             self.active_notes.push(note);
@@ -451,14 +1148,47 @@ impl Synth {
         }
     }
 
-    fn note_off(&mut self, note: u8) {     
-        for i in 0..VOICES {
-            if self.oscillators_one[i].get_current_note() == note {
-                self.oscillators_one[i].note_off();
-                self.filters[i].end_note();
+    // picks up a newly loaded state or a new choice from the tuning parameter, and hands any changes
+    // back to the parameters so they get saved - never waits on the lock, it tries again next buffer instead
+    fn update_tuning(&mut self) {
+        if self.params.tuning_loaded.load(Ordering::Acquire) {
+            let loaded = self.params.current_tuning.try_lock().ok().map(|t| t.clone());
+            if let Some(loaded) = loaded {
+                self.params.tuning_loaded.store(false, Ordering::Release);
+                self.tuning = loaded;
+                self.tuning_index = self.params.tuning_index();
+                self.tuning_changed = false;
+                self.retune();
             }
-            if self.oscillators_two[i].get_current_note() == note {
-                self.oscillators_two[i].note_off();
+        }
+        let index = self.params.tuning_index();
+        if index != self.tuning_index {
+            self.tuning = self.params.tunings[index].clone();
+            self.tuning_index = index;
+            self.tuning_changed = true;
+            self.retune();
+        }
+        if self.tuning_changed {
+            if let Ok(mut current) = self.params.current_tuning.try_lock() {
+                *current = self.tuning.clone();
+                self.tuning_changed = false;
+            }
+        }
+    }
+
+    // move held notes to the current tuning
+    fn retune(&mut self) {
+        for voice in self.voices.iter_mut() {
+            if let Some(pitch) = self.tuning.pitch(voice.get_current_note()) {
+                voice.retune(pitch);
+            }
+        }
+    }
+
+    fn note_off(&mut self, note: u8) {     
+        for voice in self.voices.iter_mut() {
+            if voice.get_current_note() == note {
+                voice.note_off();
             }
         }
         if self.active_notes.contains(&note) {
@@ -466,10 +1196,553 @@ impl Synth {
             self.active_notes.remove(idx);
             self.active_velocities.remove(idx);
         }
-        if self.monophonic && self.active_notes.len() > 0 {
+        if self.monophonic && !self.active_notes.is_empty() {
             self.note_on(*self.active_notes.last().unwrap(), *self.active_velocities.last().unwrap());
         }
     }
 }
 
-plugin_main!(Synth);
\ No newline at end of file
+#[cfg(feature = "entry")]
+plugin_main_with_properties!(Synth, SynthParameters);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vst::buffer::SendEventBuffer;
+    use vst::event::MidiEvent;
+
+    type P = SynthParameters;
+
+    #[test]
+    fn octave_text_matches_the_octave_played() {
+        let params = SynthParameters::default();
+        for (value, expected, text) in [(0.0, -2, "-2"), (0.3, -1, "-1"), (0.5, 0, "0"), (0.7, 1, "+1"), (1.0, 2, "+2")].iter() {
+            params.set_parameter(P::OSCILLATOR_ONE_OCTAVE, *value);
+            assert_eq!(params.plain(P::OSCILLATOR_ONE_OCTAVE) as i8, *expected);
+            assert_eq!(params.get_parameter_text(P::OSCILLATOR_ONE_OCTAVE), *text);
+        }
+    }
+
+    #[test]
+    fn semitone_text_matches_the_semitone_played() {
+        let params = SynthParameters::default();
+        let semitone = |value| {
+            params.set_parameter(P::OSCILLATOR_ONE_SEMITONE, value);
+            params.plain(P::OSCILLATOR_ONE_SEMITONE) as i8
+        };
+        for value in [0.0, 0.25, 0.49, 0.5, 0.51, 0.99, 1.0].iter() {
+            let played = semitone(*value);
+            assert_eq!(params.get_parameter_text(P::OSCILLATOR_ONE_SEMITONE).parse::<i8>(), Ok(played));
+        }
+        assert_eq!(semitone(0.0), -24);
+        assert_eq!(semitone(0.5), 0);
+        assert_eq!(semitone(1.0), 24);
+        // values saved when there were 48 steps still land on the same semitones
+        for i in 0..=48 {
+            assert_eq!(semitone(i as f32 / 48.0), i as i8 - 24);
+        }
+    }
+
+    #[test]
+    fn discrete_parameters_report_their_steps() {
+        use rs_common::properties::properties;
+
+        let range = |id| properties::<SynthParameters>(id).map(|p| (p.min_integer, p.max_integer));
+        assert_eq!(range(P::OSCILLATOR_ONE_OCTAVE), Some((-2, 2)));
+        assert_eq!(range(P::NUM_VOICES), Some((1, 8)));
+        assert_eq!(range(P::MSEG_SYNC), Some((0, 1)));
+        assert_eq!(range(P::FILTER_CUTOFF), None);
+
+        let params = SynthParameters::default();
+        for (value, expected) in [(0.0, RetriggerMode::Retrigger), (0.4, RetriggerMode::Reset), (0.7, RetriggerMode::Legato), (1.0, RetriggerMode::Legato)].iter() {
+            params.set_parameter(P::RETRIGGER, *value);
+            assert_eq!(RetriggerMode::from_value(params.retrigger.get()), *expected);
+        }
+        assert_eq!(params.get_parameter_text(P::RETRIGGER), "Legato");
+        params.set_parameter(P::NUM_VOICES, 0.0);
+        assert_eq!(params.get_parameter_text(P::NUM_VOICES), "1");
+        assert_eq!(params.get_parameter_text(P::MSEG_POINTS), "5");
+    }
+
+    #[test]
+    fn typed_values_set_parameters_in_their_units() {
+        let params = SynthParameters::default();
+        assert!(params.string_to_parameter(P::FILTER_CUTOFF, "1200 Hz".to_string()));
+        assert_eq!(params.get_parameter_text(P::FILTER_CUTOFF), "1200.00");
+        assert!(params.string_to_parameter(P::PORTAMENTO, "0.5".to_string()));
+        assert_eq!(params.get_parameter_text(P::PORTAMENTO), "0.50");
+        assert!(params.string_to_parameter(P::DRIVE_TYPE, "arctan".to_string()));
+        assert_eq!(ShaperType::from_value(params.get_parameter(P::DRIVE_TYPE)), ShaperType::Arctan);
+        assert!(params.string_to_parameter(P::OSCILLATOR_ONE_OCTAVE, "+1".to_string()));
+        assert_eq!(params.plain(P::OSCILLATOR_ONE_OCTAVE), 1.0);
+        assert!(params.string_to_parameter(P::OSCILLATOR_ONE_SEMITONE, "-7".to_string()));
+        assert_eq!(params.plain(P::OSCILLATOR_ONE_SEMITONE), -7.0);
+        assert!(params.string_to_parameter(P::BEND_RANGE, "12 st".to_string()));
+        assert_eq!(params.plain(P::BEND_RANGE), 12.0);
+        assert!(params.string_to_parameter(P::TUNING, "12-TET".to_string()));
+        assert!(!params.string_to_parameter(P::TUNING, "Bohlen-Pierce".to_string()));
+        assert!(!params.string_to_parameter(P::FILTER_TYPE, "Notch".to_string()));
+        assert_eq!(params.get_parameter_text(P::FILTER_TYPE), "Lowpass");
+
+        assert!(params.string_to_parameter(P::MSEG_1_TIME, "0.4 s".to_string()));
+        assert_eq!(params.get_parameter_text(P::MSEG_1_TIME), "0.40 s");
+        params.set_parameter(P::MSEG_SYNC, 1.0);
+        assert!(params.string_to_parameter(P::MSEG_1_TIME, "1/16".to_string()));
+        assert_eq!(params.get_parameter_text(P::MSEG_1_TIME), "1/16");
+        assert!(!params.string_to_parameter(P::MSEG_1_TIME, "0.4 s".to_string()));
+    }
+
+    #[test]
+    fn every_parameter_has_one_entry_in_the_table() {
+        let params = SynthParameters::default();
+        for (i, (id, parameter)) in SynthParameters::TABLE.iter().enumerate() {
+            assert_eq!(*id, i as i32);
+            assert_eq!(params.get_parameter(*id), parameter.default, "{}", parameter.name);
+            assert_eq!(SynthParameters::TABLE.iter().filter(|(_, p)| p.name == parameter.name).count(), 1, "{}", parameter.name);
+        }
+        params.set_parameter(P::FILTER_CUTOFF, 0.5);
+        assert_eq!(params.get_parameter_text(P::FILTER_CUTOFF), "2517.50");
+        assert_eq!(params.get_parameter_label(P::FILTER_CUTOFF), "Hz");
+        assert!(!params.can_be_automated(P::TUNING));
+    }
+
+    // every parameter set to something other than its default, and A4 retuned a quarter tone sharp
+    fn changed_parameters() -> SynthParameters {
+        let params = SynthParameters::default();
+        for i in 0..PARAMETERS {
+            params.set_parameter(i, ((i as f32 * 0.37) + 0.1).fract());
+        }
+        assert!(params.current_tuning.lock().unwrap().apply_sysex(&[0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x01, 69, 69, 0x40, 0x00, 0xf7]));
+        params
+    }
+
+    fn assert_same_state(loaded: &SynthParameters, saved: &SynthParameters) {
+        for i in 0..PARAMETERS {
+            assert_eq!(loaded.get_parameter(i), saved.get_parameter(i), "parameter {} ({})", i, saved.get_parameter_name(i));
+        }
+        assert_eq!(*loaded.current_tuning.lock().unwrap(), *saved.current_tuning.lock().unwrap());
+        assert!(loaded.tuning_loaded.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let saved = changed_parameters();
+        for case in ["preset", "bank"].iter() {
+            let loaded = SynthParameters::default();
+            if *case == "bank" {
+                loaded.load_bank_data(&saved.get_bank_data());
+            }
+            else {
+                loaded.load_preset_data(&saved.get_preset_data());
+            }
+            assert_same_state(&loaded, &saved);
+            assert_eq!(loaded.get_preset_data(), saved.get_preset_data(), "{}", case);
+        }
+    }
+
+    #[test]
+    fn loads_steps_saved_with_the_old_mappings() {
+        // index, value, and the step the old mapping picked
+        let cases = [
+            (P::FILTER_TYPE, 0.333, "Bandpass"),
+            (P::FILTER_TYPE, 0.66, "Highpass"),
+            (P::FILTER_ROUTING, 0.5, "Parallel"),
+            (P::RETRIGGER, 0.65, "Reset"),
+            (P::NUM_VOICES, 0.99, "7"),
+            (P::NUM_VOICES, 1.0, "8"),
+            (P::OSCILLATOR_ONE_SEMITONE, 0.99, "+23"),
+            (P::TRANSPOSE, 0.0, "-24"),
+            (P::BEND_RANGE, 0.52, "12"),
+            (P::MSEG_POINTS, 0.25, "4"),
+            (P::MSEG_LOOP_START, 0.93, "8"),
+            (P::OSCILLATOR_ONE_TYPE, 0.3, "Pulse"),
+            (P::OSCILLATOR_ONE_OCTAVE, 0.3, "-1")
+        ];
+        for (index, value, text) in cases.iter() {
+            let mut parameters = SynthParameters::default().parameter_values();
+            parameters[*index as usize] = *value;
+            let loaded = SynthParameters::default();
+            loaded.load_preset_data(&State::new(0, parameters).write());
+            assert_eq!(loaded.get_parameter_text(*index), *text, "parameter {} at {}", index, value);
+        }
+
+        // synced mseg times were rounded, free ones are left alone
+        for (sync, text) in [(1.0, "1/32"), (0.0, &*SynthParameters::default().mseg_time_text(0.1))].iter() {
+            let mut parameters = SynthParameters::default().parameter_values();
+            parameters[P::MSEG_SYNC as usize] = *sync;
+            parameters[P::MSEG_1_TIME as usize] = 0.1;
+            let loaded = SynthParameters::default();
+            loaded.load_preset_data(&State::new(0, parameters).write());
+            assert_eq!(loaded.get_parameter_text(P::MSEG_1_TIME), *text);
+        }
+    }
+
+    #[test]
+    fn loads_state_from_a_newer_version() {
+        let saved = changed_parameters();
+        let mut state = State::read(&saved.get_preset_data()).unwrap();
+        state.version += 1;
+        state.parameters.push(0.5);
+        state.add_section(*b"NEW ", vec![1, 2, 3]);
+        let loaded = SynthParameters::default();
+        loaded.load_preset_data(&state.write());
+        assert_same_state(&loaded, &saved);
+    }
+
+    #[test]
+    fn ignores_state_that_isnt_whole() {
+        let data = changed_parameters().get_preset_data();
+        let cases: [(&str, Vec<u8>); 5] = [
+            ("empty", vec![]),
+            ("wrong magic", [&b"VST2"[..], &data[4..]].concat()),
+            ("truncated header", data[..10].to_vec()),
+            ("truncated parameters", data[..data.len() / 2].to_vec()),
+            ("truncated name", data[..data.len() - 1].to_vec())
+        ];
+        for (case, data) in cases.iter() {
+            let loaded = SynthParameters::default();
+            loaded.load_preset_data(data);
+            loaded.load_bank_data(data);
+            for i in 0..PARAMETERS {
+                assert_eq!(loaded.get_parameter(i), SynthParameters::default().get_parameter(i), "{} parameter {}", case, i);
+            }
+        }
+    }
+
+    #[test]
+    fn switching_programs_loads_them_and_keeps_edits() {
+        let params = SynthParameters::default();
+        assert_eq!(params.get_preset_name(0), "Init");
+        assert_eq!(params.get_preset_name(1), "Sub Bass");
+        params.change_preset(1);
+        assert_eq!(params.get_preset_num(), 1);
+        assert_eq!(params.get_parameter_text(P::NUM_VOICES), "1");
+        params.set_parameter(P::FILTER_CUTOFF, 0.9);
+        params.set_preset_name("My Bass".to_string());
+        params.change_preset(0);
+        assert_eq!(params.get_parameter(P::FILTER_CUTOFF), 1.0);
+        params.change_preset(1);
+        assert_eq!(params.get_parameter(P::FILTER_CUTOFF), 0.9);
+        assert_eq!(params.get_preset_name(1), "My Bass");
+        // out of range is ignored
+        params.change_preset(100);
+        params.change_preset(-1);
+        assert_eq!(params.get_preset_num(), 1);
+    }
+
+    #[test]
+    fn bank_round_trips_every_program() {
+        // the program selected when the bank is saved, and what it's renamed to
+        for (current, name) in [(0, "Edited"), (2, ""), (7, "Ünïcode \"quoted\"")].iter() {
+            let saved = changed_parameters();
+            saved.change_preset(*current);
+            saved.set_parameter(P::OSCILLATOR_ONE_TYPE, 0.6);
+            saved.set_preset_name(name.to_string());
+            let loaded = SynthParameters::default();
+            loaded.load_bank_data(&saved.get_bank_data());
+            assert_same_state(&loaded, &saved);
+            assert_eq!(loaded.get_preset_num(), *current);
+            // every program, including the ones left behind, kept its name and values
+            for program in 0..8 {
+                saved.change_preset(program);
+                loaded.change_preset(program);
+                assert_eq!(loaded.get_preset_name(program), saved.get_preset_name(program));
+                for i in 0..PARAMETERS {
+                    assert_eq!(loaded.get_parameter(i), saved.get_parameter(i), "program {} parameter {}", program, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn preset_loads_into_the_current_program() {
+        let saved = SynthParameters::default();
+        saved.change_preset(3);
+        let loaded = SynthParameters::default();
+        loaded.change_preset(5);
+        loaded.load_preset_data(&saved.get_preset_data());
+        assert_eq!(loaded.get_preset_num(), 5);
+        assert_eq!(loaded.get_preset_name(5), saved.get_preset_name(3));
+        for i in 0..PARAMETERS {
+            assert_eq!(loaded.get_parameter(i), saved.get_parameter(i));
+        }
+    }
+
+    #[test]
+    fn midi_program_change_selects_a_program() {
+        let mut synth = Synth::with_params(HostCallback::default(), SynthParameters::default());
+        synth.process_midi_event([192, 6, 0]);
+        synth.update_program();
+        assert_eq!(synth.params.get_preset_num(), 6);
+        assert_eq!(synth.params.get_parameter(P::ATTACK), 0.45);
+        // there's no program 100
+        synth.process_midi_event([192, 100, 0]);
+        synth.update_program();
+        assert_eq!(synth.params.get_preset_num(), 6);
+        // every channel is listened to
+        synth.process_midi_event([192 | 9, 2, 0]);
+        synth.update_program();
+        assert_eq!(synth.params.get_preset_num(), 2);
+        synth.process_midi_event([144 | 15, 60, 100]);
+        assert_eq!(synth.active_notes, [60]);
+        synth.process_midi_event([128 | 15, 60, 0]);
+        assert!(synth.active_notes.is_empty());
+    }
+
+    #[test]
+    fn notes_start_on_their_frame() {
+        for frame in [0, 1, 100, 400].iter() {
+            let mut synth = Synth::with_params(HostCallback::default(), SynthParameters::default());
+            synth.set_sample_rate(44100.0);
+            synth.resume();
+            let note_on = Event::Midi(MidiEvent {
+                data: [144, 69, 127],
+                delta_frames: *frame as i32,
+                live: false,
+                note_length: None,
+                note_offset: None,
+                detune: 0,
+                note_off_velocity: 0
+            });
+            SendEventBuffer::new(1).send_events_to_plugin([note_on], &mut synth);
+            let mut outputs = vec![vec![0.0; BLOCK_SIZE]; 2];
+            let inputs: Vec<*const f32> = vec![];
+            let mut pointers: Vec<*mut f32> = outputs.iter_mut().map(|o| o.as_mut_ptr()).collect();
+            let mut buffer = unsafe { AudioBuffer::from_raw(0, 2, inputs.as_ptr(), pointers.as_mut_ptr(), BLOCK_SIZE) };
+            synth.process(&mut buffer);
+            assert_eq!(peak(&outputs[0][..*frame]), 0.0, "sound before frame {}", frame);
+            assert!(peak(&outputs[0][*frame..]) > 0.0, "silence after frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn sysex_is_queued_in_the_room_made_for_it() {
+        let mut synth = Synth::with_params(HostCallback::default(), SynthParameters::default());
+        synth.set_sample_rate(44100.0);
+        synth.resume();
+        // A4 up a quarter tone, then one too big for the room there is
+        let retune = [0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x01, 69, 69, 0x40, 0x00, 0xf7];
+        let room = synth.sysex.as_ptr();
+        synth.queue_sysex(10, &retune);
+        synth.queue_sysex(0, &[0xf0; SYSEX_BYTES]);
+        assert_eq!(synth.events.len(), 1);
+        assert_eq!(synth.sysex.as_ptr(), room);
+
+        let mut outputs = vec![vec![0.0; BLOCK_SIZE]; 2];
+        let inputs: Vec<*const f32> = vec![];
+        let mut pointers: Vec<*mut f32> = outputs.iter_mut().map(|o| o.as_mut_ptr()).collect();
+        let mut buffer = unsafe { AudioBuffer::from_raw(0, 2, inputs.as_ptr(), pointers.as_mut_ptr(), BLOCK_SIZE) };
+        synth.process(&mut buffer);
+        assert_eq!(synth.tuning.pitch(69), Some(69.5));
+        assert!(synth.sysex.is_empty());
+        assert_eq!(synth.sysex.as_ptr(), room);
+    }
+
+    #[test]
+    fn events_keep_their_order_at_the_same_frame() {
+        let mut synth = Synth::with_params(HostCallback::default(), SynthParameters::default());
+        synth.resume();
+        let midi = |data, frame| Event::Midi(MidiEvent { data, delta_frames: frame, live: false, note_length: None, note_offset: None, detune: 0, note_off_velocity: 0 });
+        SendEventBuffer::new(4).send_events_to_plugin([midi([144, 60, 100], 5), midi([128, 60, 0], 5), midi([144, 62, 100], 0), midi([144, 64, 100], 9)], &mut synth);
+        let queued: Vec<(usize, [u8; 3])> = synth.events.iter().map(|(frame, event)| match event {
+            PendingEvent::Midi(data) => (*frame, *data),
+            PendingEvent::SysEx(_) => panic!("no SysEx was sent")
+        }).collect();
+        assert_eq!(queued, vec![(0, [144, 62, 100]), (5, [144, 60, 100]), (5, [128, 60, 0]), (9, [144, 64, 100])]);
+    }
+
+    fn temp_library(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("oscicrate_{}_{}", test, std::process::id()))
+    }
+
+    #[test]
+    fn renaming_a_program_only_renames_it() {
+        let synth = Synth::default();
+        synth.params.set_preset_name("My Init".to_string());
+        synth.params.set_preset_name("My Init 2".to_string());
+        assert_eq!(synth.params.get_preset_name(0), "My Init 2");
+        assert_eq!(synth.params.get_preset_name(1), presets::factory(&synth.params)[1].name);
+    }
+
+    // a preset keeps plain values, which don't always turn back into exactly the same 0 - 1 value, so
+    // values are the same if they're shown the same
+    fn assert_same_values(loaded: &[f32], saved: &SynthParameters) {
+        let params = SynthParameters::default();
+        params.set_parameter_values(loaded);
+        for i in 0..PARAMETERS {
+            assert_eq!(params.get_parameter_text(i), saved.get_parameter_text(i), "parameter {} ({})", i, saved.get_parameter_name(i));
+        }
+    }
+
+    #[test]
+    fn saved_presets_join_the_bank_and_import() {
+        let dir = temp_library("save");
+        let synth = Synth::default();
+        synth.params.change_preset(3);
+        synth.params.set_parameter(P::FILTER_CUTOFF, 0.9);
+        synth.params.set_preset_name("My Acid".to_string());
+        let saved = synth.save_preset(&dir);
+        let text = std::fs::read_to_string(dir.join("My Acid.toml"));
+        let programs = presets::with_library(&SynthParameters::default(), &dir);
+        // into another program
+        let imported = Synth::default();
+        let import = imported.import_preset(&dir.join("My Acid.toml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(saved, Ok(dir.join("My Acid.toml")));
+        let text = text.unwrap();
+        assert!(text.starts_with("name = \"My Acid\"\n"));
+        assert!(text.contains("\"Filter Cutoff\" = \"14585.418 Hz\"\n"));
+        assert!(text.contains("\"Osc 1 Type\" = \"Saw\"\n"));
+        assert_eq!(programs.len(), 9);
+        assert_eq!(programs[8].name, "My Acid");
+        assert_same_values(&programs[8].parameters, &synth.params);
+        assert_eq!(import, Ok(()));
+        assert_eq!(imported.params.get_preset_num(), 0);
+        assert_eq!(imported.params.get_preset_name(0), "My Acid");
+        assert_same_values(&imported.params.parameter_values(), &synth.params);
+    }
+
+    #[test]
+    fn library_failures_are_reported() {
+        let dir = temp_library("failures");
+        let synth = Synth::default();
+        assert!(synth.import_preset(&dir.join("Missing.toml")).unwrap_err().starts_with("couldn't import"));
+        // a file where the folder should be
+        std::fs::write(&dir, "").unwrap();
+        let saved = synth.save_preset(&dir);
+        std::fs::remove_file(&dir).unwrap();
+        assert!(saved.unwrap_err().starts_with("couldn't save Init in"));
+    }
+
+    #[test]
+    fn user_presets_fall_back_to_the_defaults() {
+        let dir = temp_library("defaults");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Bright.toml"), "name = \"Bright\"\n\n[parameters]\n\"Filter Cutoff\" = \"1200 Hz\"\n\"Not A Parameter\" = 0.5\n").unwrap();
+        let programs = presets::with_library(&SynthParameters::default(), &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let expected = SynthParameters::default();
+        expected.set_text(P::FILTER_CUTOFF, "1200 Hz");
+        assert_eq!(programs[8].name, "Bright");
+        assert_eq!(programs[8].parameters, expected.parameter_values());
+    }
+
+    const SAMPLE_RATES: [f32; 3] = [44100.0, 48000.0, 96000.0];
+    const BLOCK_SIZE: usize = 512;
+
+    // plays A4 at full velocity for note_length seconds and renders seconds of one output channel
+    fn render(sample_rate: f32, seconds: f32, note_length: f32, setup: impl Fn(&SynthParameters)) -> Vec<f32> {
+        let mut synth = Synth::with_params(HostCallback::default(), SynthParameters::default());
+        setup(&synth.params);
+        synth.set_sample_rate(sample_rate);
+        synth.resume();
+        synth.note_on(69, 127);
+        let total = (seconds * sample_rate) as usize;
+        let note_off = (note_length * sample_rate) as usize;
+        let mut rendered = Vec::with_capacity(total);
+        let mut outputs = vec![vec![0.0; BLOCK_SIZE]; 2];
+        let inputs: Vec<*const f32> = vec![];
+        while rendered.len() < total {
+            if rendered.len() >= note_off && rendered.len() < note_off + BLOCK_SIZE {
+                synth.note_off(69);
+            }
+            let mut pointers: Vec<*mut f32> = outputs.iter_mut().map(|o| o.as_mut_ptr()).collect();
+            let mut buffer = unsafe { AudioBuffer::from_raw(0, 2, inputs.as_ptr(), pointers.as_mut_ptr(), BLOCK_SIZE) };
+            synth.process(&mut buffer);
+            rendered.extend_from_slice(&outputs[0]);
+        }
+        rendered.truncate(total);
+        rendered
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, x| peak.max(x.abs()))
+    }
+
+    // when the level first reaches half its peak, within a cycle of A4
+    fn half_rise_time(samples: &[f32], sample_rate: f32) -> f32 {
+        let half = peak(samples) * 0.5;
+        samples.iter().position(|x| x.abs() >= half).unwrap() as f32 / sample_rate
+    }
+
+    // when the level last reaches half its peak, within a cycle of A4
+    fn half_fall_time(samples: &[f32], sample_rate: f32) -> f32 {
+        let half = peak(samples) * 0.5;
+        samples.iter().rposition(|x| x.abs() >= half).unwrap() as f32 / sample_rate
+    }
+
+    fn assert_time(sample_rate: f32, measured: f32, expected: f32, tolerance: f32) {
+        assert!((measured - expected).abs() < tolerance, "at {} Hz expected {} s, measured {} s", sample_rate, expected, measured);
+    }
+
+    #[test]
+    fn envelope_attack_takes_the_same_time_at_every_rate() {
+        for sample_rate in SAMPLE_RATES.iter() {
+            // a straight 0.1 s attack
+            let rendered = render(*sample_rate, 0.3, 0.3, |params| {
+                params.attack.set(0.1);
+                params.attack_curve.set(0.5);
+            });
+            assert_time(*sample_rate, half_rise_time(&rendered, *sample_rate), 0.05, 0.003);
+        }
+    }
+
+    #[test]
+    fn envelope_release_takes_the_same_time_at_every_rate() {
+        for sample_rate in SAMPLE_RATES.iter() {
+            // a straight 0.1 s release from a note let go at 0.2 s
+            let rendered = render(*sample_rate, 0.4, 0.2, |params| {
+                params.release.set(0.1);
+                params.release_curve.set(0.5);
+            });
+            // the note off lands at the start of the next block
+            let note_off = (0.2 * sample_rate / BLOCK_SIZE as f32).ceil() * BLOCK_SIZE as f32 / sample_rate;
+            assert_time(*sample_rate, half_fall_time(&rendered, *sample_rate), note_off + 0.05, 0.003);
+        }
+    }
+
+    #[test]
+    fn filter_envelope_takes_the_same_time_at_every_rate() {
+        for sample_rate in SAMPLE_RATES.iter() {
+            // a sine through a lowpass swept from 55 Hz up six octaves by a straight 0.1 s attack - the cutoff
+            // passes A4 half way through, where a Q of 0.707 lets the sine through at 1/sqrt(2) of its full level
+            let rendered = render(*sample_rate, 0.3, 0.3, |params| {
+                params.oscillator_one_type.set(1.0);
+                assert!(params.set_text(SynthParameters::FILTER_CUTOFF, "55"));
+                assert!(params.set_text(SynthParameters::FILTER_RESONANCE, "0.7071"));
+                assert!(params.set_text(SynthParameters::FILTER_ENV_AMOUNT, "6"));
+                params.filter_attack.set(0.1);
+                params.filter_attack_curve.set(0.5);
+            });
+            // the level over each cycle of A4, timed from the middle of the cycle
+            let cycle = (sample_rate / 440.0).ceil() as usize;
+            let levels: Vec<f32> = rendered.windows(cycle).map(peak).collect();
+            let through = levels[levels.len() - 1] * std::f32::consts::FRAC_1_SQRT_2;
+            let crossing = levels.iter().position(|level| *level >= through).unwrap() + (cycle / 2);
+            assert_time(*sample_rate, crossing as f32 / sample_rate, 0.05, 0.003);
+        }
+    }
+
+    #[test]
+    fn pitch_lfo_runs_at_the_same_rate_at_every_rate() {
+        for sample_rate in SAMPLE_RATES.iter() {
+            // a 2 Hz sine of about a semitone on a sine oscillator
+            let rendered = render(*sample_rate, 1.0, 1.0, |params| {
+                params.oscillator_one_type.set(1.0);
+                params.pitch_lfo_depth.set(0.3);
+                assert!(params.set_text(P::PITCH_LFO_RATE, "2 Hz"));
+            });
+            let mut crossings = vec![];
+            for (i, pair) in rendered.windows(2).enumerate() {
+                if pair[0] < 0.0 && pair[1] >= 0.0 {
+                    crossings.push((i as f32 + (pair[0] / (pair[0] - pair[1]))) / sample_rate);
+                }
+            }
+            // the lfo starts rising, so the pitch first falls back through A4 half way through its cycle
+            let falls = crossings.windows(3).position(|c| 1.0 / (c[1] - c[0]) >= 440.0 && 1.0 / (c[2] - c[1]) < 440.0).unwrap();
+            assert_time(*sample_rate, crossings[falls + 1], 0.25, 0.005);
+        }
+    }
+}
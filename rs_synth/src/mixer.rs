@@ -0,0 +1,66 @@
+use rs_common::parameter::Choice;
+
+#[derive(Clone, Copy)]
+pub enum Source {
+    OscillatorOne, OscillatorTwo, SubOscillator, Noise
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VelocityCurve {
+    Linear,
+    // velocity squared - soft notes drop off faster
+    Exponential,
+    // ignore velocity, every note plays at full level
+    Fixed
+}
+
+impl Choice for VelocityCurve {
+    const ALL: &'static [VelocityCurve] = &[VelocityCurve::Linear, VelocityCurve::Exponential, VelocityCurve::Fixed];
+    const NAMES: &'static [&'static str] = &["Linear", "Exponential", "Fixed"];
+}
+
+// per-voice levels for each source, scaled by how much each one responds to velocity
+#[derive(Clone)]
+pub struct Mixer {
+    levels: [f32; 4],
+    sensitivities: [f32; 4],
+    curve: VelocityCurve,
+    velocity: f32
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Mixer {
+            levels: [0.05, 0.05, 0.0, 0.0],
+            sensitivities: [1.0; 4],
+            curve: VelocityCurve::Linear,
+            velocity: 0.0
+        }
+    }
+}
+
+impl Mixer {
+    // sensitivity is 0 - 1, 0 means velocity has no effect on this source
+    pub fn set_channel(&mut self, source: Source, level: f32, sensitivity: f32) {
+        self.levels[source as usize] = level;
+        self.sensitivities[source as usize] = sensitivity;
+    }
+
+    pub fn set_curve(&mut self, curve: VelocityCurve) {
+        self.curve = curve;
+    }
+
+    pub fn set_velocity(&mut self, vel: u8) {
+        self.velocity = vel as f32 / 127.0;
+    }
+
+    pub fn gain(&self, source: Source) -> f32 {
+        let curved = match self.curve {
+            VelocityCurve::Linear => self.velocity,
+            VelocityCurve::Exponential => self.velocity * self.velocity,
+            VelocityCurve::Fixed => 1.0
+        };
+        let sensitivity = self.sensitivities[source as usize];
+        self.levels[source as usize] * ((1.0 - sensitivity) + (sensitivity * curved))
+    }
+}
@@ -0,0 +1,203 @@
+use crate::adsr;
+use crate::context::Context;
+
+use rs_common::parameter::Choice;
+
+pub const MAX_POINTS: usize = 8;
+
+// a breakpoint - the envelope ramps to level (0 - 1) over time seconds, bent by curve (-1 - 1, see adsr::shape)
+#[derive(Clone, Copy)]
+pub struct Point {
+    pub level: f32,
+    pub time: f32,
+    pub curve: f32
+}
+
+// where the envelope goes, the amount is -1 - 1
+#[derive(Clone, Copy, PartialEq)]
+pub enum Destination {
+    Off, Pitch, Cutoff, PulseWidth, Volume
+}
+
+impl Choice for Destination {
+    const ALL: &'static [Destination] = &[Destination::Off, Destination::Pitch, Destination::Cutoff, Destination::PulseWidth, Destination::Volume];
+    const NAMES: &'static [&'static str] = &["Off", "Pitch", "Cutoff", "PWM", "Volume"];
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum MultiSegmentState {
+    // Released carries on through the points after the loop instead of going round again
+    Idle, Running, Released
+}
+
+// up to MAX_POINTS breakpoints played in order from wherever the output was when the note started
+// while the note is held, reaching loop_end jumps back to loop_start's level and heads for the point after it
+#[derive(Clone)]
+pub struct MultiSegmentEnvelope {
+    points: [Point; MAX_POINTS],
+    num_points: usize,
+    // no loop unless loop_start is before loop_end
+    loop_start: usize,
+    loop_end: usize,
+    state: MultiSegmentState,
+    // the point being headed for, seconds since leaving the last one and the output when we left it
+    segment: usize,
+    segment_time: f32,
+    segment_start: f32,
+    context: Context,
+    output: f32
+}
+
+impl Default for MultiSegmentEnvelope {
+    fn default() -> Self {
+        MultiSegmentEnvelope {
+            points: [Point { level: 0.0, time: 0.1, curve: 0.0 }; MAX_POINTS],
+            num_points: MAX_POINTS,
+            loop_start: 0,
+            loop_end: 0,
+            state: MultiSegmentState::Idle,
+            segment: 0,
+            segment_time: 0.0,
+            segment_start: 0.0,
+            context: Context::default(),
+            output: 0.0
+        }
+    }
+}
+
+impl MultiSegmentEnvelope {
+    pub fn start_note(&mut self) {
+        self.state = MultiSegmentState::Running;
+        self.segment = 0;
+        self.segment_time = 0.0;
+        self.segment_start = self.output;
+    }
+
+    pub fn end_note(&mut self) {
+        if self.state == MultiSegmentState::Running {
+            self.state = MultiSegmentState::Released;
+        }
+    }
+
+    pub fn get_output(&self) -> f32 {
+        self.output
+    }
+
+    pub fn set_point(&mut self, index: usize, point: Point) {
+        self.points[index] = point;
+    }
+
+    // num_points is 1 - MAX_POINTS, the loop points are indexes into the points
+    pub fn set_shape(&mut self, num_points: usize, loop_start: usize, loop_end: usize) {
+        self.num_points = num_points.clamp(1, MAX_POINTS);
+        self.loop_start = loop_start;
+        self.loop_end = loop_end.min(self.num_points - 1);
+    }
+
+    pub fn set_context(&mut self, context: Context) {
+        self.context = context;
+    }
+
+    // a loop shorter than a sample would go round forever without moving - taking a tiny time off the time into
+    // the segment can leave it where it was
+    fn loops(&self) -> bool {
+        self.loop_start < self.loop_end
+            && self.points[self.loop_start + 1..=self.loop_end].iter().map(|p| p.time).sum::<f32>() >= self.context.sample_period()
+    }
+
+    pub fn process(&mut self) {
+        if self.state == MultiSegmentState::Idle {
+            return;
+        }
+        self.segment_time += self.context.sample_period();
+        loop {
+            // past the last point, hold its level
+            if self.segment >= self.num_points {
+                self.state = MultiSegmentState::Idle;
+                return;
+            }
+            let point = self.points[self.segment];
+            if self.segment_time < point.time {
+                self.output = self.segment_start + ((point.level - self.segment_start) * adsr::shape(self.segment_time / point.time, point.curve));
+                return;
+            }
+            self.output = point.level;
+            self.segment_time -= point.time;
+            if self.state == MultiSegmentState::Running && self.segment == self.loop_end && self.loops() {
+                self.segment = self.loop_start;
+                self.output = self.points[self.loop_start].level;
+            }
+            self.segment += 1;
+            self.segment_start = self.output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 1000.0;
+
+    fn envelope(points: &[(f32, f32)], loop_start: usize, loop_end: usize) -> MultiSegmentEnvelope {
+        let mut envelope = MultiSegmentEnvelope::default();
+        envelope.set_context(Context::new(SAMPLE_RATE));
+        for (i, (level, time)) in points.iter().enumerate() {
+            envelope.set_point(i, Point { level: *level, time: *time, curve: 0.0 });
+        }
+        envelope.set_shape(points.len(), loop_start, loop_end);
+        envelope
+    }
+
+    fn run(envelope: &mut MultiSegmentEnvelope, seconds: f32) -> f32 {
+        for _ in 0..(seconds * SAMPLE_RATE).round() as usize {
+            envelope.process();
+        }
+        envelope.get_output()
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-3, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn plays_the_points_in_order_and_holds_the_last() {
+        let mut envelope = envelope(&[(1.0, 0.1), (0.5, 0.2), (0.25, 0.1)], 0, 0);
+        envelope.start_note();
+        assert_near(run(&mut envelope, 0.05), 0.5);
+        assert_near(run(&mut envelope, 0.15), 0.75);
+        assert_near(run(&mut envelope, 0.15), 0.375);
+        assert_near(run(&mut envelope, 1.0), 0.25);
+        assert_eq!(envelope.state, MultiSegmentState::Idle);
+    }
+
+    #[test]
+    fn loops_while_held_then_plays_the_rest() {
+        // a saw from point 1 down to point 2 that repeats every 0.1 s
+        let mut envelope = envelope(&[(1.0, 0.0), (0.0, 0.1), (1.0, 0.0), (0.0, 0.2)], 0, 1);
+        envelope.start_note();
+        assert_near(run(&mut envelope, 0.25), 0.5);
+        assert_near(run(&mut envelope, 1.0), 0.5);
+        envelope.end_note();
+        // finishes the ramp it's on, then jumps to 1 for the last ramp down
+        assert_near(run(&mut envelope, 0.04), 0.1);
+        assert_near(run(&mut envelope, 0.11), 0.5);
+        assert_near(run(&mut envelope, 0.3), 0.0);
+        assert_eq!(envelope.state, MultiSegmentState::Idle);
+    }
+
+    #[test]
+    fn a_loop_with_no_length_is_ignored() {
+        let mut envelope = envelope(&[(1.0, 0.0), (0.0, 0.0), (0.5, 0.1)], 0, 1);
+        envelope.start_note();
+        assert_near(run(&mut envelope, 0.2), 0.5);
+    }
+
+    #[test]
+    fn a_loop_shorter_than_a_sample_is_ignored() {
+        // 10 * v^2 seconds for an unsynced time of 1e-7
+        let mut envelope = envelope(&[(1.0, 0.0), (0.0, 1e-13), (1.0, 1e-13), (0.5, 0.1)], 0, 2);
+        envelope.start_note();
+        assert_near(run(&mut envelope, 0.2), 0.5);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::random;
+
+use rs_common::parameter::Choice;
+
+#[derive(Clone)]
+pub struct Noise {
+    random: random::Random,
+    noise_type: NoiseType,
+    // paul kellet's pink noise filter state
+    pink: [f32; 7],
+    brown: f32
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NoiseType {
+    White, Pink, Brown
+}
+
+impl Choice for NoiseType {
+    const ALL: &'static [NoiseType] = &[NoiseType::White, NoiseType::Pink, NoiseType::Brown];
+    const NAMES: &'static [&'static str] = &["White", "Pink", "Brown"];
+}
+
+impl Noise {
+    pub fn new(seed: u32) -> Self {
+        Noise {
+            random: random::Random::new(seed),
+            noise_type: NoiseType::White,
+            pink: [0.0; 7],
+            brown: 0.0
+        }
+    }
+
+    pub fn set_params(&mut self, noise_type: NoiseType) {
+        self.noise_type = noise_type;
+    }
+
+    // must call every sample, keeps all the filters running so switching type doesn't click
+    pub fn process(&mut self) -> f32 {
+        let white = self.random.next_bipolar();
+
+        // http://www.firstpr.com.au/dsp/pink-noise/ - "refined" method, roughly -3dB/oct above 10Hz
+        self.pink[0] = 0.99886 * self.pink[0] + white * 0.0555179;
+        self.pink[1] = 0.99332 * self.pink[1] + white * 0.0750759;
+        self.pink[2] = 0.96900 * self.pink[2] + white * 0.153852;
+        self.pink[3] = 0.86650 * self.pink[3] + white * 0.3104856;
+        self.pink[4] = 0.55000 * self.pink[4] + white * 0.5329522;
+        self.pink[5] = -0.7616 * self.pink[5] - white * 0.0168980;
+        let pink = (self.pink[0] + self.pink[1] + self.pink[2] + self.pink[3] + self.pink[4] + self.pink[5] + self.pink[6] + white * 0.5362) * 0.11;
+        self.pink[6] = white * 0.115926;
+
+        // leaky integrator so it doesn't wander off, gain brings it back up to roughly the same level as white
+        self.brown = (self.brown + (0.02 * white)) / 1.02;
+
+        match self.noise_type {
+            NoiseType::White => white,
+            NoiseType::Pink => pink,
+            NoiseType::Brown => self.brown * 3.5
+        }
+    }
+}
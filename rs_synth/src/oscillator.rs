@@ -1,244 +1,378 @@
-use crate::adsr;
+use crate::context::Context;
+use crate::random;
+
+use rs_common::parameter::Choice;
 
 const TWO_PI: f32 = std::f32::consts::PI * 2.0;
 const A4_PITCH: u8 = 69;
-const A4_FREQ: f32 = 440.0;
+pub const A4_FREQ: f32 = 440.0;
+// a pulse any thinner than this is basically DC
+const MIN_PULSEWIDTH: f32 = 0.02;
+// analog drift picks a new random target this often and glides towards it
+const DRIFT_INTERVAL: f32 = 0.25;
+const DRIFT_GLIDE_TIME: f32 = 0.5;
+// once drift is turned off and it has glided back this close (in cents) it snaps to exactly in tune
+const DRIFT_SETTLED: f32 = 0.001;
+
+// pitch is a note number in semitones, fractional for microtuning, bends, glides and modulation
+// and it can go past either end of the MIDI range
+pub fn mtof(pitch: f32, a4_freq: f32) -> f32 {    
+    ((pitch - f32::from(A4_PITCH)) / 12.0).exp2() * a4_freq
+}
 
-fn mtof(note: u8) -> f32 {    
-    (f32::from(note - A4_PITCH) / 12.0).exp2() * A4_FREQ
+// polynomial band-limited step, t is the phase (0 - 1) and dt the phase increment per sample
+// subtract from a naive waveform at each discontinuity to smooth out the aliasing
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    }
+    else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    }
+    else {
+        0.0
+    }
 }
 
 #[derive(Clone)]
 pub struct Oscillator {
-    frequency: f32,
-    previous_frequency: f32,
+    // the tuned pitch of the note being played, and where a glide towards it started
+    pitch: f32,
+    previous_pitch: f32,
+    // where the glide has got to
+    glide_pitch: f32,
+    current_frequency: f32,
     port_time: f32,
     monophonic: bool,
     sample_counter: u32,
-    velocity: f32,
     note: u8,
     phase: f32,
     output: f32,
     pulsewidth: f32,
-    sample_rate: f32,
-    octave_mod: f32,
-    semitone_mod: i8,
-    fine_mod: f32,
+    context: Context,
+    octave: i8,
+    semitone: i8,
+    fine: f32,
+    transpose: i8,
+    master_tune: f32,
     osc_type: OscillatorType,    
-    pub envelope: adsr::ADSR
+    phase_mode: PhaseMode,
+    start_phase: f32,
+    random: random::Random,
+    drift_amount: f32,
+    drift: f32,
+    drift_target: f32,
+    drift_counter: u32
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct LFO {
     frequency: f32,
     phase: f32,
     output: f32,
     depth: f32,     
-    sample_rate: f32
+    context: Context
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum OscillatorType {
     Saw, Pulse, Sin, Triangle
 }
 
-impl Default for Oscillator {
-    fn default() -> Self {
+impl Choice for OscillatorType {
+    const ALL: &'static [OscillatorType] = &[OscillatorType::Saw, OscillatorType::Pulse, OscillatorType::Triangle, OscillatorType::Sin];
+    const NAMES: &'static [&'static str] = &["Saw", "Pulse", "Tri", "Sine"];
+}
+
+// what happens to the phase when a note starts
+#[derive(Clone, Copy, PartialEq)]
+pub enum PhaseMode {
+    // carry on from wherever the oscillator was
+    Free,
+    // jump to the start phase
+    Reset,
+    // jump somewhere random, like an analog oscillator that was never stopped
+    Random
+}
+
+impl Choice for PhaseMode {
+    const ALL: &'static [PhaseMode] = &[PhaseMode::Free, PhaseMode::Reset, PhaseMode::Random];
+    const NAMES: &'static [&'static str] = &["Free", "Reset", "Random"];
+}
+
+// follows the frequency of another oscillator, one or two octaves down
+#[derive(Clone)]
+pub struct SubOscillator {
+    phase: f32,
+    context: Context,
+    octave_divisor: f32,
+    sub_type: SubOscillatorType
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SubOscillatorType {
+    Square, Sine
+}
+
+impl Choice for SubOscillatorType {
+    const ALL: &'static [SubOscillatorType] = &[SubOscillatorType::Square, SubOscillatorType::Sine];
+    const NAMES: &'static [&'static str] = &["Square", "Sine"];
+}
+
+impl Oscillator {
+    pub fn new(seed: u32) -> Self {
         Oscillator {
-            frequency: 261.63,
-            previous_frequency: 261.63,
+            pitch: 60.0,
+            previous_pitch: 60.0,
+            glide_pitch: 60.0,
+            current_frequency: 261.63,
             port_time: 0.0,
             monophonic: false,
             sample_counter: 0,
-            velocity: 0.0,
             note: 60,
             phase: 0.0,
             output: 0.0,
             pulsewidth: 0.5,
-            sample_rate: 44100.0,
-            octave_mod: 1.0,
-            semitone_mod: 0,
-            fine_mod: 0.0,
+            context: Context::default(),
+            octave: 0,
+            semitone: 0,
+            fine: 0.0,
+            transpose: 0,
+            master_tune: A4_FREQ,
             osc_type: OscillatorType::Saw,
-            envelope: adsr::ADSR::default()
+            phase_mode: PhaseMode::Free,
+            start_phase: 0.0,
+            random: random::Random::new(seed),
+            drift_amount: 0.0,
+            drift: 0.0,
+            drift_target: 0.0,
+            drift_counter: 0
         }
     }
-}
 
-impl Oscillator {
-    pub fn note_on(&mut self, note: u8, vel: u8, mono: bool) {
-        self.velocity = vel as f32 / 127.0;
+    // pitch is what the tuning says this note plays
+    pub fn note_on(&mut self, note: u8, pitch: f32, mono: bool) {
+        self.change_note(note, pitch, mono);
+        match self.phase_mode {
+            PhaseMode::Free => (),
+            PhaseMode::Reset => self.set_phase(self.start_phase),
+            PhaseMode::Random => {
+                let phase = self.random.next_unipolar();
+                self.set_phase(phase);
+            }
+        }
+    }
+
+    // a new note without touching the phase, for legato
+    pub fn change_note(&mut self, note: u8, pitch: f32, mono: bool) {
         self.monophonic = mono;
         if mono {
-            self.previous_frequency = self.frequency;
-            self.frequency = mtof(note);
+            // glide from wherever the last glide got to
+            self.previous_pitch = self.glide_pitch;
+            self.pitch = pitch;
             self.sample_counter = 0;
         }
         else {
-            self.frequency = mtof(note);
-            self.previous_frequency = self.frequency;            
+            self.pitch = pitch;
+            self.previous_pitch = self.pitch;            
         }
         self.note = note;
-        self.envelope.start_note();
     }
 
-    pub fn note_off(&mut self) {
-        self.envelope.end_note();
+    // the tuning changed under a held note, a glide in progress carries on towards the new pitch
+    pub fn retune(&mut self, pitch: f32) {
+        self.pitch = pitch;
+        if !self.monophonic {
+            self.previous_pitch = self.pitch;
+        }
+    }
+
+    // start_phase is 0 - 1 through the waveform's cycle
+    pub fn set_phase_params(&mut self, phase_mode: PhaseMode, start_phase: f32) {
+        self.phase_mode = phase_mode;
+        self.start_phase = start_phase;
+    }
+
+    // max random pitch wander in cents, 0 turns it off
+    pub fn set_drift(&mut self, cents: f32) {
+        self.drift_amount = cents;
+    }
+
+    pub fn retriggers_phase(&self) -> bool {
+        self.phase_mode != PhaseMode::Free
+    }
+
+    // position is 0 - 1 through the cycle, 0 lands on the rising zero crossing like the sine - the saw's phase
+    // runs -1 - 1, and the triangle's phase 0 is its trough so it starts a quarter cycle on
+    fn set_phase(&mut self, position: f32) {
+        self.phase = match self.osc_type {
+            OscillatorType::Saw => if position < 0.5 { position * 2.0 } else { (position * 2.0) - 2.0 },
+            OscillatorType::Triangle => (position + 0.25).rem_euclid(1.0),
+            _ => position
+        };
+    }
+
+    // slow random walk - a new target every DRIFT_INTERVAL, smoothed so it never jumps
+    // with the amount at 0 it glides back to in tune instead of staying wherever it got to
+    fn update_drift(&mut self) {
+        if self.drift_amount > 0.0 {
+            if self.drift_counter == 0 {
+                self.drift_target = self.random.next_bipolar() * self.drift_amount;
+                self.drift_counter = (DRIFT_INTERVAL / self.context.sample_period()) as u32;
+            }
+            self.drift_counter -= 1;
+        }
+        else {
+            // a fresh target as soon as it's turned back on
+            self.drift_target = 0.0;
+            self.drift_counter = 0;
+            if self.drift.abs() < DRIFT_SETTLED {
+                self.drift = 0.0;
+                return;
+            }
+        }
+        self.drift += (self.drift_target - self.drift) * (self.context.sample_period() / DRIFT_GLIDE_TIME);
     }
 
     pub fn get_current_note(&self) -> u8 {
         self.note
     }
 
-    pub fn set_params(&mut self, osc_type: OscillatorType, pw: f32, octave: f32, port_time: f32, semitone: i8, fine: f32) {
+    // the waveform from the last call to process
+    pub fn get_output(&self) -> f32 {
+        self.output
+    }
+
+    // the frequency used in the last call to process, after portamento, pitch mod and tuning
+    pub fn get_frequency(&self) -> f32 {
+        self.current_frequency
+    }
+
+    pub fn set_params(&mut self, osc_type: OscillatorType, pw: f32, port_time: f32) {
         self.osc_type = osc_type;
         self.pulsewidth = pw;
         self.port_time = port_time;
-        self.semitone_mod = semitone;
-        self.fine_mod = fine;
-        // octave will be 0 - 1 float, need to translate that to -2 to 2 multiplier...        
-        self.octave_mod = match octave {
-            o if o < 0.2 => 0.25,
-            o if o < 0.4 => 0.5,
-            o if o < 0.6 => 1.0,
-            o if o < 0.8 => 2.0,
-            _ => 3.0
-        }        
     }
 
-    pub fn set_sample_rate(&mut self, sr: f32) {
-        self.sample_rate = sr;
-        self.envelope.set_sample_rate(self.sample_rate);        
+    // octave, semitone and fine (cents) are per oscillator, transpose (semitones) and master_tune (A4 in Hz) are global
+    pub fn set_pitch(&mut self, octave: i8, semitone: i8, fine: f32, transpose: i8, master_tune: f32) {
+        self.octave = octave;
+        self.semitone = semitone;
+        self.fine = fine;
+        self.transpose = transpose;
+        self.master_tune = master_tune;
     }
 
-    // must call every sample
-    pub fn process(&mut self) -> f32 {
-        self.envelope.process();
-        let mut freq;
-        if self.monophonic {
-            let elapsed_port_time = self.sample_counter as f32 / self.sample_rate;
-            if elapsed_port_time < self.port_time {      
-                self.sample_counter += 1;
-                freq = self.previous_frequency + 
-                    ((self.frequency - self.previous_frequency) * (elapsed_port_time / self.port_time));
-            }
-            else {
-                freq = self.frequency;
-            }
-        }  
-        else {
-            freq = self.frequency;
-        }        
-        freq *= 2.0f32.powf(((self.semitone_mod as f32 * 100.0) + self.fine_mod) / 1200.0);
-        freq *= self.octave_mod;        
-        match self.osc_type {
-            OscillatorType::Saw => {
-                self.output = self.phase;
-                if self.phase >= 1.0 {
-                    self.phase -= 2.0;
-                }
-                self.phase += (1.0 / (self.sample_rate / freq)) * 2.0;                                
-                self.output * self.envelope.get_output() * self.velocity
-            }
-            OscillatorType::Pulse => {
-                if self.phase >= 1.0 {
-                    self.phase -= 1.0;
-                }
-                self.phase += 1.0 / (self.sample_rate / freq);
-                if self.phase < self.pulsewidth { 
-                    1.0 * self.envelope.get_output() * self.velocity
-                } else { 
-                    -1.0 * self.envelope.get_output() * self.velocity
-                }
-            }
-            OscillatorType::Sin => {
-                self.output = (self.phase * TWO_PI).sin();
-                if self.phase >= 1.0 {
-                    self.phase -= 1.0;
-                }
-                self.phase += 1.0 / (self.sample_rate / freq);
-                self.output * self.envelope.get_output() * self.velocity
-            }
-            OscillatorType::Triangle => {
-                if self.phase >= 1.0 {
-                    self.phase -= 1.0;
-                }
-                self.phase += 1.0 / (self.sample_rate / freq);
-                if self.phase <= 0.5 {
-                    self.output = (self.phase - 0.25) * 4.0;
-                }
-                else {
-                    self.output = ((1.0 - self.phase) - 0.25) * 4.0;
-                }
-                self.output * self.envelope.get_output() * self.velocity
-            } 
-        }
+    pub fn set_context(&mut self, context: Context) {
+        self.context = context;
     }
 
-    pub fn process_with_pitch_mod(&mut self, pitch_mod: f32) -> f32 {
-        self.envelope.process();
-        let mut freq;        
+    // must call every sample, pitch_mod is in semitones (LFO, pitch bend etc. summed by the caller)
+    // and pulsewidth_mod is added to the pulsewidth knob
+    pub fn process_with_mod(&mut self, pitch_mod: f32, pulsewidth_mod: f32) -> f32 {
+        // glides are linear in semitones so they sound even across the keyboard
+        self.glide_pitch = self.pitch;
         if self.monophonic {
-            let elapsed_port_time = self.sample_counter as f32 / self.sample_rate;
+            let elapsed_port_time = self.sample_counter as f32 * self.context.sample_period();
             if elapsed_port_time < self.port_time {      
                 self.sample_counter += 1;
-                freq = self.previous_frequency + 
-                    ((self.frequency - self.previous_frequency) * (elapsed_port_time / self.port_time));
-                freq += freq * pitch_mod;
+                self.glide_pitch = self.previous_pitch + ((self.pitch - self.previous_pitch) * (elapsed_port_time / self.port_time));
             }
-            else {
-                freq = self.frequency + (self.frequency * pitch_mod);
-            }  
         }
-        else {
-            freq = self.frequency + (self.frequency * pitch_mod);
-        }    
-        freq *= 2.0f32.powf(((self.semitone_mod as f32 * 100.0) + self.fine_mod) / 1200.0);
-        freq *= self.octave_mod;
+        self.update_drift();
+        // everything that moves the pitch meets here and gets converted to Hz once
+        let offset = (i32::from(self.octave) * 12) + i32::from(self.semitone) + i32::from(self.transpose);
+        let pitch = self.glide_pitch + pitch_mod + offset as f32 + ((self.fine + self.drift) / 100.0);
+        // a few hundred cents of modulation on a high note can take it past nyquist, where the
+        // phase would run away - pin it there instead
+        let freq = mtof(pitch, self.master_tune).min(self.context.nyquist());
+        self.current_frequency = freq;
         match self.osc_type {
             OscillatorType::Saw => {
                 self.output = self.phase;
                 if self.phase >= 1.0 {
                     self.phase -= 2.0;
                 }
-                self.phase += (1.0 / (self.sample_rate / freq)) * 2.0;
-                self.output * self.envelope.get_output() * self.velocity
+                self.phase += self.context.phase_increment(freq) * 2.0;
+                self.output
             }
             OscillatorType::Pulse => {
-                if self.phase >= 1.0 {
-                    self.phase -= 1.0;
-                }
-                self.phase += 1.0 / (self.sample_rate / freq);
-                if self.phase < self.pulsewidth { 
-                    1.0 * self.envelope.get_output() * self.velocity
-                } else { 
-                    -1.0 * self.envelope.get_output() * self.velocity
-                }
+                let dt = self.context.phase_increment(freq);
+                let pulsewidth = (self.pulsewidth + pulsewidth_mod).clamp(MIN_PULSEWIDTH, 1.0 - MIN_PULSEWIDTH);
+                self.phase += dt;
+                // phase can be anywhere if we've just switched from a saw
+                self.phase = self.phase.rem_euclid(1.0);
+                self.output = if self.phase < pulsewidth { 1.0 } else { -1.0 };
+                self.output += poly_blep(self.phase, dt);
+                self.output -= poly_blep((self.phase - pulsewidth).rem_euclid(1.0), dt);
+                self.output
             }
             OscillatorType::Sin => {
                 self.output = (self.phase * TWO_PI).sin();
                 if self.phase >= 1.0 {
                     self.phase -= 1.0;
                 }
-                self.phase += 1.0 / (self.sample_rate / freq);
-                self.output * self.envelope.get_output() * self.velocity
+                self.phase += self.context.phase_increment(freq);
+                self.output
             }
             OscillatorType::Triangle => {
                 if self.phase >= 1.0 {
                     self.phase -= 1.0;
                 }
-                self.phase += 1.0 / (self.sample_rate / freq);
+                self.phase += self.context.phase_increment(freq);
                 if self.phase <= 0.5 {
                     self.output = (self.phase - 0.25) * 4.0;
                 }
                 else {
                     self.output = ((1.0 - self.phase) - 0.25) * 4.0;
                 }
-                self.output * self.envelope.get_output() * self.velocity
+                self.output
             }        
         }
     }
 }
 
+impl Default for SubOscillator {
+    fn default() -> Self {
+        SubOscillator {
+            phase: 0.0,
+            context: Context::default(),
+            octave_divisor: 2.0,
+            sub_type: SubOscillatorType::Square
+        }
+    }
+}
+
+impl SubOscillator {
+    pub fn reset_phase(&mut self) {
+        self.phase = 0.0;
+    }
+
+    // octaves is 1 or 2
+    pub fn set_params(&mut self, sub_type: SubOscillatorType, octaves: u8) {
+        self.sub_type = sub_type;
+        self.octave_divisor = f32::from(1u8 << octaves);
+    }
+
+    pub fn set_context(&mut self, context: Context) {
+        self.context = context;
+    }
+
+    // must call every sample with the frequency of the oscillator being followed, returns the raw waveform
+    pub fn process(&mut self, freq: f32) -> f32 {
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        self.phase += self.context.phase_increment(freq / self.octave_divisor);
+        match self.sub_type {
+            SubOscillatorType::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            SubOscillatorType::Sine => (self.phase * TWO_PI).sin()
+        }
+    }
+}
+
 impl Default for LFO {
     fn default() -> Self {
         LFO {
@@ -246,7 +380,7 @@ impl Default for LFO {
             phase: 0.0,
             output: 0.0,
             depth: 0.0,
-            sample_rate: 44100.0
+            context: Context::default()
         }
     }
 }
@@ -257,12 +391,158 @@ impl LFO {
         self.depth = depth;
     }
 
+    pub fn set_context(&mut self, context: Context) {
+        self.context = context;
+    }
+
     pub fn process(&mut self) -> f32 {
         self.output = (self.phase * TWO_PI).sin();
         if self.phase >= 1.0 {
             self.phase -= 1.0;
         }
-        self.phase += 1.0 / (self.sample_rate / self.frequency);
+        self.phase += self.context.phase_increment(self.frequency);
         self.output * self.depth
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    // renders a second of sine and measures the frequency from the first and last rising zero crossings
+    fn rendered_frequency(oscillator: &mut Oscillator, pitch_mod: f32) -> f32 {
+        let mut crossings = vec![];
+        let mut previous = 0.0;
+        for i in 0..SAMPLE_RATE as usize {
+            oscillator.process_with_mod(pitch_mod, 0.0);
+            let current = oscillator.get_output();
+            if previous < 0.0 && current >= 0.0 {
+                // interpolate where the crossing actually fell between the two samples
+                crossings.push(i as f32 - (current / (current - previous)));
+            }
+            previous = current;
+        }
+        let cycles = (crossings.len() - 1) as f32;
+        cycles * SAMPLE_RATE / (crossings[crossings.len() - 1] - crossings[0])
+    }
+
+    fn sine(note: u8, octave: i8, semitone: i8, fine: f32, transpose: i8, master_tune: f32) -> Oscillator {
+        let mut oscillator = Oscillator::new(1);
+        oscillator.set_context(Context::new(SAMPLE_RATE));
+        oscillator.set_params(OscillatorType::Sin, 0.5, 0.0);
+        oscillator.set_pitch(octave, semitone, fine, transpose, master_tune);
+        oscillator.note_on(note, f32::from(note), false);
+        oscillator
+    }
+
+    fn assert_frequency(oscillator: &mut Oscillator, expected: f32) {
+        assert_modulated_frequency(oscillator, 0.0, expected);
+    }
+
+    fn assert_modulated_frequency(oscillator: &mut Oscillator, pitch_mod: f32, expected: f32) {
+        let rendered = rendered_frequency(oscillator, pitch_mod);
+        // a hundredth of a semitone
+        let cents = 1200.0 * (rendered / expected).log2();
+        assert!(cents.abs() < 1.0, "expected {} Hz, rendered {} Hz", expected, rendered);
+    }
+
+    #[test]
+    fn octaves_are_powers_of_two() {
+        for (octave, expected) in [(-2, 110.0), (-1, 220.0), (0, 440.0), (1, 880.0), (2, 1760.0)].iter() {
+            assert_frequency(&mut sine(69, *octave, 0, 0.0, 0, A4_FREQ), *expected);
+        }
+    }
+
+    #[test]
+    fn semitones_cents_and_transpose() {
+        // E5, a fifth above A4
+        assert_frequency(&mut sine(69, 0, 7, 0.0, 0, A4_FREQ), 659.255);
+        // C4 transposed up to D4
+        assert_frequency(&mut sine(60, 0, 0, 0.0, 2, A4_FREQ), 293.665);
+        // everything stacks - A3 + 1 octave + 12 semitones - 1 octave of transpose + 50 cents
+        assert_frequency(&mut sine(57, 1, 12, 50.0, -12, A4_FREQ), 440.0 * (0.5f32 / 12.0).exp2());
+    }
+
+    #[test]
+    fn master_tune_moves_every_note() {
+        assert_frequency(&mut sine(69, 0, 0, 0.0, 0, 432.0), 432.0);
+        assert_frequency(&mut sine(45, 0, 0, 0.0, 0, 415.0), 103.75);
+        // changing it while a note is held retunes the note
+        let mut oscillator = sine(69, 0, 0, 0.0, 0, A4_FREQ);
+        oscillator.set_pitch(0, 0, 0.0, 0, 442.0);
+        assert_frequency(&mut oscillator, 442.0);
+    }
+
+    #[test]
+    fn every_midi_note_is_in_tune() {
+        for note in 0..128u8 {
+            let expected = 440.0 * ((f32::from(note) - 69.0) / 12.0).exp2();
+            assert!((mtof(f32::from(note), A4_FREQ) / expected - 1.0).abs() < 1e-5, "note {}", note);
+            assert_frequency(&mut sine(note, 0, 0, 0.0, 0, A4_FREQ), expected);
+        }
+    }
+
+    #[test]
+    fn fractional_pitch_and_modulation_add_up() {
+        // a tuning puts middle C a quarter tone sharp, the pitch mod bends it up another 1.5 semitones
+        let mut oscillator = sine(60, 0, 0, 0.0, 0, A4_FREQ);
+        oscillator.note_on(60, 60.5, false);
+        assert_modulated_frequency(&mut oscillator, 1.5, mtof(62.0, A4_FREQ));
+        // bent down past the bottom of the MIDI range
+        assert_modulated_frequency(&mut sine(0, 0, 0, 0.0, 0, A4_FREQ), -12.0, mtof(-12.0, A4_FREQ));
+    }
+
+    #[test]
+    fn glide_is_linear_in_semitones() {
+        let mut oscillator = sine(48, 0, 0, 0.0, 0, A4_FREQ);
+        oscillator.note_on(48, 48.0, true);
+        oscillator.process_with_mod(0.0, 0.0);
+        oscillator.set_params(OscillatorType::Sin, 0.5, 1.0);
+        oscillator.note_on(72, 72.0, true);
+        for _ in 0..(SAMPLE_RATE / 2.0) as usize {
+            oscillator.process_with_mod(0.0, 0.0);
+        }
+        // half way from C3 to C5 is C4
+        let cents = 1200.0 * (oscillator.get_frequency() / mtof(60.0, A4_FREQ)).log2();
+        assert!(cents.abs() < 1.0, "{} cents out half way through the glide", cents);
+    }
+
+    #[test]
+    fn start_phase_zero_is_the_zero_crossing() {
+        // the pulse has no zero crossing, it starts at the beginning of its high half
+        for (osc_type, quarter) in [(OscillatorType::Saw, 0.5), (OscillatorType::Sin, 1.0), (OscillatorType::Triangle, 1.0)].iter() {
+            for (start, expected) in [(0.0, 0.0), (0.25, *quarter)].iter() {
+                let mut oscillator = sine(60, 0, 0, 0.0, 0, A4_FREQ);
+                oscillator.set_params(*osc_type, 0.5, 0.0);
+                oscillator.set_phase_params(PhaseMode::Reset, *start);
+                oscillator.note_on(60, 60.0, false);
+                let output = oscillator.process_with_mod(0.0, 0.0);
+                assert!((output - expected).abs() < 0.05, "starting at {} gave {}", start, output);
+            }
+        }
+    }
+
+    #[test]
+    fn turning_drift_off_brings_the_pitch_back() {
+        let mut oscillator = sine(60, 0, 0, 0.0, 0, A4_FREQ);
+        oscillator.set_drift(20.0);
+        for _ in 0..SAMPLE_RATE as usize {
+            oscillator.process_with_mod(0.0, 0.0);
+        }
+        assert_ne!(oscillator.get_frequency(), mtof(60.0, A4_FREQ));
+        oscillator.set_drift(0.0);
+        for _ in 0..(SAMPLE_RATE * 10.0) as usize {
+            oscillator.process_with_mod(0.0, 0.0);
+        }
+        assert_eq!(oscillator.get_frequency(), mtof(60.0, A4_FREQ));
+    }
+
+    #[test]
+    fn frequency_stops_at_nyquist() {
+        let mut oscillator = sine(127, 2, 24, 100.0, 24, A4_FREQ);
+        oscillator.process_with_mod(24.0, 0.0);
+        assert_eq!(oscillator.get_frequency(), SAMPLE_RATE * 0.5);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{SynthParameters, PARAMETERS};
+
+use rs_common::preset::{self, Preset};
+use rs_common::program::Program;
+use vst::plugin::PluginParameters;
+
+use std::path::Path;
+
+// each program is the default patch with a few parameters changed, by name
+const FACTORY: [(&str, &[(&str, f32)]); 8] = [
+    ("Init", &[]),
+    ("Sub Bass", &[
+        ("Voices", 0.0),
+        ("Retrigger", 1.0),
+        ("Portamento Time", 0.2),
+        ("Osc 1 Type", 1.0),
+        ("Osc 1 Octave", 0.3),
+        ("Osc 2 Volume", 0.0),
+        ("Sub Type", 1.0),
+        ("Sub Volume", 0.05),
+        ("Filter Cutoff", 0.35),
+        ("Envelope Release", 0.15)
+    ]),
+    ("Saw Bass", &[
+        ("Voices", 0.0),
+        ("Osc 1 Octave", 0.3),
+        ("Osc 2 Octave", 0.3),
+        ("Osc 2 Fine", 0.53),
+        ("Filter Cutoff", 0.25),
+        ("Filter Resonance", 0.25),
+        ("Filter Env Amount", 0.75),
+        ("Filter Decay", 0.35),
+        ("Filter Sustain", 0.2),
+        ("Envelope Release", 0.1),
+        ("Drive", 0.3)
+    ]),
+    ("Acid Bass", &[
+        ("Voices", 0.0),
+        ("Retrigger", 1.0),
+        ("Portamento Time", 0.35),
+        ("Osc 1 Octave", 0.3),
+        ("Osc 2 Volume", 0.0),
+        ("Filter Cutoff", 0.2),
+        ("Filter Resonance", 0.6),
+        ("Filter Env Amount", 0.8),
+        ("Filter Decay", 0.3),
+        ("Filter Sustain", 0.0),
+        ("Filter Velocity", 0.5),
+        ("Saturation", 0.4)
+    ]),
+    ("Square Lead", &[
+        ("Voices", 0.0),
+        ("Portamento Time", 0.3),
+        ("Osc 1 Type", 0.3),
+        ("Osc 1 Pulsewidth", 0.35),
+        ("Osc 2 Type", 0.3),
+        ("Osc 2 Octave", 0.7),
+        ("Osc 2 Fine", 0.52),
+        ("Filter Cutoff", 0.55),
+        ("Filter Env Amount", 0.6),
+        ("Filter Decay", 0.3),
+        ("Filter Sustain", 0.5),
+        ("Pitch LFO Depth", 0.15),
+        ("Pitch LFO Rate", 0.26),
+        ("Envelope Release", 0.2)
+    ]),
+    ("Glide Lead", &[
+        ("Voices", 0.0),
+        ("Retrigger", 1.0),
+        ("Portamento Time", 0.4),
+        ("Osc 2 Fine", 0.55),
+        ("Filter Cutoff", 0.5),
+        ("Filter Resonance", 0.2),
+        ("Drive", 0.4),
+        ("Drive Type", 0.5),
+        ("Envelope Release", 0.25)
+    ]),
+    ("Warm Pad", &[
+        ("Envelope Attack", 0.45),
+        ("Envelope Release", 0.5),
+        ("Osc 2 Fine", 0.54),
+        ("Filter Cutoff", 0.35),
+        ("Filter Env Amount", 0.6),
+        ("Filter Attack", 0.5),
+        ("Filter Sustain", 0.6),
+        ("Filter Release", 0.5),
+        ("Filter LFO Depth", 0.2),
+        ("Filter LFO Rate", 0.01),
+        ("Analog", 0.4)
+    ]),
+    ("PWM Pad", &[
+        ("Envelope Attack", 0.4),
+        ("Envelope Release", 0.45),
+        ("Osc 1 Type", 0.3),
+        ("Osc 2 Type", 0.3),
+        ("Osc 2 Fine", 0.46),
+        ("PWM Depth", 0.6),
+        ("PWM LFO Rate", 0.02),
+        ("Filter Cutoff", 0.45),
+        ("Analog", 0.3)
+    ])
+];
+
+pub fn names(params: &SynthParameters) -> Vec<String> {
+    (0..PARAMETERS).map(|i| params.get_parameter_name(i)).collect()
+}
+
+pub fn factory(defaults: &SynthParameters) -> Vec<Program> {
+    let values = defaults.parameter_values();
+    let names = names(defaults);
+    // every factory program only changes parameters the plugin has, the tests make sure of it
+    FACTORY.iter().filter_map(|(name, changes)| Program::new(name, &values, &names, changes)).collect()
+}
+
+// the factory programs followed by every preset in the user library
+pub fn with_library(defaults: &SynthParameters, dir: &Path) -> Vec<Program> {
+    // each preset is read into a scratch copy of the defaults, with the same tunings so their names read
+    let scratch = SynthParameters { tunings: defaults.tunings.clone(), ..Default::default() };
+    let mut programs = factory(defaults);
+    programs.extend(preset::load_library(dir).iter().map(|p| Program::from_preset(p, &scratch)));
+    programs
+}
+
+// a preset file read into params, anything it doesn't set goes back to the default
+pub fn import(path: &Path, params: &SynthParameters) -> Result<Program, String> {
+    Ok(Program::from_preset(&Preset::load(path)?, params))
+}
+
+// the current program as a user preset
+pub fn current(params: &SynthParameters) -> Preset {
+    Preset::from_parameters(&params.get_preset_name(params.get_preset_num()), params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_factory_program_is_built() {
+        let programs = factory(&SynthParameters::default());
+        let names: Vec<&str> = programs.iter().map(|p| p.name.as_str()).collect();
+        let expected: Vec<&str> = FACTORY.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, expected);
+    }
+}
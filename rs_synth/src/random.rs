@@ -0,0 +1,31 @@
+// xorshift32 - cheap, deterministic and good enough for audio noise, no need for a dependency
+#[derive(Clone)]
+pub struct Random {
+    state: u32
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // xorshift gets stuck on 0 forever
+        Random {
+            state: if seed == 0 { 0x9e37_79b9 } else { seed }
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state
+    }
+
+    // 0 - 1
+    pub fn next_unipolar(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    // -1 - 1
+    pub fn next_bipolar(&mut self) -> f32 {
+        (self.next_unipolar() * 2.0) - 1.0
+    }
+}
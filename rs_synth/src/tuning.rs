@@ -0,0 +1,473 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const NOTES: usize = 128;
+// pitches are stored as 12-TET note numbers against A4 = 440, so 69.0 is A4 and 69.5 is a quarter tone above it
+// the master tune knob then shifts a loaded tuning the same way it shifts 12-TET
+const A4_PITCH: f64 = 69.0;
+const A4_FREQ: f64 = 440.0;
+// 7F 7F 7F in an MTS frequency means leave this note alone
+const MTS_NO_CHANGE: [u8; 3] = [0x7f, 0x7f, 0x7f];
+// room for the name, longer ones are cut short - kept inline so tunings copy on the audio thread without allocating
+const NAME_BYTES: usize = 32;
+
+// maps each MIDI note to the pitch it plays, either 12-TET, from a Scala scale/keyboard mapping or from MTS SysEx
+#[derive(Clone, PartialEq, Debug)]
+pub struct Tuning {
+    name: [u8; NAME_BYTES],
+    name_len: usize,
+    // None for keys the keyboard mapping leaves unmapped, they don't play
+    pitches: [Option<f32>; NOTES]
+}
+
+// a .scl file - the cents of every degree above 1/1, the last one is the period (usually the octave)
+pub struct Scale {
+    cents: Vec<f64>
+}
+
+// a .kbm file - which scale degree each key plays and which key is tuned to what frequency
+pub struct KeyboardMapping {
+    first_note: i32,
+    last_note: i32,
+    // the key that plays degree 0 of the scale
+    middle_note: i32,
+    reference_note: i32,
+    reference_frequency: f64,
+    octave_degree: i32,
+    // one entry per key in the repeating pattern, None for 'x' - empty means key n plays degree n
+    mapping: Vec<Option<i32>>
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        let mut pitches = [None; NOTES];
+        for (note, pitch) in pitches.iter_mut().enumerate() {
+            *pitch = Some(note as f32);
+        }
+        let mut tuning = Tuning {
+            name: [0; NAME_BYTES],
+            name_len: 0,
+            pitches
+        };
+        tuning.set_name("12-TET");
+        tuning
+    }
+}
+
+// the standard mapping scala uses when there's no .kbm - middle C plays 1/1, A4 is 440
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        KeyboardMapping {
+            first_note: 0,
+            last_note: NOTES as i32 - 1,
+            middle_note: 60,
+            reference_note: 69,
+            reference_frequency: A4_FREQ,
+            octave_degree: 0,
+            mapping: vec![]
+        }
+    }
+}
+
+// the non comment lines of a scala file
+fn scala_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().filter(|line| !line.starts_with('!'))
+}
+
+// only the first word of a line counts, anything after it is a comment
+fn first_word<'a>(line: Option<&'a str>, what: &str) -> Result<&'a str, String> {
+    line.and_then(|l| l.split_whitespace().next()).ok_or(format!("missing {}", what))
+}
+
+fn parse_number<T: std::str::FromStr>(word: &str, what: &str) -> Result<T, String> {
+    word.parse().map_err(|_| format!("bad {} '{}'", what, word))
+}
+
+impl Scale {
+    pub fn parse(text: &str) -> Result<Scale, String> {
+        let mut lines = scala_lines(text);
+        // description, unused - the file name is shorter and what the user actually picked
+        lines.next().ok_or("empty scale")?;
+        let count: usize = parse_number(first_word(lines.next(), "note count")?, "note count")?;
+        let mut pitch_lines = lines.filter(|line| !line.trim().is_empty());
+        let cents = (0..count)
+            .map(|_| Scale::parse_pitch(first_word(pitch_lines.next(), "pitch")?))
+            .collect::<Result<Vec<f64>, String>>()?;
+        if cents.is_empty() {
+            return Err("scale has no notes".to_string());
+        }
+        Ok(Scale { cents })
+    }
+
+    // anything with a '.' is in cents, otherwise it's a ratio like 3/2 or a whole number like 2
+    fn parse_pitch(word: &str) -> Result<f64, String> {
+        if word.contains('.') {
+            return parse_number(word, "pitch");
+        }
+        let mut parts = word.splitn(2, '/');
+        let numerator: f64 = parse_number(parts.next().unwrap_or(""), "ratio")?;
+        let denominator: f64 = match parts.next() {
+            Some(d) => parse_number(d, "ratio")?,
+            None => 1.0
+        };
+        if numerator <= 0.0 || denominator <= 0.0 {
+            return Err(format!("bad ratio '{}'", word));
+        }
+        Ok((numerator / denominator).log2() * 1200.0)
+    }
+
+    fn len(&self) -> i32 {
+        self.cents.len() as i32
+    }
+
+    // degree 0 is 1/1, degrees past the end of the scale go up a period at a time
+    fn degree_cents(&self, degree: i32) -> f64 {
+        let step = degree.rem_euclid(self.len()) as usize;
+        let base = if step == 0 { 0.0 } else { self.cents[step - 1] };
+        base + (self.cents[self.cents.len() - 1] * f64::from(degree.div_euclid(self.len())))
+    }
+}
+
+impl KeyboardMapping {
+    pub fn parse(text: &str) -> Result<KeyboardMapping, String> {
+        let mut lines = scala_lines(text).filter(|line| !line.trim().is_empty());
+        let size: usize = parse_number(first_word(lines.next(), "map size")?, "map size")?;
+        let first_note = parse_number(first_word(lines.next(), "first note")?, "first note")?;
+        let last_note = parse_number(first_word(lines.next(), "last note")?, "last note")?;
+        let middle_note = parse_number(first_word(lines.next(), "middle note")?, "middle note")?;
+        let reference_note = parse_number(first_word(lines.next(), "reference note")?, "reference note")?;
+        let reference_frequency: f64 = parse_number(first_word(lines.next(), "reference frequency")?, "reference frequency")?;
+        let octave_degree = parse_number(first_word(lines.next(), "octave degree")?, "octave degree")?;
+        if reference_frequency <= 0.0 {
+            return Err("reference frequency must be above 0".to_string());
+        }
+        // a mapping repeats within the keyboard, so anything longer is a broken file - and would take all the memory there is
+        if size > NOTES {
+            return Err(format!("map size {} is more than the {} keys there are", size, NOTES));
+        }
+        for (note, what) in [(first_note, "first note"), (last_note, "last note"), (middle_note, "middle note"), (reference_note, "reference note")].iter() {
+            if !(0..NOTES as i32).contains(note) {
+                return Err(format!("{} {} isn't a MIDI note", what, note));
+            }
+        }
+        // a short mapping is allowed, the keys it leaves out are unmapped
+        let mapping = (0..size)
+            .map(|_| match lines.next().and_then(|l| l.split_whitespace().next()) {
+                None | Some("x") | Some("X") => Ok(None),
+                Some(word) => parse_number(word, "mapping").map(Some)
+            })
+            .collect::<Result<Vec<Option<i32>>, String>>()?;
+        Ok(KeyboardMapping {
+            first_note,
+            last_note,
+            middle_note,
+            reference_note,
+            reference_frequency,
+            octave_degree,
+            mapping
+        })
+    }
+
+    // the scale degree this key plays, None if it's unmapped
+    fn degree(&self, key: i32, scale: &Scale) -> Option<i32> {
+        let offset = key - self.middle_note;
+        if self.mapping.is_empty() {
+            return Some(offset);
+        }
+        let size = self.mapping.len() as i32;
+        let octave_degree = if self.octave_degree == 0 { scale.len() } else { self.octave_degree };
+        // the octave degree comes straight from the file, a huge one leaves the key unmapped rather than overflowing
+        self.mapping[offset.rem_euclid(size) as usize]
+            .and_then(|d| offset.div_euclid(size).checked_mul(octave_degree).and_then(|octaves| d.checked_add(octaves)))
+    }
+}
+
+impl Tuning {
+    pub fn from_scala(name: &str, scale: &Scale, mapping: &KeyboardMapping) -> Result<Tuning, String> {
+        let reference = mapping
+            .degree(mapping.reference_note, scale)
+            .map(|d| scale.degree_cents(d))
+            .ok_or("the reference note is unmapped")?;
+        let mut pitches = [None; NOTES];
+        for (note, pitch) in pitches.iter_mut().enumerate() {
+            let key = note as i32;
+            if key < mapping.first_note || key > mapping.last_note {
+                continue;
+            }
+            *pitch = mapping.degree(key, scale).map(|d| {
+                let frequency = mapping.reference_frequency * ((scale.degree_cents(d) - reference) / 1200.0).exp2();
+                (A4_PITCH + ((frequency / A4_FREQ).log2() * 12.0)) as f32
+            });
+        }
+        let mut tuning = Tuning {
+            name: [0; NAME_BYTES],
+            name_len: 0,
+            pitches
+        };
+        tuning.set_name(name);
+        Ok(tuning)
+    }
+
+    // a .scl and the .kbm of the same name next to it, if there is one
+    pub fn load(scl: &Path) -> Result<Tuning, String> {
+        let read = |path: &Path| fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e));
+        let scale = Scale::parse(&read(scl)?)?;
+        let kbm = scl.with_extension("kbm");
+        let mapping = if kbm.is_file() {
+            KeyboardMapping::parse(&read(&kbm)?)?
+        }
+        else {
+            KeyboardMapping::default()
+        };
+        let name = scl.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        Tuning::from_scala(&name, &scale, &mapping)
+    }
+
+    pub fn name(&self) -> &str {
+        std::str::from_utf8(&self.name[..self.name_len]).unwrap_or_default()
+    }
+
+    // cut short on a character boundary if it doesn't fit
+    fn set_name(&mut self, name: &str) {
+        let mut len = name.len().min(NAME_BYTES);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.name = [0; NAME_BYTES];
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.name_len = len;
+    }
+
+    // the pitch a key plays in 12-TET note numbers, can be fractional
+    pub fn pitch(&self, note: u8) -> Option<f32> {
+        self.pitches.get(usize::from(note)).copied().flatten()
+    }
+
+    // MIDI Tuning Standard - the bulk dump (08 01), single note changes (08 02, 08 07)
+    // and the 1 and 2 byte octave tunings (08 08, 08 09), realtime or not
+    // returns true if anything changed
+    pub fn apply_sysex(&mut self, payload: &[u8]) -> bool {
+        let data = payload.strip_prefix(&[0xf0]).unwrap_or(payload);
+        let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+        if data.len() < 4 || (data[0] != 0x7e && data[0] != 0x7f) || data[2] != 0x08 {
+            return false;
+        }
+        match data[3] {
+            0x01 if data.len() >= 21 + (NOTES * 3) => {
+                // SysEx data bytes are 7 bit, so this is only empty for a broken message
+                self.set_name(std::str::from_utf8(&data[5..21]).unwrap_or_default().trim());
+                for (note, frequency) in data[21..21 + (NOTES * 3)].chunks(3).enumerate() {
+                    self.set_mts_pitch(note as u8, frequency);
+                }
+                true
+            }
+            0x02 if data.len() >= 6 => self.apply_note_changes(&data[6..], data[5]),
+            0x07 if data.len() >= 7 => self.apply_note_changes(&data[7..], data[6]),
+            0x08 if data.len() >= 19 => {
+                let mut cents = [0.0; 12];
+                for (cent, c) in cents.iter_mut().zip(data[7..19].iter()) {
+                    *cent = f32::from(*c) - 64.0;
+                }
+                self.set_octave_tuning(&cents);
+                true
+            }
+            0x09 if data.len() >= 31 => {
+                let mut cents = [0.0; 12];
+                for (cent, c) in cents.iter_mut().zip(data[7..31].chunks(2)) {
+                    *cent = ((f32::from((u16::from(c[0]) << 7) | u16::from(c[1])) - 8192.0) / 8192.0) * 100.0;
+                }
+                self.set_octave_tuning(&cents);
+                true
+            }
+            _ => false
+        }
+    }
+
+    // [key, xx, yy, zz] for each note
+    fn apply_note_changes(&mut self, changes: &[u8], count: u8) -> bool {
+        let mut changed = false;
+        for change in changes.chunks_exact(4).take(usize::from(count)) {
+            changed |= self.set_mts_pitch(change[0], &change[1..]);
+        }
+        changed
+    }
+
+    // xx is the semitone, yy zz a 14 bit fraction of a semitone above it
+    fn set_mts_pitch(&mut self, note: u8, frequency: &[u8]) -> bool {
+        if usize::from(note) >= NOTES || frequency == MTS_NO_CHANGE {
+            return false;
+        }
+        let fraction = f32::from((u16::from(frequency[1]) << 7) | u16::from(frequency[2])) / 16384.0;
+        self.pitches[usize::from(note)] = Some(f32::from(frequency[0]) + fraction);
+        true
+    }
+
+    // the same 12 offsets from 12-TET in every octave, C first
+    fn set_octave_tuning(&mut self, cents: &[f32; 12]) {
+        for (note, pitch) in self.pitches.iter_mut().enumerate() {
+            *pitch = Some(note as f32 + (cents[note % 12] / 100.0));
+        }
+    }
+
+    // name length, name, then every pitch with NaN for unmapped keys
+    pub fn write(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&(self.name_len as u32).to_le_bytes());
+        data.extend_from_slice(self.name().as_bytes());
+        for pitch in self.pitches.iter() {
+            data.extend_from_slice(&pitch.unwrap_or(f32::NAN).to_le_bytes());
+        }
+    }
+
+    pub fn read(data: &[u8]) -> Option<Tuning> {
+        let name_len = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?) as usize;
+        let name = std::str::from_utf8(data.get(4..4 + name_len)?).ok()?;
+        let mut pitches = [None; NOTES];
+        let bytes = data.get(4 + name_len..4 + name_len + (NOTES * 4))?;
+        for (pitch, value) in pitches.iter_mut().zip(bytes.chunks_exact(4)) {
+            let value = f32::from_le_bytes(value.try_into().ok()?);
+            *pitch = if value.is_nan() { None } else { Some(value) };
+        }
+        let mut tuning = Tuning {
+            name: [0; NAME_BYTES],
+            name_len: 0,
+            pitches
+        };
+        tuning.set_name(name);
+        Some(tuning)
+    }
+}
+
+// $XDG_CONFIG_HOME/oscicrate/tuning
+fn library_dir() -> Option<PathBuf> {
+    rs_common::config::dir("oscicrate").map(|d| d.join("tuning"))
+}
+
+// 12-TET followed by every .scl in the tuning folder that loads, sorted by name
+pub fn load_library() -> Vec<Tuning> {
+    let mut tunings = vec![Tuning::default()];
+    let mut files: Vec<PathBuf> = library_dir()
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+        .unwrap_or_default();
+    files.retain(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("scl")));
+    files.sort();
+    tunings.extend(files.iter().filter_map(|path| Tuning::load(path).ok()));
+    tunings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EQUAL: &str = "! 12tet.scl\n!\n12 tone equal temperament\n 12\n!\n 100.0\n 200.\n 300.0\n 400.0\n 500.0\n 600.0\n 700.0\n 800.0\n 900.0\n 1000.0\n 1100.0\n 2/1\n";
+    const JUST: &str = "just major pentatonic\n5\n9/8\n5/4 major third\n3/2\n5/3\n2\n";
+
+    fn assert_pitch(tuning: &Tuning, note: u8, expected: f64) {
+        let pitch = f64::from(tuning.pitch(note).unwrap());
+        assert!((pitch - expected).abs() < 0.001, "note {} plays {}, expected {}", note, pitch, expected);
+    }
+
+    #[test]
+    fn equal_scale_matches_12_tet() {
+        let tuning = Tuning::from_scala("12tet", &Scale::parse(EQUAL).unwrap(), &KeyboardMapping::default()).unwrap();
+        for note in 0..128 {
+            assert_pitch(&tuning, note, f64::from(note));
+        }
+    }
+
+    #[test]
+    fn keyboard_mapping_skips_unmapped_keys() {
+        // the white keys play the scale up from C4 = 261.63 Hz, E-G a period apart, the black keys and F and B are silent
+        let kbm = "! white keys\n12\n0\n127\n60\n60\n261.6255653\n5\n0\nx\n1\nx\n2\nx\nx\n3\nx\n4\nx\nx\n";
+        let tuning = Tuning::from_scala("just", &Scale::parse(JUST).unwrap(), &KeyboardMapping::parse(kbm).unwrap()).unwrap();
+        assert_pitch(&tuning, 60, 60.0);
+        assert_eq!(tuning.pitch(61), None);
+        assert_pitch(&tuning, 62, 60.0 + (1.125f64.log2() * 12.0));
+        assert_pitch(&tuning, 64, 60.0 + (1.25f64.log2() * 12.0));
+        assert_eq!(tuning.pitch(65), None);
+        assert_pitch(&tuning, 72, 72.0);
+        assert_pitch(&tuning, 55, 48.0 + (1.5f64.log2() * 12.0));
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        assert!(Scale::parse("too short\n3\n100.0\n").is_err());
+        assert!(Scale::parse("negative ratio\n1\n-3/2\n").is_err());
+        assert!(KeyboardMapping::parse("12\n0\n127\n60\n").is_err());
+        // a map size that would need gigabytes, and notes off the keyboard
+        assert!(KeyboardMapping::parse("4294967295\n0\n127\n60\n69\n440\n0\n").err().unwrap().starts_with("map size"));
+        assert!(KeyboardMapping::parse("129\n0\n127\n60\n69\n440\n0\n").is_err());
+        assert!(KeyboardMapping::parse("0\n-1\n127\n60\n69\n440\n0\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n128\n60\n69\n440\n0\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n127\n2147483647\n69\n440\n0\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n127\n60\n-2147483648\n440\n0\n").is_err());
+        // keys whose degree would overflow with a huge octave degree are left unmapped
+        let huge = KeyboardMapping::parse("1\n0\n127\n60\n60\n261.6255653\n2147483647\n0\n").unwrap();
+        let tuning = Tuning::from_scala("huge", &Scale::parse(JUST).unwrap(), &huge).unwrap();
+        assert_pitch(&tuning, 60, 60.0);
+        assert_eq!(tuning.pitch(0), None);
+    }
+
+    #[test]
+    fn mts_single_note_change() {
+        let mut tuning = Tuning::default();
+        // A4 a quarter tone sharp, middle C left alone
+        assert!(tuning.apply_sysex(&[0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x02, 69, 69, 0x40, 0x00, 60, 0x7f, 0x7f, 0x7f, 0xf7]));
+        assert_pitch(&tuning, 69, 69.5);
+        assert_pitch(&tuning, 60, 60.0);
+        assert!(!tuning.apply_sysex(&[0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7]));
+    }
+
+    #[test]
+    fn mts_bulk_dump_and_octave_tuning() {
+        // every key a quarter tone flat, apart from middle C which is left alone
+        let mut dump = vec![0xf0, 0x7e, 0x7f, 0x08, 0x01, 0x00];
+        dump.extend_from_slice(b"quarter flat    ");
+        for note in 0..128u8 {
+            if note == 60 {
+                dump.extend_from_slice(&MTS_NO_CHANGE);
+            }
+            else {
+                dump.extend_from_slice(&[note.saturating_sub(1), 0x40, 0x00]);
+            }
+        }
+        dump.extend_from_slice(&[0x00, 0xf7]);
+        let mut tuning = Tuning::default();
+        assert!(tuning.apply_sysex(&dump));
+        assert_eq!(tuning.name(), "quarter flat");
+        assert_pitch(&tuning, 69, 68.5);
+        assert_pitch(&tuning, 60, 60.0);
+
+        // C 50 cents sharp and D 50 cents flat in every octave, the rest in tune
+        let octave = [0xf0, 0x7e, 0x7f, 0x08, 0x08, 0x03, 0x7f, 0x7f, 114, 64, 14, 64, 64, 64, 64, 64, 64, 64, 64, 64, 0xf7];
+        assert!(tuning.apply_sysex(&octave));
+        assert_pitch(&tuning, 60, 60.5);
+        assert_pitch(&tuning, 26, 25.5);
+        assert_pitch(&tuning, 69, 69.0);
+    }
+
+    #[test]
+    fn long_names_are_cut_short_and_tunings_stay_off_the_heap() {
+        // nothing to free means nothing was allocated, so the audio thread can copy and retune them
+        assert!(!std::mem::needs_drop::<Tuning>());
+        let long = "a name far too long to fit in the room there is for it";
+        let tuning = Tuning::from_scala(long, &Scale::parse(JUST).unwrap(), &KeyboardMapping::default()).unwrap();
+        assert_eq!(tuning.name(), &long[..NAME_BYTES]);
+        // 'é' is two bytes and would straddle the end
+        let tuning = Tuning::from_scala(&format!("{}é", &long[..NAME_BYTES - 1]), &Scale::parse(JUST).unwrap(), &KeyboardMapping::default()).unwrap();
+        assert_eq!(tuning.name(), &long[..NAME_BYTES - 1]);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        // every other key unmapped, middle C at 256
+        let kbm = "2\n0\n127\n60\n60\n256\n0\n0\nx\n";
+        let tuning = Tuning::from_scala("256", &Scale::parse(JUST).unwrap(), &KeyboardMapping::parse(kbm).unwrap()).unwrap();
+        assert_eq!(tuning.pitch(61), None);
+        let mut data = vec![];
+        tuning.write(&mut data);
+        assert_eq!(Tuning::read(&data), Some(tuning));
+        assert_eq!(Tuning::read(&data[..10]), None);
+    }
+}
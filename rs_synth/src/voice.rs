@@ -0,0 +1,262 @@
+use crate::adsr;
+use crate::context::Context;
+use crate::drive;
+use crate::filter;
+use crate::mixer;
+use crate::mseg;
+use crate::noise;
+use crate::oscillator;
+use crate::random;
+
+use rs_common::parameter::Choice;
+
+// at full analog amount
+const MAX_DRIFT_CENTS: f32 = 8.0;
+const MAX_CUTOFF_OFFSET_OCTAVES: f32 = 0.25;
+const MAX_ENVELOPE_TIME_VARIATION: f32 = 0.15;
+// at full mseg amount
+const MSEG_PITCH_SEMITONES: f32 = 12.0;
+const MSEG_CUTOFF_OCTAVES: f32 = 4.0;
+const MSEG_PULSEWIDTH: f32 = 0.5;
+
+#[derive(Clone)]
+pub struct Voice {
+    pub oscillator_one: oscillator::Oscillator,
+    pub oscillator_two: oscillator::Oscillator,
+    // the sub oscillator follows osc 1's pitch
+    pub sub_oscillator: oscillator::SubOscillator,
+    pub noise: noise::Noise,
+    // one amp envelope for every source, before the drive and filters
+    pub amp_envelope: adsr::ADSR,
+    pub filter_one: filter::Filter,
+    pub filter_two: filter::Filter,
+    // shared by both filters, each filter has its own amount
+    pub filter_envelope: adsr::ADSR,
+    pub pwm_envelope: adsr::ADSR,
+    pub mseg: mseg::MultiSegmentEnvelope,
+    // pre filter
+    pub drive: drive::Drive,
+    // post filter
+    pub saturation: drive::Drive,
+    pub mixer: mixer::Mixer,
+    random: random::Random,
+    analog: f32,
+    // picked at random on each note from the analog amount
+    cutoff_offset: f32,
+    envelope_time_scale: f32,
+    filter_fm_amount: f32,
+    filter_fm_from_osc_one: bool,
+    filter_routing: FilterRouting,
+    filter_balance: f32,
+    pwm_source: PwmSource,
+    pwm_depth: f32,
+    mseg_destination: mseg::Destination,
+    mseg_amount: f32
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PwmSource {
+    // the shared pwm lfo, bipolar
+    Lfo,
+    // the envelopes are unipolar, so they only ever widen the pulse
+    FilterEnvelope,
+    Envelope
+}
+
+impl Choice for PwmSource {
+    const ALL: &'static [PwmSource] = &[PwmSource::Lfo, PwmSource::FilterEnvelope, PwmSource::Envelope];
+    const NAMES: &'static [&'static str] = &["LFO", "Filter Env", "PWM Env"];
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FilterRouting {
+    // osc mix -> filter 1 -> filter 2, balance fades from filter 1's output to filter 2's
+    Serial,
+    // osc mix into both filters, balance fades between them
+    Parallel,
+    // osc 1 and sub -> filter 1, osc 2 and noise -> filter 2, balance fades between them
+    Split
+}
+
+impl Choice for FilterRouting {
+    const ALL: &'static [FilterRouting] = &[FilterRouting::Serial, FilterRouting::Parallel, FilterRouting::Split];
+    const NAMES: &'static [&'static str] = &["Serial", "Parallel", "Split"];
+}
+
+impl Voice {
+    // each voice needs its own seed, otherwise every voice plays the same noise
+    pub fn new(seed: u32) -> Self {
+        Voice {
+            oscillator_one: oscillator::Oscillator::new(seed.wrapping_mul(4)),
+            oscillator_two: oscillator::Oscillator::new(seed.wrapping_mul(4).wrapping_add(1)),
+            sub_oscillator: oscillator::SubOscillator::default(),
+            noise: noise::Noise::new(seed.wrapping_mul(4).wrapping_add(2)),
+            amp_envelope: adsr::ADSR::default(),
+            filter_one: filter::Filter::default(),
+            filter_two: filter::Filter::default(),
+            filter_envelope: adsr::ADSR::default(),
+            pwm_envelope: adsr::ADSR::default(),
+            mseg: mseg::MultiSegmentEnvelope::default(),
+            drive: drive::Drive::default(),
+            saturation: drive::Drive::default(),
+            mixer: mixer::Mixer::default(),
+            random: random::Random::new(seed.wrapping_mul(4).wrapping_add(3)),
+            analog: 0.0,
+            cutoff_offset: 0.0,
+            envelope_time_scale: 1.0,
+            filter_fm_amount: 0.0,
+            filter_fm_from_osc_one: true,
+            filter_routing: FilterRouting::Serial,
+            filter_balance: 0.0,
+            pwm_source: PwmSource::Lfo,
+            pwm_depth: 0.0,
+            mseg_destination: mseg::Destination::Off,
+            mseg_amount: 0.0
+        }
+    }
+
+    // pitch is the note after tuning, in note numbers
+    // legato moves a playing note to the new pitch without restarting the envelopes or oscillators
+    pub fn note_on(&mut self, note: u8, pitch: f32, vel: u8, mono: bool, legato: bool) {
+        if legato {
+            self.oscillator_one.change_note(note, pitch, mono);
+            self.oscillator_two.change_note(note, pitch, mono);
+            self.filter_one.change_note(pitch);
+            self.filter_two.change_note(pitch);
+            return;
+        }
+        self.cutoff_offset = self.random.next_bipolar() * self.analog * MAX_CUTOFF_OFFSET_OCTAVES;
+        self.envelope_time_scale = 1.0 + (self.random.next_bipolar() * self.analog * MAX_ENVELOPE_TIME_VARIATION);
+        let velocity = f32::from(vel) / 127.0;
+        self.amp_envelope.set_note(pitch, velocity);
+        self.filter_envelope.set_note(pitch, velocity);
+        self.pwm_envelope.set_note(pitch, velocity);
+        self.oscillator_one.note_on(note, pitch, mono);
+        self.oscillator_two.note_on(note, pitch, mono);
+        // keep the sub locked to osc 1 so the attack is the same every time
+        if self.oscillator_one.retriggers_phase() {
+            self.sub_oscillator.reset_phase();
+        }
+        self.mixer.set_velocity(vel);
+        self.filter_one.start_note(pitch, vel);
+        self.filter_two.start_note(pitch, vel);
+        self.amp_envelope.start_note();
+        self.filter_envelope.start_note();
+        self.pwm_envelope.start_note();
+        self.mseg.start_note();
+    }
+
+    pub fn note_off(&mut self) {
+        self.amp_envelope.end_note();
+        self.filter_envelope.end_note();
+        self.pwm_envelope.end_note();
+        self.mseg.end_note();
+    }
+
+    pub fn retune(&mut self, pitch: f32) {
+        self.oscillator_one.retune(pitch);
+        self.oscillator_two.retune(pitch);
+    }
+
+    pub fn get_current_note(&self) -> u8 {
+        self.oscillator_one.get_current_note()
+    }
+
+    // 0 - 1, how far this voice is allowed to wander from the others
+    pub fn set_analog(&mut self, amount: f32) {
+        self.analog = amount;
+        self.oscillator_one.set_drift(amount * MAX_DRIFT_CENTS);
+        self.oscillator_two.set_drift(amount * MAX_DRIFT_CENTS);
+    }
+
+    // multiply envelope times by this so each note's envelopes are slightly different
+    pub fn get_envelope_time_scale(&self) -> f32 {
+        self.envelope_time_scale
+    }
+
+    pub fn set_params(&mut self, filter_fm_amount: f32, filter_fm_from_osc_one: bool, routing: FilterRouting, balance: f32, pwm_source: PwmSource, pwm_depth: f32) {
+        self.filter_fm_amount = filter_fm_amount;
+        self.filter_fm_from_osc_one = filter_fm_from_osc_one;
+        self.filter_routing = routing;
+        self.filter_balance = balance;
+        self.pwm_source = pwm_source;
+        self.pwm_depth = pwm_depth;
+    }
+
+    // amount is -1 - 1
+    pub fn set_mseg_routing(&mut self, destination: mseg::Destination, amount: f32) {
+        self.mseg_destination = destination;
+        self.mseg_amount = amount;
+    }
+
+    pub fn set_context(&mut self, context: Context) {
+        self.oscillator_one.set_context(context);
+        self.oscillator_two.set_context(context);
+        self.sub_oscillator.set_context(context);
+        self.filter_one.set_context(context);
+        self.filter_two.set_context(context);
+        self.amp_envelope.set_context(context);
+        self.filter_envelope.set_context(context);
+        self.pwm_envelope.set_context(context);
+        self.mseg.set_context(context);
+    }
+
+    // must call every sample, pitch_mod is in semitones, filter_mod is in octaves and pwm_lfo is the -1 - 1 pwm lfo
+    pub fn process(&mut self, pitch_mod: f32, filter_mod: f32, pwm_lfo: f32) -> f32 {
+        self.amp_envelope.process();
+        self.filter_envelope.process();
+        self.pwm_envelope.process();
+        self.mseg.process();
+        let envelope = self.filter_envelope.get_output();
+
+        // volume turns the level down where the mseg is low, or where it's high with a negative amount
+        let mseg = self.mseg.get_output() * self.mseg_amount;
+        let (mseg_pitch, mseg_cutoff, mseg_pulsewidth, mseg_gain) = match self.mseg_destination {
+            mseg::Destination::Off => (0.0, 0.0, 0.0, 1.0),
+            mseg::Destination::Pitch => (mseg * MSEG_PITCH_SEMITONES, 0.0, 0.0, 1.0),
+            mseg::Destination::Cutoff => (0.0, mseg * MSEG_CUTOFF_OCTAVES, 0.0, 1.0),
+            mseg::Destination::PulseWidth => (0.0, 0.0, mseg * MSEG_PULSEWIDTH, 1.0),
+            mseg::Destination::Volume => (0.0, 0.0, 0.0, if self.mseg_amount >= 0.0 {
+                1.0 - (self.mseg_amount - mseg)
+            } else {
+                1.0 + mseg
+            })
+        };
+        let pitch_mod = pitch_mod + mseg_pitch;
+
+        let pulsewidth_mod = (match self.pwm_source {
+            PwmSource::Lfo => pwm_lfo,
+            PwmSource::FilterEnvelope => envelope,
+            PwmSource::Envelope => self.pwm_envelope.get_output()
+        } * self.pwm_depth) + mseg_pulsewidth;
+
+        let amplitude = self.amp_envelope.get_output() * mseg_gain;
+        let osc_one = self.oscillator_one.process_with_mod(pitch_mod, pulsewidth_mod) * amplitude * self.mixer.gain(mixer::Source::OscillatorOne);
+        let osc_two = self.oscillator_two.process_with_mod(pitch_mod, pulsewidth_mod) * amplitude * self.mixer.gain(mixer::Source::OscillatorTwo);
+        let sub = self.sub_oscillator.process(self.oscillator_one.get_frequency()) * amplitude * self.mixer.gain(mixer::Source::SubOscillator);
+        let noise = self.noise.process() * amplitude * self.mixer.gain(mixer::Source::Noise);
+
+        let filter_fm = if self.filter_fm_from_osc_one {
+            self.oscillator_one.get_output()
+        } else {
+            self.oscillator_two.get_output()
+        } * self.filter_fm_amount;
+        let cutoff_mod = filter_mod + filter_fm + self.cutoff_offset + mseg_cutoff;
+
+        let (one, two) = match self.filter_routing {
+            FilterRouting::Serial => {
+                let one = self.filter_one.process(self.drive.process(osc_one + osc_two + sub + noise), envelope, cutoff_mod);
+                (one, self.filter_two.process(one, envelope, cutoff_mod))
+            }
+            FilterRouting::Parallel => {
+                let driven = self.drive.process(osc_one + osc_two + sub + noise);
+                (self.filter_one.process(driven, envelope, cutoff_mod), self.filter_two.process(driven, envelope, cutoff_mod))
+            }
+            FilterRouting::Split => (
+                self.filter_one.process(self.drive.process(osc_one + sub), envelope, cutoff_mod),
+                self.filter_two.process(self.drive.process(osc_two + noise), envelope, cutoff_mod)
+            )
+        };
+        self.saturation.process((one * (1.0 - self.filter_balance)) + (two * self.filter_balance))
+    }
+}